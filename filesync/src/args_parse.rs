@@ -1,5 +1,8 @@
 use std::path::PathBuf;
 use clap::{ArgAction, ArgGroup, Parser};
+use crate::structures::DuplicatePolicy;
+use crate::hashing::HashAlgo;
+use crate::sync::{ConflictPolicy, ReflinkMode, SymlinkMode};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -9,8 +12,19 @@ use clap::{ArgAction, ArgGroup, Parser};
     after_help = r#"EXAMPLES:
   filesync -t "$HOME/Downloads"
   filesync -t "$HOME/Downloads" -p firefox_pictures -p chrome
+  filesync -t "$HOME/Downloads" --dry-run
+  filesync -t "$HOME/Downloads" --hash --update
+  filesync -t "$HOME/projects" --exclude node_modules --exclude '*.tmp' --exclude .git
   filesync -d "$HOME/Downloads" "$HOME/Pictures"
+  filesync --check "$HOME/Downloads/filesync_tracking.txt" "$HOME/Downloads"
   filesync -s "$HOME/Downloads" "$HOME/Pictures" --dry-run
+  filesync -s "$HOME/Downloads" "$HOME/Pictures" --delete
+  filesync -s "$HOME/Downloads" "$HOME/Pictures" --trash
+  filesync -s "$HOME/Notes" /mnt/nas/Notes --delete --watch
+  filesync -s "$HOME/Downloads" /mnt/nas/Downloads --jobs 4 --bwlimit 10M
+  filesync --sync-both "$HOME/Notes" /mnt/usb/Notes --conflict newer
+  filesync --find-dupes "$HOME/Pictures"
+  filesync --manifest-merge a.txt b.txt -o merged.txt --on-duplicate keep-newest
 "#
 )]
 #[command(
@@ -18,8 +32,9 @@ use clap::{ArgAction, ArgGroup, Parser};
         ArgGroup::new("command")
             .required(true)
             .multiple(false) // exactly ONE of these must be present
-            .args(["track", "diff", "sync"])
-    )
+            .args(["track", "diff", "check", "sync", "sync_both", "find_dupes", "manifest_merge"])
+    ),
+    group(ArgGroup::new("digests").multiple(true).args(["hash", "verify", "find_dupes"])),
 )]
 pub struct ProgramArgs {
     /// Write a tracking file to PATH (requires a DIR positional argument)
@@ -27,24 +42,246 @@ pub struct ProgramArgs {
     #[arg(short = 't', long = "track", value_name = "DIR")]
     pub track: Option<PathBuf>,
 
-    /// Compare master vs slave directories
+    /// Compare master vs slave directories: list paths only in one of them, and paths whose type, size or mtime (or symlink target) differ.
+    /// Exits nonzero when any differences are found
     #[arg(short = 'd', long = "diff", value_names = ["DIR_MASTER", "DIR_SLAVE"], num_args = 2)]
     pub diff: Option<Vec<PathBuf>>,
 
+    /// Compare a tracking file against DIR as it is now: list paths that vanished or appeared since it was written, and paths that changed.
+    /// Hashes DIR if the tracking file has digests. Exits nonzero when any differences are found
+    #[arg(long, value_names = ["TRACKING_FILE", "DIR"], num_args = 2)]
+    pub check: Option<Vec<PathBuf>>,
+
     /// Sync slave directory to match master directory
     #[arg(short = 's', long = "sync", value_names = ["DIR_MASTER", "DIR_SLAVE"], num_args = 2)]
     pub sync: Option<Vec<PathBuf>>,
 
+    /// Sync two directories both ways: entries changed, added or removed on one side since the last --sync-both are carried over to the other.
+    /// Entries changed on both sides are conflicts (see --conflict). A baseline file (`filesync_baseline.txt`) is kept in both directories
+    #[arg(long, value_names = ["DIR_MASTER", "DIR_SLAVE"], num_args = 2)]
+    pub sync_both: Option<Vec<PathBuf>>,
+
+    /// List groups of files in DIR with the same content (hashed with --hash-algo), the most wasted space first
+    #[arg(long, value_name = "DIR")]
+    pub find_dupes: Option<PathBuf>,
+
+    /// Merge tracking files into a single one (requires --output)
+    #[arg(long = "manifest-merge", value_name = "TRACKING_FILE", num_args = 2.., requires = "output")]
+    pub manifest_merge: Option<Vec<PathBuf>>,
+
 
     //optionals:
 
-    /// Only include paths that start with PREFIX (repeatable). Allowed in any mode.
-    #[arg(short, long, value_name = "PREFIX", action = ArgAction::Append)]
-    pub prefix: Option<Vec<String>>,
+    /// Only list paths in the tracking file, without their metadata (valid with --track).
+    /// Such files can't be used with --check, --manifest-merge or --track --dry-run
+    #[arg(long, requires = "track", conflicts_with = "dry_run")]
+    pub names_only: bool,
+
+    /// Gzip the tracking file, as `filesync_tracking.txt.gz` (valid with --track). Every command that reads tracking files
+    /// recognizes gzipped ones, whatever their name
+    #[arg(long, requires = "track", conflicts_with = "dry_run")]
+    pub compress: bool,
+
+    /// Build on the existing tracking file instead of starting over (valid with --track): entries of directories whose
+    /// mtime is still the recorded one keep their recorded metadata (and digests), everything else is looked at again.
+    /// Files rewritten in place, or whose mode or owner changed, are missed that way until a --full rebuild.
+    /// Expects the tracking file to have been written with the same scan options
+    #[arg(long, requires = "track", conflicts_with = "names_only")]
+    pub update: bool,
+
+    /// Rebuild the tracking file from scratch, even when --update is given too (valid with --track)
+    #[arg(long, requires = "track")]
+    pub full: bool,
+
+    /// How --sync-both settles entries changed on both sides: keep the newer one, always master's or slave's, or skip them
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = ConflictPolicy::Skip, requires = "sync_both")]
+    pub conflict: ConflictPolicy,
+
+    /// Where to write the merged tracking file (valid with --manifest-merge)
+    #[arg(short, long, value_name = "FILE", requires = "manifest_merge")]
+    pub output: Option<PathBuf>,
+
+    /// How --manifest-merge handles a path that's listed in more than one tracking file
+    #[arg(long, value_enum, default_value_t = DuplicatePolicy::Error, requires = "manifest_merge")]
+    pub on_duplicate: DuplicatePolicy,
+
+    /// Only include paths under SUBDIR, relative to the scanned root (repeatable; a path under any of them is included).
+    /// Applies to both trees in --diff and --sync, so --sync --delete never touches slave paths outside of them. Allowed in any mode.
+    #[arg(short = 'p', long, visible_alias = "prefix", value_name = "SUBDIR", action = ArgAction::Append)]
+    pub only: Vec<String>,
+
+    /// Skip directories that contain an entry named MARKER, along with everything inside them (repeatable)
+    #[arg(long, value_name = "MARKER", action = ArgAction::Append)]
+    pub exclude_if_present: Vec<String>,
+
+    /// Skip paths matching GLOB (repeatable), along with everything under matched directories. Allowed in any mode.
+    /// `*`, `?` and `[...]` stay within one path component, `**` spans several; a GLOB without `/` matches names at any depth.
+    /// A `.filesyncignore` file (gitignore syntax) at a scanned root is applied too; its `!` rules can't undo an --exclude
+    #[arg(long, value_name = "GLOB", action = ArgAction::Append)]
+    pub exclude: Vec<String>,
+
+    /// Follow symlinks: record them as what they point to, and list linked directories' content. Allowed in any mode.
+    /// Broken links and links that loop back into their own ancestors are still recorded as symlinks
+    #[arg(long)]
+    pub follow_links: bool,
+
+    /// Only record entries up to N levels below the scanned root (1 = its direct children). Allowed in any mode.
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Skip entries that can't be read due to permissions, instead of failing; they're listed on stderr once the scan is done,
+    /// along with any that vanished during it (which are always skipped)
+    #[arg(long)]
+    pub ignore_permission_errors: bool,
+
+    /// Hash the content of regular files (see --hash-algo), so changes that keep both size and mtime are caught too.
+    /// Also lets --diff report renamed files, and --sync --delete move them instead of copying. Allowed in any mode.
+    #[arg(long)]
+    pub hash: bool,
+
+    /// Digest used by --hash and --verify
+    #[arg(long, value_enum, default_value_t = HashAlgo::Sha256, requires = "digests")]
+    pub hash_algo: HashAlgo,
+
+    /// Treat mtimes less than MILLIS apart as equal (used by --diff and --sync; default: exact).
+    /// Useful with filesystems that store coarse timestamps, like FAT's 2 seconds
+    #[arg(long, value_name = "MILLIS", default_value_t = 0)]
+    pub mtime_tolerance: u64,
+
+    /// Don't compare mtimes in --diff, --check and --sync (entries that only differ in them are left alone).
+    /// With --hash, same-size content changes are still caught; handy after a copy that didn't keep the mtimes
+    #[arg(long)]
+    pub ignore_mtime: bool,
+
+    /// Don't compare permission bits: --sync doesn't fix up the mode of entries that are otherwise up to date
+    #[arg(long)]
+    pub ignore_mode: bool,
+
+    /// Only compare types and sizes (and symlink targets); implies --ignore-mtime and --ignore-mode, and ignores --hash digests
+    #[arg(long)]
+    pub size_only: bool,
+
+    /// Match paths case-insensitively in --diff and --sync, for trees on case-insensitive filesystems (macOS, exFAT, ...).
+    /// Synced entries keep the slave's spelling; two entries that only differ in case are an error
+    #[arg(long)]
+    pub ignore_case: bool,
+
+    /// Match paths by their NFC form in --diff and --sync, so names stored decomposed (as macOS does) match composed ones.
+    /// Synced entries keep the slave's spelling; two entries that only differ in normalization are an error
+    #[arg(long)]
+    pub normalize_unicode: bool,
+
+    /// File of `from_prefix=to_prefix` lines, used to place master paths elsewhere in the slave (valid with --sync)
+    #[arg(long, value_name = "FILE", requires = "sync")]
+    pub rename_map: Option<PathBuf>,
 
-    /// Print actions only (valid with --sync)
+    /// Only fix up mode and mtime of slave entries whose content already matches master; never copy (valid with --sync)
+    #[arg(long, visible_alias = "sync-attrs-only", requires = "sync")]
+    pub attrs_only: bool,
+
+    /// Remove slave entries that don't exist in master (valid with --sync).
+    /// With --hash, a removed file whose content is needed elsewhere is moved there instead of being re-copied
+    #[arg(long, requires = "sync", conflicts_with = "attrs_only")]
+    pub delete: bool,
+
+    /// Also give slave entries master's owner and group (Unix, valid with --sync).
+    /// Needs root; without it, a warning is printed and ownership is left alone
+    #[arg(long, requires = "sync")]
+    pub preserve_owner: bool,
+
+    /// Copy up to N files at once (valid with --sync; default, or 0: one per CPU)
+    #[arg(short, long, value_name = "N", requires = "sync")]
+    pub jobs: Option<usize>,
+
+    /// Copy at most RATE bytes per second, across all --jobs together (valid with --sync).
+    /// RATE takes SI (`k`, `M`, `G`, `T`) or binary (`Ki`, `Mi`, `Gi`, `Ti`) multipliers, and an optional `B`/`/s`: `10M`, `512KiB/s`
+    #[arg(long, value_name = "RATE", requires = "sync", value_parser = parse_rate)]
+    pub bwlimit: Option<u64>,
+
+    /// Re-hash every copied file (see --hash-algo) and compare it to its source; a mismatching copy is redone once,
+    /// then reported as failed (valid with --sync). Sources are hashed as they're copied, unless --hash already did it
+    #[arg(long, requires = "sync")]
+    pub verify: bool,
+
+    /// Make copies as reflinks, which share their source's data until either changes, so same-filesystem copies are instant
+    /// (btrfs, XFS, ...; valid with --sync). `auto` falls back to copying bytes where reflinks can't be made, `always` fails there
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ReflinkMode::Never, requires = "sync")]
+    pub reflink: ReflinkMode,
+
+    /// What becomes of symlinks with absolute targets (valid with --sync): `copy` keeps every target as it is, `relativize` rewrites
+    /// the ones inside master as relative links (into the slave), and `skip-absolute` does that too, but skips the ones leading outside it
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = SymlinkMode::Copy, requires = "sync")]
+    pub symlink_mode: SymlinkMode,
+
+    /// Instead of copying a file whose content is already in the slave, hardlink it there (valid with --sync, needs --hash).
+    /// Linked files share their mode, owner and mtime, so only files that also agree on those are linked
+    #[arg(long, requires_all = ["sync", "hash"])]
+    pub hardlink_dupes: bool,
+
+    /// Before overwriting or deleting (with --delete) a slave entry, move it into DIR, at the same relative path (valid with --sync).
+    /// Backups keep their mode and mtime; a DIR inside the slave is left out of the sync
+    #[arg(long, value_name = "DIR", requires = "sync")]
+    pub backup_dir: Option<PathBuf>,
+
+    /// Like --delete, but move the removed entries into `.filesync-trash/<UTC timestamp>/` at the slave root (valid with --sync).
+    /// That folder is never scanned, so it doesn't show up as a difference on the next run; empty it by hand
+    #[arg(long, requires = "sync", conflicts_with_all = ["attrs_only", "delete"])]
+    pub trash: bool,
+
+    /// Ask `[y/N/a/q]` (yes, no, yes to all, quit) on the terminal before each overwrite or deletion (valid with --sync).
+    /// All questions come first, before anything is changed; combine with --backup-dir to keep what's overwritten
+    #[arg(short, long, requires = "sync", conflicts_with_all = ["attrs_only", "dry_run"])]
+    pub interactive: bool,
+
+    /// Don't list what a sync does, only print its summary (on stderr) (valid with --sync)
+    #[arg(short, long, requires = "sync")]
+    pub quiet: bool,
+
+    /// Print a detailed breakdown of what a sync did, instead of its one-line summary (on stderr; valid with --sync)
+    #[arg(long, requires = "sync")]
+    pub stats: bool,
+
+    /// Show a progress bar of the bytes being copied, on stderr (valid with --sync)
+    #[arg(long, requires = "sync", conflicts_with = "progress_json")]
+    pub progress: bool,
+
+    /// Report sync progress as JSON lines on stderr (`start`, one event per handled entry, `finish`)
     #[arg(long, requires = "sync")]
+    pub progress_json: bool,
+
+    /// After syncing, keep watching master (with inotify) and sync each change to the slave as it happens, until interrupted
+    /// (valid with --sync). Where master can't be watched, both trees are rescanned every 2 seconds instead
+    #[arg(long, requires = "sync", conflicts_with_all = ["attrs_only", "dry_run", "interactive", "progress_json"])]
+    pub watch: bool,
+
+    /// Print actions only, without writing anything (valid with --track, --sync and --sync-both).
+    /// With --track, lists the paths and metadata fields that changed since the existing tracking file.
+    #[arg(long, conflicts_with_all = ["diff", "check"])]
     pub dry_run: bool,
 
 }
 
+
+/// Bytes per second, from e.g. `10M`, `1.5Gi`, `512KiB/s` or `4096`
+pub fn parse_rate(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: f64 = number.parse().map_err(|_| format!("'{s}' doesn't start with a number"))?;
+
+    let unit = unit.trim().to_ascii_lowercase();
+    let unit = unit.strip_suffix("/s").unwrap_or(&unit);
+    let unit = unit.strip_suffix('b').unwrap_or(unit);
+    // binary first, then SI (like table_formatter's number parsing)
+    let multipliers: &[(&str, f64)] = &[
+        ("ki", 1024.0), ("mi", 1024.0_f64.powi(2)), ("gi", 1024.0_f64.powi(3)), ("ti", 1024.0_f64.powi(4)),
+        ("k", 1e3), ("m", 1e6), ("g", 1e9), ("t", 1e12),
+        ("", 1.0),
+    ];
+    let (_, multiplier) = multipliers.iter().find(|(prefix, _)| unit == *prefix)
+        .ok_or_else(|| format!("unknown unit in '{s}' (expected e.g. 10M, 512Ki, 1G)"))?;
+
+    let rate = (value * multiplier).round();
+    if rate < 1.0 { return Err(format!("'{s}' is less than 1 byte per second")); }
+    Ok(rate as u64)
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+
+/// Everything that can go wrong while scanning, tracking, diffing or syncing
+#[derive(Debug)]
+pub enum FilesyncError {
+    /// A filesystem operation failed; `context` says which one, and on what path
+    Io { context: String, source: io::Error },
+    /// A tracking file record couldn't be (de)serialized; `context` says which one
+    Serde { context: String, source: serde_json::Error },
+    /// A path that must be a directory isn't one
+    NotADirectory(PathBuf),
+    /// A path that must be a regular file (when it exists) isn't one
+    NotAFile(PathBuf),
+    /// A tracking file line that isn't `<path json> <record json>`
+    BadTrackingLine { line: String, reason: &'static str },
+    /// A rename map line that isn't `from=to`
+    BadRenameRule(String),
+    /// Two master paths that the rename map sends to the same slave path
+    RenameConflict { first: PathBuf, second: PathBuf, dest: PathBuf },
+    /// A path that's listed in more than one merged manifest (with `DuplicatePolicy::Error`)
+    DuplicatePath(PathBuf),
+    /// Entries whose content digests were made with different algorithms
+    HashAlgoMismatch { path: PathBuf, master: &'static str, slave: &'static str },
+    /// A copied file whose content still didn't match its source after a second try
+    VerifyFailed(PathBuf),
+    /// Copies that failed during a sync (the others were still carried out)
+    CopyFailures(Vec<FilesyncError>),
+    /// Two paths that would match as one (with `--ignore-case` or `--normalize-unicode`, the option they differ under)
+    PathCollision { first: PathBuf, second: PathBuf, option: &'static str },
+    /// An interactive sync was run without a terminal to ask on
+    NotATerminal,
+    /// An interactive sync was quit at a prompt (before anything was changed)
+    Cancelled,
+}
+
+impl fmt::Display for FilesyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilesyncError::Io { context, source } => write!(f, "{context}: {source}"),
+            FilesyncError::Serde { context, source } => write!(f, "{context}: {source}"),
+            FilesyncError::NotADirectory(path) => write!(f, "not a directory: '{}'", path.display()),
+            FilesyncError::NotAFile(path) => write!(f, "exists but is not a file: '{}'", path.display()),
+            FilesyncError::BadTrackingLine { line, reason } => write!(f, "{reason}; line={line:?}"),
+            FilesyncError::BadRenameRule(line) => write!(f, "rename rule must look like 'from=to'; line={line:?}"),
+            FilesyncError::RenameConflict { first, second, dest } =>
+                write!(f, "rename conflict: both '{}' and '{}' map to '{}'", first.display(), second.display(), dest.display()),
+            FilesyncError::DuplicatePath(path) => write!(f, "path '{}' appears in more than one manifest", path.display()),
+            FilesyncError::HashAlgoMismatch { path, master, slave } =>
+                write!(f, "can't compare '{}': hashed with {master} in master but {slave} in slave; use the same --hash-algo for both", path.display()),
+            FilesyncError::VerifyFailed(path) => write!(f, "copy '{}' doesn't match its source, even after copying it again", path.display()),
+            FilesyncError::CopyFailures(errors) => {
+                write!(f, "{} file(s) couldn't be copied:", errors.len())?;
+                errors.iter().try_for_each(|e| write!(f, "\n  {e}"))
+            },
+            FilesyncError::PathCollision { first, second, option } => {
+                let how = if *option == "--normalize-unicode" { "Unicode normalization" } else { "case" };
+                write!(f, "'{}' and '{}' only differ in {how}, so {option} can't tell them apart", first.display(), second.display())
+            },
+            FilesyncError::NotATerminal => write!(f, "--interactive needs a terminal to ask on, but stdin isn't one"),
+            FilesyncError::Cancelled => write!(f, "sync cancelled; nothing was changed"),
+        }
+    }
+}
+
+impl std::error::Error for FilesyncError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FilesyncError::Io { source, .. } => Some(source),
+            FilesyncError::Serde { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T, E = FilesyncError> = std::result::Result<T, E>;
+
+
+/// Attach a description of the failed operation to an `io::Error`
+pub trait IoContext<T> {
+    fn context(self, describe: impl FnOnce() -> String) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn context(self, describe: impl FnOnce() -> String) -> Result<T> {
+        self.map_err(|source| FilesyncError::Io { context: describe(), source })
+    }
+}
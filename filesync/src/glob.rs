@@ -0,0 +1,127 @@
+use std::path::Path;
+
+
+/// A shell-style pattern matched against paths relative to the scanned root.
+/// `*` and `?` stay within one path component, `**` spans any number of them, and `[a-z]`/`[!x]` are character classes.
+/// A pattern without a `/` matches the name of an entry at any depth (`*.tmp`, `node_modules`);
+/// one with a `/` is matched against the whole relative path (a leading `/` is just an anchor, and is dropped).
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: Vec<char>,
+    anchored: bool,
+}
+
+impl Glob {
+
+    pub fn new(pattern: &str) -> Self {
+        let trimmed = pattern.trim_end_matches('/');
+        let anchored = trimmed.contains('/');
+        Glob { pattern: trimmed.trim_start_matches('/').chars().collect(), anchored }
+    }
+
+    /// A pattern that only matches `rel` itself (relative to the scanned root), whatever characters it has
+    pub fn literal(rel: &Path) -> Self {
+        let text = rel.to_string_lossy();
+        let pattern = text.trim_matches('/').chars()
+            .flat_map(|c| if matches!(c, '*' | '?' | '[' | '\\') { vec!['\\', c] } else { vec![c] })
+            .collect();
+        Glob { pattern, anchored: true }
+    }
+
+    /// Whether `rel` (relative to the scanned root; a trailing `/` is ignored) is matched
+    pub fn matches(&self, rel: &Path) -> bool {
+        let text = rel.to_string_lossy();
+        let text = text.trim_end_matches('/');
+        let subject = if self.anchored { text } else { text.rsplit('/').next().unwrap_or(text) };
+        glob_match(&self.pattern, &subject.chars().collect::<Vec<_>>())
+    }
+}
+
+fn glob_match(p: &[char], t: &[char]) -> bool {
+    match p {
+        [] => t.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `a/**/b` also matches `a/b`
+            rest.strip_prefix(&['/']).is_some_and(|after| glob_match(after, t))
+                || (0..=t.len()).any(|i| glob_match(rest, &t[i..]))
+        },
+        ['*', rest @ ..] => (0..=t.len())
+            .take_while(|&i| i == 0 || t[i - 1] != '/')
+            .any(|i| glob_match(rest, &t[i..])),
+        ['?', rest @ ..] => t.first().is_some_and(|&c| c != '/') && glob_match(rest, &t[1..]),
+        ['[', rest @ ..] => match (parse_class(rest), t.first()) {
+            (Some((matches_char, after)), Some(&c)) => c != '/' && matches_char(c) && glob_match(after, &t[1..]),
+            (Some(_), None) => false,
+            (None, _) => t.first() == Some(&'[') && glob_match(rest, &t[1..]),  // unterminated: a literal '['
+        },
+        ['\\', c, rest @ ..] => t.first() == Some(c) && glob_match(rest, &t[1..]),
+        [c, rest @ ..] => t.first() == Some(c) && glob_match(rest, &t[1..]),
+    }
+}
+
+/// Parse the inside of a `[...]` class (after the `[`). Returns a char test and the pattern after the closing `]`.
+fn parse_class(p: &[char]) -> Option<(impl Fn(char) -> bool + '_, &[char])> {
+    let (negated, body) = match p {
+        ['!' | '^', rest @ ..] => (true, rest),
+        _ => (false, p),
+    };
+    // a `]` right at the start is part of the class
+    let end = body.iter().skip(1).position(|&c| c == ']')? + 1;
+    let (items, after) = (&body[..end], &body[end + 1..]);
+
+    let test = move |c: char| {
+        let mut i = 0;
+        let mut hit = false;
+        while i < items.len() {
+            if i + 2 < items.len() && items[i + 1] == '-' {
+                hit |= (items[i]..=items[i + 2]).contains(&c);
+                i += 3;
+            } else {
+                hit |= items[i] == c;
+                i += 1;
+            }
+        }
+        hit != negated
+    };
+    Some((test, after))
+}
+
+
+/// Rules read from an ignore file, one per line, with gitignore semantics:
+/// `#` starts a comment, a trailing `/` only matches directories, and a leading `!` re-includes what an earlier rule excluded.
+/// The last rule that matches a path decides. Like git, a path can't be re-included once its parent directory is excluded.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules(Vec<IgnoreRule>);
+
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnoreRules {
+
+    pub fn parse(content: &str) -> Self {
+        let rules = content.lines()
+            .map(str::trim_end)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| {
+                let (negated, pattern) = match l.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, l.strip_prefix('\\').unwrap_or(l)),  // `\#` and `\!` are literal
+                };
+                IgnoreRule { glob: Glob::new(pattern), negated, dir_only: pattern.ends_with('/') }
+            })
+            .collect();
+
+        IgnoreRules(rules)
+    }
+
+    /// Whether `rel` (relative to the root the rules were read from) is ignored
+    pub fn is_ignored(&self, rel: &Path, is_dir: bool) -> bool {
+        self.0.iter().rev()
+            .find(|r| (is_dir || !r.dir_only) && r.glob.matches(rel))
+            .is_some_and(|r| !r.negated)
+    }
+}
@@ -0,0 +1,316 @@
+use std::io;
+
+
+/// Whether `data` starts like a gzip stream (RFC 1952 magic bytes, and deflate as the method)
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b, 8])
+}
+
+/// `data` as a single gzip member, deflated with fixed Huffman codes (no external crate needed for tracking files,
+/// which are mostly repeated JSON keys, so plain LZ77 matching does most of the work)
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3];  // no flags, no mtime, no extra flags, OS = Unix
+    let mut bits = BitWriter { out, buf: 0, len: 0 };
+    bits.put(1, 1);  // BFINAL: this is the only block
+    bits.put(1, 2);  // BTYPE 01: fixed Huffman codes
+    deflate_fixed(data, &mut bits);
+    put_symbol(&mut bits, 256);  // end of block
+    out = bits.finish();
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());  // ISIZE is the length modulo 2^32
+    out
+}
+
+/// The content of a gzip stream (of one or more members), checking each member's CRC and length
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        if !is_gzip(&data[pos..]) { return Err(invalid("not a gzip stream")); }
+        let start = out.len();
+        let mut bits = BitReader { data, pos: skip_header(data, pos)?, buf: 0, len: 0 };
+        inflate(&mut bits, &mut out)?;
+        pos = bits.pos;  // the unused bits of the last byte are dropped
+        let trailer = data.get(pos..pos + 8).ok_or_else(|| invalid("truncated gzip trailer"))?;
+        let (crc, size) = (u32::from_le_bytes(trailer[..4].try_into().unwrap()), u32::from_le_bytes(trailer[4..].try_into().unwrap()));
+        if crc != crc32(&out[start..]) || size != (out.len() - start) as u32 { return Err(invalid("gzip checksum mismatch")); }
+        pos += 8;
+    }
+    Ok(out)
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// Where a member's deflate data starts, past its header (and the optional fields its flags announce)
+fn skip_header(data: &[u8], start: usize) -> io::Result<usize> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+    let truncated = || invalid("truncated gzip header");
+    let flags = *data.get(start + 3).ok_or_else(truncated)?;
+    let mut pos = start + 10;
+    if flags & FEXTRA != 0 {
+        let len = data.get(pos..pos + 2).ok_or_else(truncated)?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for field in [FNAME, FCOMMENT] {
+        if flags & field != 0 {  // zero-terminated
+            pos += data.get(pos..).and_then(|rest| rest.iter().position(|&b| b == 0)).ok_or_else(truncated)? + 1;
+        }
+    }
+    if flags & FHCRC != 0 { pos += 2; }
+    if pos > data.len() { return Err(truncated()); }
+    Ok(pos)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+//////////////////////////////////////////DEFLATE///////////////////////////////////////////////////
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// RFC 1951, 3.2.5: the lengths and distances that each code (starting at 257, and 0) stands for, and their extra bits
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// RFC 1951, 3.2.7: the order in which a dynamic block lists its code length code lengths
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;  // candidates tried per position; more compresses (slightly) better, but slower
+
+struct BitWriter { out: Vec<u8>, buf: u64, len: u32 }
+
+impl BitWriter {
+    /// Append the low `n` bits of `value`, least significant first
+    fn put(&mut self, value: u32, n: u32) {
+        self.buf |= u64::from(value) << self.len;
+        self.len += n;
+        while self.len >= 8 {
+            self.out.push(self.buf as u8);
+            self.buf >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 { self.out.push(self.buf as u8); }
+        self.out
+    }
+}
+
+/// The fixed literal/length code of `symbol` (RFC 1951, 3.2.6), which unlike everything else goes most significant bit first
+fn put_symbol(bits: &mut BitWriter, symbol: u16) {
+    let (code, len) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + (symbol - 144), 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + (symbol - 280), 8),
+    };
+    bits.put(reverse(code, len), len);
+}
+
+fn reverse(code: u16, len: u32) -> u32 {
+    u32::from(code.reverse_bits() >> (16 - len))
+}
+
+/// Greedy LZ77 over hash chains of 3-byte prefixes, written as fixed-code symbols
+fn deflate_fixed(data: &[u8], bits: &mut BitWriter) {
+    let hash = |pos: usize| (usize::from(data[pos]) << 10 ^ usize::from(data[pos + 1]) << 5 ^ usize::from(data[pos + 2])) & (WINDOW - 1);
+    let mut head = vec![usize::MAX; WINDOW];  // hash -> latest position with it
+    let mut prev = vec![usize::MAX; WINDOW];  // position (mod WINDOW) -> the one before it with the same hash
+    let insert = |pos: usize, head: &mut [usize], prev: &mut [usize]| {
+        if pos + MIN_MATCH > data.len() { return; }
+        let h = hash(pos);
+        prev[pos % WINDOW] = head[h];
+        head[h] = pos;
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(pos)];
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || candidate >= pos || pos - candidate > WINDOW { break; }
+                let len = data[candidate..].iter().zip(&data[pos..pos + max_len]).take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    (best_len, best_dist) = (len, pos - candidate);
+                    if len == max_len { break; }
+                }
+                candidate = prev[candidate % WINDOW];
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            let code = LENGTH_BASE.iter().rposition(|&base| usize::from(base) <= best_len).unwrap();
+            put_symbol(bits, 257 + code as u16);
+            bits.put((best_len - usize::from(LENGTH_BASE[code])) as u32, u32::from(LENGTH_EXTRA[code]));
+            let code = DIST_BASE.iter().rposition(|&base| usize::from(base) <= best_dist).unwrap();
+            bits.put(reverse(code as u16, 5), 5);
+            bits.put((best_dist - usize::from(DIST_BASE[code])) as u32, u32::from(DIST_EXTRA[code]));
+            for p in pos..pos + best_len { insert(p, &mut head, &mut prev); }
+            pos += best_len;
+        } else {
+            put_symbol(bits, u16::from(data[pos]));
+            insert(pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+}
+
+
+struct BitReader<'a> { data: &'a [u8], pos: usize, buf: u32, len: u32 }
+
+impl BitReader<'_> {
+    /// The next `n` (up to 16) bits, least significant first
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.len < n {
+            let byte = *self.data.get(self.pos).ok_or_else(|| invalid("truncated deflate data"))?;
+            self.buf |= u32::from(byte) << self.len;
+            self.pos += 1;
+            self.len += 8;
+        }
+        let value = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.len -= n;
+        Ok(value)
+    }
+
+    /// Skip to the next byte boundary (the bits left over are all from the last byte read)
+    fn align(&mut self) {
+        self.buf = 0;
+        self.len = 0;
+    }
+
+    fn symbol(&mut self, huffman: &Huffman) -> io::Result<usize> {
+        // canonical codes of each length are consecutive, so one bit at a time is enough to find a code's symbol
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for len in 1..huffman.counts.len() {
+            code |= self.bits(1)? as usize;
+            let count = huffman.counts[len];
+            if code < first + count { return Ok(huffman.symbols[index + code - first]); }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code in deflate data"))
+    }
+}
+
+/// A canonical Huffman code: how many codes there are of each length (1 to 15), and the symbols in code order
+struct Huffman { counts: [usize; 16], symbols: Vec<usize> }
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &len in lengths { counts[usize::from(len)] += 1; }
+        counts[0] = 0;
+        let mut symbols: Vec<usize> = (0..lengths.len()).filter(|&s| lengths[s] != 0).collect();
+        symbols.sort_by_key(|&s| lengths[s]);  // stable, so symbols of one length stay in order
+        Huffman { counts, symbols }
+    }
+}
+
+fn inflate(bits: &mut BitReader, out: &mut Vec<u8>) -> io::Result<()> {
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = bits.data.get(bits.pos..bits.pos + 4).ok_or_else(|| invalid("truncated stored block"))?;
+                let (len, nlen) = (u16::from_le_bytes([header[0], header[1]]), u16::from_le_bytes([header[2], header[3]]));
+                if len != !nlen { return Err(invalid("corrupt stored block length")); }
+                let start = bits.pos + 4;
+                out.extend_from_slice(bits.data.get(start..start + usize::from(len)).ok_or_else(|| invalid("truncated stored block"))?);
+                bits.pos = start + usize::from(len);
+            },
+            1 => {
+                let lengths: Vec<u8> = (0..288).map(|s| match s { 0..=143 => 8, 144..=255 => 9, 256..=279 => 7, _ => 8 }).collect();
+                inflate_block(bits, out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            },
+            2 => {
+                let (literals, distances) = read_dynamic_codes(bits)?;
+                inflate_block(bits, out, &literals, &distances)?;
+            },
+            _ => return Err(invalid("invalid deflate block type")),
+        }
+        if last { return Ok(()); }
+    }
+}
+
+/// The literal/length and distance codes at the start of a dynamic block (RFC 1951, 3.2.7)
+fn read_dynamic_codes(bits: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_lengths] { lengths[symbol] = bits.bits(3)? as u8; }
+    let code_length_code = Huffman::new(&lengths);
+
+    let mut lengths: Vec<u8> = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (value, repeat) = match bits.symbol(&code_length_code)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| invalid("repeated code length with nothing before it"))?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literals + distances { return Err(invalid("code lengths overrun their table")); }
+    if lengths[256] == 0 { return Err(invalid("dynamic block has no end-of-block code")); }
+    Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+fn inflate_block(bits: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> io::Result<()> {
+    loop {
+        match bits.symbol(literals)? {
+            literal @ 0..=255 => out.push(literal as u8),
+            256 => return Ok(()),
+            symbol => {
+                let code = symbol - 257;
+                if code >= LENGTH_BASE.len() { return Err(invalid("invalid length code in deflate data")); }
+                let len = usize::from(LENGTH_BASE[code]) + bits.bits(u32::from(LENGTH_EXTRA[code]))? as usize;
+                let code = bits.symbol(distances)?;
+                if code >= DIST_BASE.len() { return Err(invalid("invalid distance code in deflate data")); }
+                let dist = usize::from(DIST_BASE[code]) + bits.bits(u32::from(DIST_EXTRA[code]))? as usize;
+                if dist > out.len() { return Err(invalid("deflate distance reaches before the start of the data")); }
+                let start = out.len() - dist;
+                for i in 0..len { out.push(out[start + i]); }  // one at a time: a match can overlap what it's copying
+            },
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+//////////////////////////////////////////CRC-32////////////////////////////////////////////////////
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+// the reflected IEEE polynomial, as gzip uses it
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &b| CRC_TABLE[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8))
+}
@@ -2,127 +2,1116 @@
 mod tests;
 mod structures;
 mod args_parse;
+mod sync;
+mod hashing;
+mod error;
+mod glob;
+mod gzip;
+mod unicode;
+mod unicode_tables;
+mod walk;
+mod watch;
 
 pub use crate::args_parse::ProgramArgs;
+pub use crate::sync::{ask_on_terminal, Answer, ConflictPolicy, NoObserver, ProgressJson, ReflinkMode, RenameMap, SymlinkMode, SyncObserver, SyncOptions, SyncStats};
+use crate::sync::{can_change_owner, trash_timestamp, copy_file, copy_file_verified, make_hardlink, make_symlink, move_entry, move_to_backup, remove_entry, sync_attributes, Throttle};
 
-#[cfg(unix)]
-use crate::structures::ManifestEntry;
+pub use crate::structures::{CompareOptions, FieldChange, FieldDiff, FileMeta, ManifestEntry, NodeType, MANIFEST_HEADER, MANIFEST_VERSION};
+pub use crate::hashing::HashAlgo;
+pub use crate::error::{FilesyncError, Result};
+pub use crate::glob::{Glob, IgnoreRules};
+use crate::error::IoContext;
 
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
+use std::ffi::OsStr;
 use std::fs;
 use std::fs::{File, OpenOptions};
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use rayon::prelude::*;
+use crate::walk::walk;
+use crate::watch::watch;
 use std::io::{Write, BufWriter};
 use crate::structures::Manifest;
+pub use crate::structures::DuplicatePolicy;
 
 pub const TRACKING_FILENAME: &str = "filesync_tracking.txt";
+/// The tracking file's name when written with `--compress` (gzipped); readers take either
+pub const COMPRESSED_TRACKING_FILENAME: &str = "filesync_tracking.txt.gz";
+/// The manifest `--sync-both` keeps in both of its directories: what they held after the last two-way sync
+pub const BASELINE_FILENAME: &str = "filesync_baseline.txt";
+/// Ignore file (gitignore syntax, see [`IgnoreRules`]) that's honored at the root of every scanned tree
+pub const IGNORE_FILENAME: &str = ".filesyncignore";
+/// Where `--sync --trash` puts slave entries that master doesn't have; left out of every scan, like the tracking file
+pub const TRASH_DIRNAME: &str = ".filesync-trash";
+
+/// Settings that control which entries a tree scan records
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Only include paths under one of these subdirs (relative to the scanned root, compared component-wise); empty means everything
+    pub only: Vec<PathBuf>,
+    /// Skip (and report) entries that can't be read due to permissions, rather than failing
+    pub ignore_permission_errors: bool,
+    /// Skip (entirely) any directory that directly contains an entry with one of these names
+    pub exclusion_markers: Vec<String>,
+    /// Skip entries matching any of these; a matched directory is skipped along with everything inside it
+    pub excludes: Vec<Glob>,
+    /// Record a digest of every regular file's content, made with this algorithm
+    pub hash: Option<HashAlgo>,
+    /// Descend into symlinked directories, recording links as what they point to (see [`discover_files`])
+    pub follow_links: bool,
+    /// Don't record entries nested deeper than this (1 = only the root's direct children)
+    pub max_depth: Option<usize>,
+    /// Reuse what this (earlier) manifest of the same tree recorded, for the entries of directories whose mtime hasn't
+    /// changed since; only entries that are new, or in a changed directory (or the root), are stat-ed (and hashed) again
+    pub previous: Option<Manifest>,
+}
+
+impl TryFrom<&ProgramArgs> for SyncOptions {
+    type Error = FilesyncError;
+
+    fn try_from(args: &ProgramArgs) -> Result<Self> {
+        Ok(SyncOptions {
+            rename_map: args.rename_map.as_deref().map(RenameMap::read).transpose()?.unwrap_or_default(),
+            attrs_only: args.attrs_only,
+            delete: args.delete,
+            preserve_owner: args.preserve_owner,
+            dry_run: args.dry_run,
+            progress_json: args.progress_json,
+            compare: CompareOptions::from(args),
+            jobs: args.jobs,
+            bwlimit: args.bwlimit,
+            verify: args.verify.then_some(args.hash_algo),
+            reflink: args.reflink,
+            hardlink_dupes: args.hardlink_dupes,
+            backup_dir: args.backup_dir.clone(),
+            trash: args.trash,
+            interactive: args.interactive,
+            symlink_mode: args.symlink_mode,
+        })
+    }
+}
+
+impl From<&ProgramArgs> for CompareOptions {
+    fn from(args: &ProgramArgs) -> Self {
+        CompareOptions {
+            mtime_tolerance: std::time::Duration::from_millis(args.mtime_tolerance),
+            ignore_mtime: args.ignore_mtime,
+            ignore_mode: args.ignore_mode,
+            size_only: args.size_only,
+            ignore_case: args.ignore_case,
+            normalize_unicode: args.normalize_unicode,
+        }
+    }
+}
+
+impl From<&ProgramArgs> for ScanOptions {
+    fn from(args: &ProgramArgs) -> Self {
+        ScanOptions {
+            only: args.only.iter().map(PathBuf::from).collect(),
+            ignore_permission_errors: args.ignore_permission_errors,
+            exclusion_markers: args.exclude_if_present.clone(),
+            excludes: args.exclude.iter().map(|p| Glob::new(p)).collect(),
+            hash: args.hash.then_some(args.hash_algo),
+            follow_links: args.follow_links,
+            max_depth: args.max_depth,
+            previous: None,  // read from the tracking file, if there is one (see `--update`)
+        }
+    }
+}
+
+pub fn run(args: ProgramArgs) -> Result<String> {
+    run_with_observer(args, &NoObserver)
+}
+
+/// Like [`run`], with `observer` told about the files a sync copies
+pub fn run_with_observer(args: ProgramArgs, observer: &dyn SyncObserver) -> Result<String> {
+    let scan = ScanOptions::from(&args);
+    let compare = CompareOptions::from(&args);
 
-pub fn run(args: ProgramArgs) -> String {
     if let Some(dir) = args.track {
-        write_tracking_file_with_content(dir, args.prefix.as_deref())
-            .to_str().unwrap().to_string()
+        let previous = existing_tracking_file(&dir).filter(|_| args.update && !args.full);
+        let previous = previous.map(|file| Manifest::deserialize_manifest(&read_tracking_file_into_string(&file)?)).transpose()?;
+        let scan = ScanOptions { previous, ..scan };
+        if args.dry_run {
+            return Ok(preview_tracking_file_update(dir, &scan)?.join("\n"));
+        }
+        if args.compress {
+            return Ok(write_compressed_tracking_file(dir, &scan, args.names_only)?.display().to_string());
+        }
+        if args.names_only {
+            return Ok(write_tracking_file_with_names(dir, &scan)?.display().to_string());
+        }
+        Ok(write_tracking_file_with_content(dir, &scan)?.display().to_string())
+    } else if let Some(tracking_files) = args.manifest_merge {
+        let output = args.output.expect("clap requires --output with --manifest-merge");
+        Ok(merge_tracking_files(&tracking_files, &output, args.on_duplicate)?.display().to_string())
     } else if let Some(files_pair) = args.diff {
-        let master = &files_pair[0];
-        let slave = &files_pair[1];
-        // ...
-        "".to_string()
-    } else if let Some(dirs) = args.sync {
-        let master = &dirs[0];
-        let slave = &dirs[1];
-        // ...
-        "".to_string()
+        let (master, slave) = (&files_pair[0], &files_pair[1]);
+        Ok(diff_trees(master, slave, &scan, &compare)?.join("\n"))
+    } else if let Some(pair) = args.check {
+        let (tracking_file, dir) = (&pair[0], &pair[1]);
+        Ok(check_tracking_file(tracking_file, dir, &scan, &compare)?.join("\n"))
+    } else if let Some(dir) = &args.find_dupes {
+        Ok(find_dupes(dir, &ScanOptions { hash: Some(args.hash_algo), ..scan })?.join("\n"))
+    } else if let Some(dirs) = &args.sync_both {
+        Ok(sync_both(&dirs[0], &dirs[1], &scan, &compare, args.conflict, args.dry_run)?.join("\n"))
+    } else if let Some(dirs) = &args.sync {
+        let (master, slave) = (&dirs[0], &dirs[1]);
+        let sync = SyncOptions::try_from(&args)?;
+        let (log, stats) = match sync.attrs_only {
+            true => sync_attributes_only(master, slave, &scan, &sync, sync.progress_json.then(std::io::stderr))?,
+            false => sync_trees(master, slave, &scan, &sync, sync.progress_json.then(std::io::stderr), observer)?,
+        };
+        if args.watch {  // which only returns on failure, so everything is printed as it happens
+            let report = |log: Vec<String>, stats: SyncStats| {
+                if !args.quiet && !log.is_empty() { println!("{}", log.join("\n")); }
+                eprintln!("{}", if args.stats { stats.breakdown() } else { stats.to_string() });
+            };
+            report(log, stats);
+            watch(master, slave, &scan, &sync, observer, &AtomicBool::new(false), report)?;
+            return Ok(String::new());
+        }
+        if !sync.progress_json {  // which has its own `finish` event, and mustn't get other lines mixed in
+            eprintln!("{}", if args.stats { stats.breakdown() } else { stats.to_string() });
+        }
+        Ok(if args.quiet { String::new() } else { log.join("\n") })
     } else {
         unreachable!("clap ArgGroup enforces exactly one command");
     }
 }
 
-pub fn write_tracking_file(dir: impl AsRef<Path>) -> (PathBuf, File) {
-    let dir = dir.as_ref();
+pub fn write_tracking_file(dir: impl AsRef<Path>) -> Result<(PathBuf, File)> {
+    let file_path = tracking_file_path(dir.as_ref(), TRACKING_FILENAME)?;
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)  // existing content is kept until it's explicitly rewritten
+        .read(true)  // for optionally reading from the same handle later
+        .write(true)  // for optionally writing with the same handle later
+        .open(&file_path)
+        .context(|| format!("failed to create '{}'", file_path.display()))?;
 
-    match fs::metadata(&dir) {
+    Ok((file_path, file))
+}
+
+/// `dir/name`, after checking that `dir` is a directory, and that `name` isn't something other than a file in it
+fn tracking_file_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    match fs::metadata(dir) {
         Ok(md) if md.is_dir() => {}
-        Ok(_) => panic!("not a directory: '{}'", dir.display()),
-        Err(e) => panic!("metadata failed for '{}': {e}", dir.display()),
+        Ok(_) => return Err(FilesyncError::NotADirectory(dir.to_path_buf())),
+        Err(e) => return Err(e).context(|| format!("metadata failed for '{}'", dir.display())),
     }
 
-    let file_path = dir.join(TRACKING_FILENAME);
+    let file_path = dir.join(name);
 
     match fs::symlink_metadata(&file_path) {
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}  // doesn't exist: ok
         Ok(m) if m.is_file() => {}                             // exists and is file: ok
-        Ok(_) => panic!("tracking path exists but is not a file: '{}'", file_path.display()),
-        Err(e) => panic!("metadata failed for '{}': {e}", file_path.display()),
+        Ok(_) => return Err(FilesyncError::NotAFile(file_path)),
+        Err(e) => return Err(e).context(|| format!("metadata failed for '{}'", file_path.display())),
     }
+    Ok(file_path)
+}
 
-    let file = OpenOptions::new()
-        .create(true)
-        .read(true)  // for optionally reading from the same handle later
-        .write(true)  // for optionally writing with the same handle later
-        .open(&file_path)
-        .unwrap_or_else(|e| panic!("failed to create '{}': {e}", file_path.display()));
+/// The tracking file in `dir`, compressed or not, if there is one
+fn existing_tracking_file(dir: &Path) -> Option<PathBuf> {
+    [COMPRESSED_TRACKING_FILENAME, TRACKING_FILENAME].into_iter().map(|name| dir.join(name)).find(|path| path.is_file())
+}
 
-    (file_path, file)
+/// Remove the tracking file in `dir` that's named `name`, since one under the other name was just written
+fn remove_replaced_tracking_file(dir: &Path, name: &str) -> Result<()> {
+    let path = dir.join(name);
+    if !path.is_file() { return Ok(()); }
+    fs::remove_file(&path).context(|| format!("failed to remove '{}'", path.display()))
 }
 
 
 
+/// Every entry under `dir` (except its tracking file, compressed or not, and its two-way sync baseline), with metadata, sorted by `path_key`
+pub fn build_manifest(dir: impl AsRef<Path>) -> Result<Vec<ManifestEntry>> {
+    Ok(discover_files(dir.as_ref(), &ScanOptions::default())?.into())
+}
+
 /// Walk directory
-fn discover_files(root: &Path, allowed_prefixes: Option<&[String]>) -> Manifest {
-    let root_str = root.to_str().unwrap();
-
-    let mut out: Manifest = WalkDir::new(root).follow_links(false).into_iter()
-        .filter_entry(|e| {
-            allowed_prefixes.is_none() || e.depth() == 0 || {  // depth 0 is root, which we don't want to stop at
-                allowed_prefixes.into_iter()
-                    .flatten()
-                    .map(|p| format!("{root_str}/{p}"))
-                    .any(|s| e.path().starts_with(s))
-            }
+/// Entries matching `options.excludes` are always skipped; the root's ignore file can only narrow things further,
+/// so its `!` rules can't bring back something an `--exclude` pattern matched.
+///
+/// With `options.follow_links`, symlinks are recorded as their targets (a linked directory's content is listed under
+/// the link's path), except for links that can't be followed: broken ones, and ones that lead back into one of their
+/// own ancestors (a loop, detected by the walker comparing device and inode numbers). Those are recorded as symlinks.
+///
+/// Entries that vanish during the scan (and, with `options.ignore_permission_errors`, unreadable ones) are left out,
+/// and listed together on stderr once the scan is done: `N entries skipped: 'a' (vanished during the scan), ...`
+fn discover_files(root: &Path, options: &ScanOptions) -> Result<Manifest> {
+    let (manifest, skipped) = scan_tree(root, options)?;
+    if !skipped.is_empty() {
+        let list = skipped.iter().map(|s| format!("'{}' ({})", root.join(&s.path).display(), s.reason)).collect::<Vec<_>>().join(", ");
+        eprintln!("{} entries skipped: {list}", skipped.len());
+    }
+    Ok(manifest)
+}
+
+/// An entry that [`scan_tree`] left out instead of failing, and why
+#[derive(Debug)]
+struct SkippedEntry {
+    /// Relative to the scanned root
+    path: PathBuf,
+    reason: &'static str,
+}
+
+impl SkippedEntry {
+
+    /// `path`, if `kind` of error is one the scan can do without: the entry's gone, or (when allowed) unreadable
+    fn skippable(path: &Path, kind: Option<std::io::ErrorKind>, options: &ScanOptions) -> Option<Self> {
+        let reason = match kind? {
+            std::io::ErrorKind::NotFound => "vanished during the scan",
+            std::io::ErrorKind::PermissionDenied if options.ignore_permission_errors => "permission denied",
+            _ => return None,
+        };
+        Some(SkippedEntry { path: path.to_path_buf(), reason })
+    }
+}
+
+/// What [`discover_files`] does, with the skipped entries returned rather than reported
+fn scan_tree(root: &Path, options: &ScanOptions) -> Result<(Manifest, Vec<SkippedEntry>)> {
+    let ignore_file = root.join(IGNORE_FILENAME);
+    let ignored = if ignore_file.is_file() {
+        IgnoreRules::parse(&fs::read_to_string(&ignore_file).context(|| format!("failed to read '{}'", ignore_file.display()))?)
+    } else { IgnoreRules::default() };
+
+    let mut skipped = Vec::new();
+    let walked = walk(root, options.follow_links, options.max_depth.unwrap_or(usize::MAX), |e| {
+        let trash = e.depth == 1 && e.file_name() == TRASH_DIRNAME;
+        // the way down to a nested --only subdir is walked, but (see below) not recorded
+        let allowed = e.path.strip_prefix(root).is_ok_and(|rel| options.is_selected(rel) || options.leads_to_selected(rel));
+        // prune marked directories before descending into them
+        let marked = e.file_type.is_dir() && options.exclusion_markers.iter().any(|m| e.path.join(m).exists());
+        // returning false for a directory also keeps the walk from descending into it
+        let excluded = e.path.strip_prefix(root).is_ok_and(|rel|
+            options.excludes.iter().any(|g| g.matches(rel)) || ignored.is_ignored(rel, e.file_type.is_dir()));
+        allowed && !trash && !marked && !excluded
+    });
+    let rel_paths = walked.into_iter()
+        .filter_map(|e| match e {
+            Ok(entry) => Some(Ok((entry.path, options.follow_links, entry.file_type.is_dir()))),
+            Err(err) if options.follow_links && err.depth > 0 && err.path.is_symlink() && err.source.kind() != std::io::ErrorKind::PermissionDenied => {
+                Some(Ok((err.path, false, false)))  // a loop or a broken link: keep the link itself
+            },
+            Err(err) => match SkippedEntry::skippable(err.path.strip_prefix(root).unwrap_or(&err.path), Some(err.source.kind()), options) {
+                Some(skip) if err.depth > 0 => { skipped.push(skip); None },
+                _ => {
+                    let context = format!("failed to walk '{}'", err.path.display());
+                    Some(Err(FilesyncError::Io { context, source: err.source }))
+                },
+            },
         })
-        .filter_map(|e| e.ok())  // ignore traversal errors for now
-        .filter(|e| e.depth() != 0)  // exclude root itself)
-        .map(|e| e.path().strip_prefix(root).unwrap().to_path_buf())
-        .filter(|rel| rel.as_os_str() != TRACKING_FILENAME)
-        .map(|rel| ManifestEntry::from_rel_path(root, rel))
-        .collect();
+        .map(|e| e.map(|(path, follow, is_dir)| (path.strip_prefix(root).unwrap().to_path_buf(), follow, is_dir)))
+        .filter(|e| e.as_ref().map_or(true, |(rel, _, _)| ![TRACKING_FILENAME, COMPRESSED_TRACKING_FILENAME, BASELINE_FILENAME].map(OsStr::new).contains(&rel.as_os_str()) && options.is_selected(rel)))
+        .collect::<Result<Vec<_>>>()?;
+    let stats = stat_entries(root, rel_paths, options);
 
+    let mut out = Vec::with_capacity(stats.len());
+    for (rel, stat) in stats {
+        match stat {
+            Ok(entry) => out.push(entry),
+            Err(FilesyncError::Io { context, source }) => match SkippedEntry::skippable(&rel, Some(source.kind()), options) {
+                Some(skip) => skipped.push(skip),
+                None => return Err(FilesyncError::Io { context, source }),
+            },
+            Err(e) => return Err(e),
+        }
+    }
+    let mut out = Manifest::from(out);
     out.sort();
-    out
+    Ok((out, skipped))
+}
+
+/// [`ManifestEntry::from_rel_path`] for each walked `(rel, follow_links, is_dir)`, in parallel (hashing, if any, included).
+/// With `options.previous`, directories are stat-ed first, and the entries of those whose mtime is the recorded one are
+/// taken from it, when they're recorded there too (and with a digest made the way `options.hash` asks for)
+fn stat_entries(root: &Path, walked: Vec<(PathBuf, bool, bool)>, options: &ScanOptions) -> Vec<(PathBuf, Result<ManifestEntry>)> {
+    let stat = |rel: PathBuf, follow| (rel.clone(), ManifestEntry::from_rel_path(root, rel, options.hash, follow));
+    let Some(previous) = &options.previous else {
+        return walked.into_par_iter().map(|(rel, follow, _)| stat(rel, follow)).collect();
+    };
+
+    let recorded: HashMap<&Path, &ManifestEntry> = previous.entries().iter().map(|e| (e.path_key(), e)).collect();  // "a/" == "a"
+    let (dirs, others): (Vec<_>, Vec<_>) = walked.into_iter().partition(|(_, _, is_dir)| *is_dir);
+    let mut stats: Vec<_> = dirs.into_par_iter().map(|(rel, follow, _)| stat(rel, follow)).collect();
+    let unchanged: HashSet<PathBuf> = stats.iter()
+        .filter(|(rel, now)| now.as_ref().is_ok_and(|now| recorded.get(rel.as_path())
+            .is_some_and(|then| then.record().ty == NodeType::Dir && then.record().mtime_ns == now.record().mtime_ns)))
+        .map(|(rel, _)| rel.clone())
+        .collect();
+
+    let reusable = |rel: &Path| rel.parent()
+        .filter(|dir| unchanged.contains(*dir))  // never the root, whose mtime isn't recorded
+        .and_then(|_| recorded.get(rel))
+        .filter(|then| {
+            let record = then.record();
+            let hash = options.hash.filter(|_| record.ty == NodeType::File);
+            record.ty != NodeType::Dir && record.content_hash().map(|(algo, _)| algo) == hash
+        })
+        .map(|&then| then.clone());
+    stats.par_extend(others.into_par_iter().map(|(rel, follow, _)| match reusable(&rel) {
+        Some(then) => (rel, Ok(then)),
+        None => stat(rel, follow),
+    }));
+    stats
 }
 
+impl ScanOptions {
+
+    /// Whether `rel` is within one of the `only` subdirs (always true when there are none)
+    fn is_selected(&self, rel: &Path) -> bool {
+        self.only.is_empty() || self.only.iter().any(|p| rel.starts_with(p))
+    }
 
+    /// Whether `rel` is a proper ancestor of one of the `only` subdirs
+    fn leads_to_selected(&self, rel: &Path) -> bool {
+        self.only.iter().any(|p| p.starts_with(rel) && p.components().count() > rel.components().count())
+    }
+}
 
-pub fn write_tracking_file_with_content(dir: impl AsRef<Path>, allowed_prefix: Option<&[String]>) -> PathBuf {
+/// `dir` relative to `root`, if it's inside it (compared as absolute paths, without needing either to exist)
+fn dir_under(dir: &Path, root: &Path) -> Option<PathBuf> {
+    let (dir, root) = (std::path::absolute(dir).ok()?, std::path::absolute(root).ok()?);
+    dir.strip_prefix(root).ok().filter(|rel| !rel.as_os_str().is_empty()).map(Path::to_path_buf)
+}
+
+
+
+pub fn write_tracking_file_with_content(dir: impl AsRef<Path>, options: &ScanOptions) -> Result<PathBuf> {
     let dir = dir.as_ref();
-    let (tracker_path, tracker_file) = write_tracking_file(dir);
+    let (tracker_path, tracker_file) = write_tracking_file(dir)?;
+
+    let entries = discover_files(dir, options)?;
+    write_manifest(entries, tracker_file, &tracker_path)?;
+    remove_replaced_tracking_file(dir, COMPRESSED_TRACKING_FILENAME)?;
 
-    let entries = discover_files(dir, allowed_prefix);
+    Ok(tracker_path)
+}
+
+/// Like [`write_tracking_file_with_content`], but only the paths are listed, one JSON string per line.
+/// [`read_tracking_file_into_filepaths`] reads such files; anything that needs the metadata (--check, --manifest-merge, ...) can't
+pub fn write_tracking_file_with_names(dir: impl AsRef<Path>, options: &ScanOptions) -> Result<PathBuf> {
+    let dir = dir.as_ref();
+    let (tracker_path, tracker_file) = write_tracking_file(dir)?;
+
+    let entries = discover_files(dir, options)?;
+    write_lines(Manifest::serialize_names(entries)?, tracker_file, &tracker_path)?;
+    remove_replaced_tracking_file(dir, COMPRESSED_TRACKING_FILENAME)?;
+
+    Ok(tracker_path)
+}
 
-    let data = Manifest::serialize(entries);
-    let mut w = BufWriter::new(tracker_file);  // buffered writing (smaller burden on RAM)
+/// Like [`write_tracking_file_with_content`] (or, with `names_only`, [`write_tracking_file_with_names`]), but gzipped,
+/// to `dir/COMPRESSED_TRACKING_FILENAME`. An uncompressed tracking file in `dir` is removed, so there's only ever one to read
+pub fn write_compressed_tracking_file(dir: impl AsRef<Path>, options: &ScanOptions, names_only: bool) -> Result<PathBuf> {
+    let dir = dir.as_ref();
+    let tracker_path = tracking_file_path(dir, COMPRESSED_TRACKING_FILENAME)?;
+
+    let entries = discover_files(dir, options)?;
+    let lines = if names_only { Manifest::serialize_names(entries)? } else { Manifest::serialize(entries)? };
+    let content: String = lines.into_iter().flat_map(|line| [line, "\n".to_string()]).collect();
+    fs::write(&tracker_path, gzip::compress(content.as_bytes())).context(|| format!("failed to write to '{}'", tracker_path.display()))?;
+    remove_replaced_tracking_file(dir, TRACKING_FILENAME)?;
+
+    Ok(tracker_path)
+}
+
+/// Manifests with more entries than this are streamed to their file (see [`Manifest::serialize_to`]) instead of being
+/// rendered whole first, so a huge tree's tracking file doesn't need a second copy of it all in memory
+const STREAMING_MIN_ENTRIES: usize = 100_000;
+
+/// Replace the contents of `file` (located at `path`) with the serialized manifest
+fn write_manifest(manifest: Manifest, file: File, path: &Path) -> Result<()> {
+    if manifest.len() <= STREAMING_MIN_ENTRIES {
+        return write_lines(Manifest::serialize(manifest)?, file, path);
+    }
+    let mut w = truncate_for_writing(file, path)?;
+    manifest.serialize_to(&mut w).and_then(|_| w.flush()).context(|| format!("failed to write to '{}'", path.display()))
+}
+
+fn write_lines(data: Vec<String>, file: File, path: &Path) -> Result<()> {
+    let mut w = truncate_for_writing(file, path)?;
     for d in data {
-        writeln!(w, "{}", d).unwrap_or_else(|err| panic!("failed to write to '{}': {err}", tracker_path.display()))
+        writeln!(w, "{}", d).context(|| format!("failed to write to '{}'", path.display()))?;
+    }
+    w.flush().context(|| format!("failed to write to '{}'", path.display()))
+}
+
+fn truncate_for_writing(file: File, path: &Path) -> Result<BufWriter<File>> {
+    file.set_len(0)  // drop the previous listing, which may be longer than the new one
+        .context(|| format!("failed to truncate '{}'", path.display()))?;
+    Ok(BufWriter::new(file))  // buffered writing (smaller burden on RAM)
+}
+
+/// Combine several tracking files into a single one at `output`. Shared paths are reported on stderr.
+pub fn merge_tracking_files(tracking_files: &[PathBuf], output: &Path, policy: DuplicatePolicy) -> Result<PathBuf> {
+    let manifests = tracking_files.iter()
+        .map(|f| Manifest::deserialize_manifest(&read_tracking_file_into_string(f)?))
+        .collect::<Result<_>>()?;
+
+    let (merged, duplicates) = Manifest::merge(manifests, policy)?;
+    for path in duplicates {
+        eprintln!("duplicate path in merged manifests: '{}'", path.display());
     }
 
-    tracker_path
+    let file = File::create(output).context(|| format!("failed to create '{}'", output.display()))?;
+    write_manifest(merged, file, output)?;
+    Ok(output.to_path_buf())
+}
+
+/// Compare a fresh scan of `dir` against its existing tracking file (if any), without writing anything.
+/// Returns one line per changed path: `+ path` (new), `- path` (gone), `~ path: size 100→120, mode 644→755`.
+pub fn preview_tracking_file_update(dir: impl AsRef<Path>, options: &ScanOptions) -> Result<Vec<String>> {
+    let dir = dir.as_ref();
+    let old = match existing_tracking_file(dir) {
+        Some(tracking_file) => Manifest::deserialize_manifest(&read_tracking_file_into_string(&tracking_file)?)?,
+        None => Manifest::default(),
+    };
+    let new = discover_files(dir, options)?;
+
+    Ok(pair_by_path(&old, &new, &CompareOptions::default()).into_iter()
+        .filter_map(|(path, pair)| match pair {
+            (None, Some(_)) => Some(format!("+ {}", path.display())),
+            (Some(_), None) => Some(format!("- {}", path.display())),
+            (Some(o), Some(n)) => o.diff(n).map(|diff| format!("~ {}: {}", path.display(),
+                diff.changes.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))),
+            (None, None) => unreachable!("every map entry has at least one side"),
+        })
+        .collect())
+}
+
+/// Compare the trees under `master` and `slave`, matching entries by `path_key`.
+/// Returns up to four sections (`only in master:`, `only in slave:`, `renamed:`, `differing:`), each followed by indented paths;
+/// renames (`old -> new`) are only detected for hashed files, and differing entries also list their changed fields as `master→slave`. Empty sections are left out, so identical trees give nothing.
+/// Mtimes count as equal within `compare.mtime_tolerance`.
+pub fn diff_trees(master: &Path, slave: &Path, options: &ScanOptions, compare: &CompareOptions) -> Result<Vec<String>> {
+    let master_entries = discover_files(master, options)?;
+    let slave_entries = discover_files(slave, options)?;
+    diff_manifests(&master_entries, &slave_entries, compare, ["only in master:", "only in slave:"])
+}
+
+/// Compare a tracking file to a fresh scan of `dir`, to see what drifted since it was written (see [`diff_trees`]).
+/// Returns the same sections, with `vanished:` (only in the tracking file) and `appeared:` (only in `dir`) for the one-sided ones.
+/// When the tracking file holds digests, `dir` is hashed with the same algorithm (unless `options.hash` says otherwise), so content changes are caught too.
+/// `options.only`, `options.excludes` and `options.max_depth` narrow the tracking file's entries as well as the scan.
+pub fn check_tracking_file(tracking_file: &Path, dir: &Path, options: &ScanOptions, compare: &CompareOptions) -> Result<Vec<String>> {
+    let stored: Manifest = Vec::<ManifestEntry>::from(Manifest::deserialize_manifest(&read_tracking_file_into_string(tracking_file)?)?)
+        .into_iter()
+        .filter(|e| options.is_selected(e.path_key())
+            && !options.excludes.iter().any(|g| g.matches(e.path_key()))
+            && options.max_depth.is_none_or(|depth| e.path_key().components().count() <= depth))
+        .collect();
+    let hash = options.hash.or_else(|| stored.entries().iter().find_map(|e| e.record().content_hash()).map(|(algo, _)| algo));
+    let live = discover_files(dir, &ScanOptions { hash, ..options.clone() })?;
+    diff_manifests(&stored, &live, compare, ["vanished:", "appeared:"])
+}
+
+/// Group the regular files in `dir` by content digest (`options.hash`, which must be set), and list each group of two or more:
+/// a `N copies of SIZE (WASTED wasted):` line, then its paths indented, sorted by wasted space (all copies but one), most first.
+/// Empty files aren't counted, since they waste nothing. Ends with a total, unless there are no duplicates at all
+pub fn find_dupes(dir: &Path, options: &ScanOptions) -> Result<Vec<String>> {
+    let entries = discover_files(dir, options)?;
+    let mut by_digest: HashMap<&str, Vec<&ManifestEntry>> = HashMap::new();
+    for e in entries.entries().iter().filter(|e| e.record().ty == NodeType::File && e.record().size.is_some_and(|size| size > 0)) {
+        let (_, digest) = e.record().content_hash().expect("scanned with a hash algorithm");
+        by_digest.entry(digest).or_default().push(e);
+    }
+    let mut groups: Vec<(u64, Vec<&ManifestEntry>)> = by_digest.into_values()
+        .filter(|group| group.len() > 1)
+        .map(|group| (group[0].record().size.unwrap_or(0), group))
+        .collect();
+    let wasted = |(size, group): &(u64, Vec<&ManifestEntry>)| size * (group.len() as u64 - 1);
+    groups.sort_by(|a, b| wasted(b).cmp(&wasted(a)).then_with(|| a.1[0].path_key().cmp(b.1[0].path_key())));
+
+    let mut out = Vec::new();
+    for group in &groups {
+        out.push(format!("{} copies of {} ({} wasted):", group.1.len(), sync::human_bytes(group.0), sync::human_bytes(wasted(group))));
+        out.extend(group.1.iter().map(|e| format!("  {}", e.path_key().display())));
+    }
+    if !groups.is_empty() {
+        out.push(format!("{} groups, {} wasted in total", groups.len(), sync::human_bytes(groups.iter().map(wasted).sum())));
+    }
+    Ok(out)
+}
+
+/// The sections of [`diff_trees`], for any two manifests; `one_sided` titles the entries only in `master`, then those only in `slave`
+fn diff_manifests(master_entries: &Manifest, slave_entries: &Manifest, compare: &CompareOptions, one_sided: [&str; 2]) -> Result<Vec<String>> {
+    for manifest in [master_entries, slave_entries] {
+        compare.check_collisions(manifest.entries().iter().map(ManifestEntry::path_key))?;
+    }
+    let (mut only_master, mut only_slave, mut retyped, mut differing) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (_, pair) in pair_by_path(master_entries, slave_entries, compare) {
+        match pair {
+            (Some(m), None) => only_master.push(m),
+            (None, Some(s)) => only_slave.push(s),
+            // a file in one tree and a directory in the other share a path (`a` and `a/` name the same thing); size, mtime & co don't compare
+            (Some(m), Some(s)) if m.record().ty != s.record().ty => {
+                retyped.push(format!("  {}: {}→{}", m.path_key().to_string_lossy().trim_end_matches('/'), m.record().ty, s.record().ty));
+            },
+            (Some(m), Some(s)) => {
+                let changes: Vec<String> = content_changes(m, s, compare)?.iter().map(ToString::to_string).collect();
+                if !changes.is_empty() { differing.push(format!("  {}: {}", m.path_key().display(), changes.join(", "))); }
+            },
+            (None, None) => unreachable!("every map entry has at least one side"),
+        }
+    }
+
+    let renames = pair_renames(&only_slave, &only_master);
+    let moved: HashSet<&Path> = renames.iter().flat_map(|(old, new)| [old.path_key(), new.path_key()]).collect();
+    let listed = |entries: Vec<&ManifestEntry>| -> Vec<String> { entries.into_iter()
+        .filter(|e| !moved.contains(e.path_key()))
+        .map(|e| format!("  {}", e.path_key().display()))
+        .collect() };
+    let renamed = renames.iter().map(|(old, new)| format!("  {} -> {}", old.path_key().display(), new.path_key().display())).collect();
+
+    Ok([(one_sided[0], listed(only_master)), (one_sided[1], listed(only_slave)), ("renamed:", renamed), ("type changed:", retyped), ("differing:", differing)].into_iter()
+        .filter(|(_, lines)| !lines.is_empty())
+        .flat_map(|(title, lines)| std::iter::once(title.to_string()).chain(lines))
+        .collect())
+}
+
+/// Pair up files that are gone from one place with files that appeared elsewhere holding the same content
+/// (same digest and size), as `(gone, appeared)`. Each file is paired at most once.
+/// This relies on content hashes, so without `--hash` nothing is ever paired.
+fn pair_renames<'a>(gone: &[&'a ManifestEntry], appeared: &[&'a ManifestEntry]) -> Vec<(&'a ManifestEntry, &'a ManifestEntry)> {
+    let content_key = |e: &'a ManifestEntry| e.record().content_hash().filter(|_| e.record().ty == NodeType::File).map(|h| (h, e.record().size));
+    let mut candidates: HashMap<_, Vec<&ManifestEntry>> = HashMap::new();
+    for e in gone.iter().rev() {  // popped from the back, so the first one listed is used first
+        if let Some(key) = content_key(e) { candidates.entry(key).or_default().push(e); }
+    }
+
+    appeared.iter()
+        .filter_map(|e| Some((candidates.get_mut(&content_key(e)?)?.pop()?, *e)))
+        .collect()
+}
+
+/// The fields that tell whether two entries of the same path hold different content: type, size, mtime and hash (when both have one).
+/// A symlink's content is its target instead; its own mtime can't be carried over by a sync, so it isn't compared.
+/// Fails if both entries are hashed, but with different algorithms, since their digests can't be compared.
+fn content_changes(master: &ManifestEntry, slave: &ManifestEntry, compare: &CompareOptions) -> Result<Vec<FieldChange>> {
+    if let (Some((m_algo, _)), Some((s_algo, _))) = (master.record().content_hash(), slave.record().content_hash()) {
+        if m_algo != s_algo {
+            return Err(FilesyncError::HashAlgoMismatch { path: master.path_key().to_path_buf(), master: m_algo.name(), slave: s_algo.name() });
+        }
+    }
+    let both_links = master.record().ty == NodeType::Symlink && slave.record().ty == NodeType::Symlink;
+    let changes = master.diff(slave).map(|diff| diff.changes).unwrap_or_default().into_iter()
+        .filter(|c| if both_links { c.field == "link_target" } else { matches!(c.field, "type" | "size" | "mtime_ns" | "hash_sha256" | "hash_blake3") })
+        .collect();
+    Ok(compare.significant(changes, master.record(), slave.record()))
+}
+
+/// Line up the entries of two manifests by path (see [`CompareOptions::match_key`]): path -> (entry in `a`, entry in `b`)
+fn pair_by_path<'a>(a: &'a Manifest, b: &'a Manifest, compare: &CompareOptions) -> BTreeMap<Cow<'a, Path>, (Option<&'a ManifestEntry>, Option<&'a ManifestEntry>)> {
+    let mut by_path: BTreeMap<Cow<Path>, (Option<&ManifestEntry>, Option<&ManifestEntry>)> = BTreeMap::new();
+    for e in a.entries() { by_path.entry(compare.match_key(e.path_key())).or_default().0 = Some(e); }
+    for e in b.entries() { by_path.entry(compare.match_key(e.path_key())).or_default().1 = Some(e); }
+    by_path
+}
+
+/// Where each master entry goes in the slave: through `rename_map`, and when `compare` folds paths, onto the slave entry
+/// with the same match key, if there is one (or into such a directory), so that the slave's spelling is kept.
+/// Fails on master paths that would land on the same slave path
+fn dest_keys<'a>(master: &'a Manifest, slave: &Manifest, rename_map: &RenameMap, compare: &CompareOptions) -> Result<HashMap<&'a Path, PathBuf>> {
+    rename_map.destinations(master.entries().iter().map(ManifestEntry::path_key))?;
+    let renamed = master.entries().iter().map(|m| (m.path_key(), rename_map.apply(m.path_key())));
+    if !compare.folds_paths() { return Ok(renamed.collect()); }
+
+    let renamed: Vec<(&Path, PathBuf)> = renamed.collect();
+    compare.check_collisions(renamed.iter().map(|(_, dest)| dest.as_path()))?;
+    let slave_spelling: HashMap<Cow<Path>, &Path> = slave.entries().iter().map(|s| (compare.match_key(s.path_key()), s.path_key())).collect();
+    let mut resolved: HashMap<PathBuf, PathBuf> = HashMap::new();  // match key -> dest, for the parents of later entries
+    let mut out = HashMap::new();
+    for (src, dest) in renamed {  // parents come first
+        let key = compare.match_key(&dest).into_owned();
+        let spelled = match slave_spelling.get(key.as_path()) {
+            Some(existing) => existing.to_path_buf(),
+            None => match dest.parent().and_then(|parent| resolved.get(compare.match_key(parent).as_ref())) {
+                Some(parent) => {
+                    let joined = parent.join(dest.file_name().expect("entries have a name"));
+                    if dest.as_os_str().to_string_lossy().ends_with('/') { joined.join("") } else { joined }  // keep a dir's trailing slash
+                },
+                None => dest,
+            },
+        };
+        resolved.insert(key, spelled.clone());
+        out.insert(src, spelled);
+    }
+    Ok(out)
+}
+
+/// For every slave entry whose content already matches its master counterpart (same type and size),
+/// reapply the master's mode and mtime (and, with `sync.preserve_owner`, ownership) without copying any bytes. Symlinks are left alone.
+/// Returns one line per fixed entry: `attrs path: mode 644→755, ...`, and their count. Progress events go to `progress_out`, if given.
+pub fn sync_attributes_only(master: &Path, slave: &Path, options: &ScanOptions, sync: &SyncOptions, progress_out: Option<impl Write>) -> Result<(Vec<String>, SyncStats)> {
+    let started = Instant::now();
+    let master_entries = discover_files(master, options)?;
+    let slave_entries = discover_files(slave, options)?;
+    let slave_by_path: HashMap<&Path, &ManifestEntry> = slave_entries.entries().iter().map(|e| (e.path_key(), e)).collect();
+    let dest_keys = dest_keys(&master_entries, &slave_entries, &sync.rename_map, &sync.compare)?;
+    let mut progress = progress_out.map(|out| ProgressJson::new(out, master_entries.entries().len()));
+    let preserve_owner = sync.preserve_owner && can_change_owner();
+
+    let mut log: Vec<String> = Vec::new();
+    for m in master_entries.entries().iter().rev() {  // children before their parent directories
+        let dest_key = &dest_keys[m.path_key()];
+        let fixed = slave_by_path.get(dest_key.as_path())
+            .filter(|s| m.record().ty == s.record().ty && m.record().size == s.record().size && m.record().ty != NodeType::Symlink)
+            .map(|s| sync_attributes(&slave.join(dest_key), m.record(), s.record(), preserve_owner, &sync.compare, sync.dry_run))
+            .transpose()?
+            .filter(|changes| !changes.is_empty());
+
+        if let Some(progress) = progress.as_mut() { progress.advance(fixed.as_ref().map(|_| ("attrs", dest_key.as_path(), None))); }
+        if let Some(changes) = fixed {
+            log.push(format!("attrs {}: {}", dest_key.display(), changes.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")));
+        }
+    }
+
+    if let Some(progress) = progress { progress.finish(); }
+    log.reverse();
+    let stats = SyncStats { attrs_fixed: log.len(), elapsed: started.elapsed(), dry_run: sync.dry_run, ..SyncStats::default() };
+    Ok((log, stats))
+}
+
+/// Mirror `master` into `slave`: create missing directories, copy files that are missing or differ (see [`diff_trees`]),
+/// recreate symlinks from their recorded target, and, with `sync.delete`, remove slave entries that master doesn't have.
+/// With `sync.delete` and hashing on, a stale slave file whose content matches a missing one is moved into place instead.
+/// Copied and created entries then take master's mode and mtime (and owner, with `sync.preserve_owner`); other matching entries get theirs fixed too (`attrs` lines).
+/// Files are copied in parallel (`sync.jobs` at a time, after the directories are made, and `sync.bwlimit` bytes/s at most). A failed copy doesn't stop
+/// the others or the rest of the sync; all the failures are returned at the end, as a [`FilesyncError::CopyFailures`].
+/// With `sync.verify`, each copy is hashed and checked against its source; one that doesn't match is copied again, then counts as failed.
+/// `observer` hears about each copy as it happens (but not in a dry run).
+/// With `sync.backup_dir`, slave entries are moved there before being overwritten or deleted;
+/// a backup dir inside the slave is left out of its scan.
+/// With `sync.trash`, stale slave entries are handled as with `sync.delete`, but moved into a new `TRASH_DIRNAME/<UTC timestamp>/`
+/// folder at the slave root instead of being removed (`trash` lines); scans never include that folder.
+/// With `sync.interactive`, `observer` is asked to confirm each overwrite and deletion once everything's planned;
+/// declined ones are left as they are (attributes included), and quitting cancels the sync before it changes anything.
+/// With `sync.hardlink_dupes` (and hashing on), a file whose content, mode, owner and mtime are already in the slave is hardlinked to it.
+/// Returns one line per action (`mkdir`, `copy`, `update`, `rename`, `link`, `hardlink`, `delete`, `trash`, `attrs`), and their tallies;
+/// with `sync.dry_run` nothing is touched.
+pub fn sync_trees(master: &Path, slave: &Path, options: &ScanOptions, sync: &SyncOptions, progress_out: Option<impl Write + Send>, observer: &dyn SyncObserver) -> Result<(Vec<String>, SyncStats)> {
+    let started = Instant::now();
+    let master_entries = discover_files(master, options)?;
+    let slave_options = match sync.backup_dir.as_deref().and_then(|dir| dir_under(dir, slave)) {
+        Some(rel) => ScanOptions { excludes: [options.excludes.clone(), vec![Glob::literal(&rel)]].concat(), ..options.clone() },
+        None => options.clone(),
+    };
+    let slave_entries = discover_files(slave, &slave_options)?;
+    let slave_by_path: HashMap<&Path, &ManifestEntry> = slave_entries.entries().iter().map(|e| (e.path_key(), e)).collect();
+    let dest_keys = dest_keys(&master_entries, &slave_entries, &sync.rename_map, &sync.compare)?;
+    let destinations: HashSet<&Path> = dest_keys.values().map(PathBuf::as_path).collect();
+    // destinations holding an entry of another type, which has to go before master's can be put there (a file can't overwrite a directory)
+    let retyped: HashSet<&Path> = master_entries.entries().iter()
+        .filter(|m| matches!(m.record().ty, NodeType::File | NodeType::Dir | NodeType::Symlink))
+        .filter_map(|m| slave_by_path.get(dest_keys[m.path_key()].as_path()).filter(|s| s.record().ty != m.record().ty))
+        .map(|s| s.path_key())
+        .collect();
+
+    let stale: Vec<&ManifestEntry> = slave_entries.entries().iter()
+        .filter(|s| (sync.delete || sync.trash) && !destinations.contains(s.path_key()))
+        .filter(|s| !retyped.iter().any(|r| s.path_key().starts_with(r)))  // goes with the replaced directory
+        .collect();
+    // with --delete (or --trash), a stale file holding the same content as a missing one can be moved instead of copied over
+    let missing: Vec<&ManifestEntry> = master_entries.entries().iter()
+        .filter(|m| !slave_by_path.contains_key(dest_keys[m.path_key()].as_path()))
+        .collect();
+    let renames: HashMap<&Path, &ManifestEntry> = pair_renames(&stale, &missing).into_iter().map(|(old, new)| (new.path_key(), old)).collect();
+    let moved: HashSet<&Path> = renames.values().map(|old| old.path_key()).collect();
+    let mut stale: Vec<&ManifestEntry> = stale.into_iter().filter(|s| !moved.contains(s.path_key())).collect();
+
+    let mut progress = progress_out.map(|out| ProgressJson::new(out, master_entries.entries().len() + stale.len()));
+    let preserve_owner = sync.preserve_owner && can_change_owner();
+    let mut log: Vec<String> = Vec::new();
+    let mut stats = SyncStats { dry_run: sync.dry_run, ..SyncStats::default() };
+    // what's in the way of a new version goes to the backup dir first, if there is one
+    let back_up = |dest_key: &Path| -> Result<()> {
+        if retyped.contains(dest_key) { return Ok(()); }  // already put aside, before anything was created
+        let (Some(dir), Some(current)) = (&sync.backup_dir, slave_by_path.get(dest_key)) else { return Ok(()) };
+        move_to_backup(&slave.join(current.decode_path()), current.record(), &dir.join(current.decode_path()), preserve_owner)
+    };
+
+    // what each master symlink's copy points to (see `SymlinkMode`); `None` for the ones that are skipped
+    let link_targets: HashMap<&Path, Option<PathBuf>> = master_entries.entries().iter()
+        .filter_map(|m| Some((m.path_key(), m.record().link_target.as_deref()?)))
+        .map(|(link, target)| (link, sync.symlink_mode.target_for(master, link, target)))
+        .collect();
+
+    let mut planned: Vec<(&ManifestEntry, PathBuf, Option<&str>)> = master_entries.entries().iter()
+        .map(|m| {
+            let dest_key = dest_keys[m.path_key()].clone();
+            let current = slave_by_path.get(dest_key.as_path());
+            let changed = current.map(|s| content_changes(m, s, &sync.compare)).transpose()?.is_some_and(|changes| !changes.is_empty());
+            let current = current.filter(|_| !retyped.contains(dest_key.as_path()));  // to be replaced, as if it weren't there
+            let action = match (m.record().ty, current) {
+                (NodeType::Dir, None) => Some("mkdir"),
+                (NodeType::File, None) if renames.contains_key(m.path_key()) => Some("rename"),
+                (NodeType::File, None) => Some("copy"),
+                (NodeType::File, Some(_)) if changed => Some("update"),
+                (NodeType::Symlink, _) if link_targets[m.path_key()].is_none() => {
+                    eprintln!("warning: skipping '{}': it links to an absolute path outside the master tree", m.path_key().display());
+                    None
+                },
+                (NodeType::Symlink, None) => Some("link"),
+                (NodeType::Symlink, Some(s)) if s.record().link_target != link_targets[m.path_key()] => Some("link"),
+                _ => None,  // up to date, or not something we can recreate (sockets, fifos, ...)
+            };
+            Ok((m, dest_key, action))
+        })
+        .collect::<Result<_>>()?;
+    let is_copy = |action: &Option<&str>| matches!(action, Some("copy" | "update"));
+
+    // with --interactive, every overwrite and deletion is confirmed now, before anything's changed (or linked to)
+    let mut declined: HashSet<PathBuf> = HashSet::new();
+    if sync.interactive {
+        let mut yes_to_all = false;
+        let mut ask = |action: &str, path: &Path| -> Result<bool> {
+            if yes_to_all { return Ok(true); }
+            match observer.confirm(action, path)? {
+                Answer::Yes => Ok(true),
+                Answer::No => Ok(false),
+                Answer::All => { yes_to_all = true; Ok(true) },
+                Answer::Quit => Err(FilesyncError::Cancelled),
+            }
+        };
+        for (_, dest_key, action) in planned.iter_mut().filter(|(_, dest_key, action)| action.is_some() && slave_by_path.contains_key(dest_key.as_path())) {
+            if !ask("overwrite", dest_key)? {
+                *action = None;
+                declined.insert(dest_key.clone());
+            }
+        }
+        let mut kept: HashSet<&Path> = HashSet::new();
+        for s in stale.iter().rev() {  // children first: a directory that keeps some of them stays too, without asking
+            if kept.iter().any(|k| k.starts_with(s.path_key())) || !ask(if sync.trash { "trash" } else { "delete" }, s.path_key())? {
+                kept.insert(s.path_key());
+            }
+        }
+        stale.retain(|s| !kept.contains(s.path_key()));
+    }
+
+    // where stale and replaced entries go: the trash, the backup dir, or nowhere
+    let trash_dir = sync.trash.then(|| slave.join(TRASH_DIRNAME).join(trash_timestamp(std::time::SystemTime::now())));
+    let (deletion, put_aside) = match (&trash_dir, &sync.backup_dir) {
+        (Some(dir), _) => ("trash", Some(dir)),
+        (None, dir) => ("delete", dir.as_ref()),
+    };
+    let remove_or_put_aside = |s: &ManifestEntry| match put_aside {
+        Some(dir) => move_to_backup(&slave.join(s.decode_path()), s.record(), &dir.join(s.decode_path()), preserve_owner),
+        None => remove_entry(&slave.join(s.decode_path()), s.record().ty),
+    };
+
+    // entries of another type are cleared out of the way first, along with everything inside them (if they're directories).
+    // Master has nothing under a path where it has a file or symlink, so none of that is needed
+    for (m, dest_key, _) in planned.iter().filter(|(_, dest_key, action)| action.is_some() && retyped.contains(dest_key.as_path())) {
+        let old = slave_by_path[dest_key.as_path()];
+        let inside = slave_entries.entries().iter().filter(|s| s.path_key().starts_with(dest_key) && s.path_key() != old.path_key());
+        for s in inside.rev() {  // children before their parent directories
+            if !sync.dry_run { remove_or_put_aside(s)?; }
+            log.push(format!("{deletion} {}", s.path_key().display()));
+            stats.count(deletion, None);
+        }
+        if !sync.dry_run { remove_or_put_aside(old)?; }
+        log.push(format!("replace {}: {}→{}", dest_key.to_string_lossy().trim_end_matches('/'), old.record().ty, m.record().ty));
+    }
+
+    // a file whose content is already in the slave (or will be, from an earlier copy) can be linked to instead.
+    // A shared inode has one mode, owner and mtime, so only files that agree on those too are linked
+    let mut hardlinks: HashMap<PathBuf, PathBuf> = HashMap::new();  // dest -> existing dest with the same content
+    if sync.hardlink_dupes {
+        type LinkKey<'a> = (&'a str, Option<u32>, i128, Option<u32>, Option<u32>);  // digest, mode, mtime, uid, gid
+        fn link_key(m: &ManifestEntry) -> Option<LinkKey<'_>> {
+            let r = m.record();
+            r.content_hash().map(|(_, digest)| (digest, r.mode, r.mtime_ns, r.uid, r.gid))
+        }
+        let mut in_place: HashMap<_, PathBuf> = planned.iter()
+            .filter(|(m, _, action)| action.is_none() && m.record().ty == NodeType::File)
+            .filter_map(|(m, dest_key, _)| Some((link_key(m)?, dest_key.clone())))
+            .collect();
+        for (m, dest_key, action) in planned.iter_mut().filter(|(_, _, action)| is_copy(action)) {
+            let Some(key) = link_key(m) else { continue };
+            match in_place.get(&key) {
+                Some(existing) => { hardlinks.insert(dest_key.clone(), existing.clone()); *action = Some("hardlink"); },
+                None => { in_place.insert(key, dest_key.clone()); },
+            }
+        }
+    }
+
+    // everything but the copies (and links to them), in order: parents come before their children, so every destination directory exists in time
+    for (m, dest_key, action) in planned.iter().filter(|(_, _, action)| !is_copy(action) && *action != Some("hardlink")) {
+        let dest = slave.join(dest_key);
+        match action.filter(|_| !sync.dry_run) {
+            Some("mkdir") => fs::create_dir_all(&dest).context(|| format!("failed to create '{}'", dest.display()))?,
+            Some("link") => {
+                back_up(dest_key)?;
+                make_symlink(&dest, link_targets[m.path_key()].as_deref().expect("skipped links have no action"))?;
+            },
+            Some("rename") => move_entry(&slave.join(renames[m.path_key()].decode_path()), &dest)?,
+            _ => {},
+        }
+        if let Some(progress) = progress.as_mut() { progress.advance(action.map(|a| (a, dest_key.as_path(), None))); }
+    }
+
+    // then the copies, in parallel; a failed one doesn't stop the others, and all failures are reported together
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(sync.jobs.unwrap_or(0)).build()
+        .map_err(|e| FilesyncError::Io { context: "failed to start copying threads".to_string(), source: std::io::Error::other(e) })?;
+    let throttle = sync.bwlimit.map(Throttle::new);
+    let copy_entry = |m: &ManifestEntry, dest_key: &Path| -> Result<u64> {
+        back_up(dest_key)?;
+        observer.on_file_start(dest_key, m.record().size.unwrap_or(0));
+        let (src, dest) = (master.join(m.decode_path()), slave.join(dest_key));
+        let on_bytes = |n| {
+            if let Some(throttle) = &throttle { throttle.take(n); }
+            observer.on_bytes(n);
+        };
+        let copied = match sync.verify {
+            Some(algo) => {
+                let recorded = m.record().content_hash().filter(|(a, _)| *a == algo).map(|(_, digest)| digest);
+                copy_file_verified(&src, &dest, sync.reflink, algo, recorded, on_bytes)
+            },
+            None => copy_file(&src, &dest, sync.reflink, on_bytes),
+        };
+        observer.on_file_done(dest_key);
+        copied
+    };
+    if !sync.dry_run {
+        let copies = planned.iter().filter(|(_, _, action)| is_copy(action));
+        observer.on_sync_start(copies.clone().count(), copies.filter_map(|(m, _, _)| m.record().size).sum());
+    }
+    let progress = Mutex::new(progress);
+    let mut failures: Vec<(PathBuf, FilesyncError)> = pool.install(|| planned.par_iter()
+        .filter(|(_, _, action)| is_copy(action))
+        .filter_map(|(m, dest_key, action)| {
+            let bytes = match sync.dry_run {
+                true => None,
+                false => match copy_entry(m, dest_key) {
+                    Ok(bytes) => Some(bytes),
+                    Err(e) => return Some((dest_key.clone(), e)),
+                },
+            };
+            if let Some(progress) = progress.lock().unwrap().as_mut() { progress.advance(action.map(|a| (a, dest_key.as_path(), bytes))); }
+            None
+        })
+        .collect());
+    let mut progress = progress.into_inner().unwrap();
+
+    // and the links to copied (or already present) content; where one can't be made (across a mount point, say), it's a copy after all
+    for (m, dest_key, action) in planned.iter_mut().filter(|(_, _, action)| *action == Some("hardlink")) {
+        let mut bytes = None;
+        if !sync.dry_run && back_up(dest_key).and_then(|_| make_hardlink(&slave.join(&*dest_key), &slave.join(&hardlinks[&*dest_key]))).is_err() {
+            match copy_entry(m, dest_key) {
+                Ok(copied) => { bytes = Some(copied); *action = Some("copy"); },
+                Err(e) => { failures.push((dest_key.clone(), e)); continue; },
+            }
+        }
+        if let Some(progress) = progress.as_mut() { progress.advance(action.map(|a| (a, dest_key.as_path(), bytes))); }
+    }
+    if !sync.dry_run { observer.on_sync_done(); }
+    let failed: HashSet<PathBuf> = failures.iter().map(|(dest_key, _)| dest_key.clone()).collect();
+
+    let mut acted: HashSet<PathBuf> = HashSet::new();
+    for (m, dest_key, action) in &planned {
+        let Some(action) = action.filter(|_| !failed.contains(dest_key)) else { continue };
+        log.push(match action {
+            "link" => format!("link {} -> {}", dest_key.display(), link_targets[m.path_key()].as_ref().unwrap().display()),
+            "hardlink" => format!("hardlink {} => {}", dest_key.display(), hardlinks[dest_key].display()),
+            "rename" => format!("rename {} -> {}", renames[m.path_key()].path_key().display(), dest_key.display()),
+            _ => format!("{action} {}", dest_key.display()),
+        });
+        stats.count(action, m.record().size);
+        acted.insert(dest_key.clone());
+    }
+
+    // after the copies, so files moved out of stale directories are already gone from them
+    for s in stale.into_iter().rev() {  // children before their parent directories
+        if !sync.dry_run { remove_or_put_aside(s)?; }
+        if let Some(progress) = progress.as_mut() { progress.advance(Some((deletion, s.path_key(), None))); }
+        log.push(format!("{deletion} {}", s.path_key().display()));
+        stats.count(deletion, None);
+    }
+
+    let mut attrs_log: Vec<String> = Vec::new();
+    // a directory's mtime only settles once its children are in place
+    for m in master_entries.entries().iter().rev().filter(|m| matches!(m.record().ty, NodeType::File | NodeType::Dir)) {
+        let dest_key = dest_keys[m.path_key()].clone();
+        let dest = slave.join(&dest_key);
+        if failed.contains(&dest_key) || declined.contains(&dest_key) { continue; }
+        if !sync.dry_run {  // compare against the entry as it is now, since copying into a directory bumps its mtime
+            let current = ManifestEntry::from_rel_path(slave, dest_key.clone(), None, false)?;
+            let compare = if acted.contains(&dest_key) { sync.compare.all_fields() } else { sync.compare };
+            sync_attributes(&dest, m.record(), current.record(), preserve_owner, &compare, false)?;
+        }
+        // only report what was out of date before this sync, not what it just created or disturbed
+        let Some(original) = slave_by_path.get(dest_key.as_path()).filter(|_| !acted.contains(&dest_key)) else { continue };
+        let changes = sync_attributes(&dest, m.record(), original.record(), preserve_owner, &sync.compare, true)?;
+        if !changes.is_empty() {
+            attrs_log.push(format!("attrs {}: {}", dest_key.display(), changes.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")));
+        }
+    }
+    attrs_log.reverse();
+    stats.attrs_fixed = attrs_log.len();
+    log.append(&mut attrs_log);
+
+    if let Some(progress) = progress { progress.finish(); }
+    if !failures.is_empty() { return Err(FilesyncError::CopyFailures(failures.into_iter().map(|(_, e)| e).collect())); }
+    stats.elapsed = started.elapsed();
+    Ok((log, stats))
+}
+
+/// Reconcile `master` and `slave` both ways, against the baseline manifest of their last two-way sync (see [`BASELINE_FILENAME`]).
+/// An entry that changed, appeared or vanished on one side only is carried over to the other (mode and mtime included);
+/// one that changed on both sides, differently, is a conflict, settled by `policy`. Directories only count as changed when they come or go.
+/// Without a baseline, entries on one side only are copied over, and ones that differ are conflicts.
+/// A directory isn't removed while something in it is kept; skipped conflicts are kept out of the new baseline, so they come up again.
+/// Returns one line per action, as `<action> <side written to> <path>`, and one per conflict; with `dry_run` nothing is touched.
+pub fn sync_both(master: &Path, slave: &Path, options: &ScanOptions, compare: &CompareOptions, policy: ConflictPolicy, dry_run: bool) -> Result<Vec<String>> {
+    const SIDES: [&str; 2] = ["master", "slave"];
+    let roots = [master, slave];
+    let baseline = match roots.iter().map(|root| root.join(BASELINE_FILENAME)).find(|path| path.is_file()) {
+        Some(path) => Manifest::deserialize_manifest(&read_tracking_file_into_string(&path)?)?,
+        None => Manifest::default(),
+    };
+    let (master_entries, slave_entries) = (discover_files(master, options)?, discover_files(slave, options)?);
+
+    let mut by_path: BTreeMap<&Path, [Option<&ManifestEntry>; 3]> = BTreeMap::new();  // path -> [master, slave, baseline]
+    for (i, manifest) in [&master_entries, &slave_entries, &baseline].into_iter().enumerate() {
+        for e in manifest.entries() { by_path.entry(e.path_key()).or_default()[i] = Some(e); }
+    }
+    let differs = |a: Option<&ManifestEntry>, b: Option<&ManifestEntry>| -> Result<bool> {
+        Ok(match (a, b) {
+            (Some(a), Some(b)) if a.record().ty == NodeType::Dir && b.record().ty == NodeType::Dir => false,
+            (Some(a), Some(b)) => !content_changes(a, b, compare)?.is_empty(),
+            (a, b) => a.is_some() != b.is_some(),
+        })
+    };
+
+    let mut log: Vec<String> = Vec::new();
+    let mut skipped: HashSet<&Path> = HashSet::new();
+    let mut plan: Vec<(usize, &Path, Option<&ManifestEntry>)> = Vec::new();  // (side copied from, path, its entry there; none: delete it on the other)
+    for (&path, &[m, s, base]) in &by_path {
+        if !differs(m, s)? { continue; }
+        let from = match (differs(base, m)?, differs(base, s)?) {
+            (true, false) => 0,
+            (false, true) => 1,
+            _ => {
+                let winner = match policy {
+                    ConflictPolicy::Master => Some(0),
+                    ConflictPolicy::Slave => Some(1),
+                    ConflictPolicy::Skip => None,
+                    ConflictPolicy::Newer => Some(match (m, s) {
+                        (Some(m), Some(s)) if s.record().mtime_ns > m.record().mtime_ns => 1,
+                        (None, Some(_)) => 1,
+                        _ => 0,
+                    }),
+                };
+                match winner {
+                    Some(side) => log.push(format!("conflict {}: changed on both sides, kept {}'s", path.display(), SIDES[side])),
+                    None => { log.push(format!("conflict {}: changed on both sides, skipped", path.display())); skipped.insert(path); continue; },
+                }
+                winner.unwrap()
+            },
+        };
+        plan.push((from, path, [m, s][from]));
+    }
+
+    // a directory stays where anything in it does: an entry that isn't deleted there, or one that's copied there
+    let deleted: HashSet<(usize, &Path)> = plan.iter().filter(|(_, _, e)| e.is_none()).map(|(from, path, _)| (1 - from, *path)).collect();
+    let copied: HashSet<(usize, &Path)> = plan.iter().filter(|(_, _, e)| e.is_some()).map(|(from, path, _)| (1 - from, *path)).collect();
+    plan.retain(|&(from, path, entry)| entry.is_some() || {
+        let side = 1 - from;
+        !by_path.range::<&Path, _>((std::ops::Bound::Excluded(path), std::ops::Bound::Unbounded))
+            .take_while(|(inner, _)| inner.starts_with(path))
+            .any(|(inner, entries)| (entries[side].is_some() && !deleted.contains(&(side, *inner))) || copied.contains(&(side, *inner)))
+    });
+
+    // parents before their children, except for deletions
+    let (deletions, copies): (Vec<_>, Vec<_>) = plan.into_iter().partition(|(_, _, entry)| entry.is_none());
+    for (from, path, entry) in copies {
+        let (entry, to) = (entry.expect("partitioned"), 1 - from);
+        if entry.record().ty == NodeType::Other { continue; }  // sockets, fifos, ...: nothing to copy
+        let current = by_path[path][to];
+        let action = match (entry.record().ty, current) {
+            (NodeType::Dir, _) => "mkdir",
+            (NodeType::Symlink, _) => "link",
+            (_, None) => "copy",
+            _ => "update",
+        };
+        if !dry_run {
+            let (src, dest) = (roots[from].join(entry.decode_path()), roots[to].join(entry.decode_path()));
+            if let Some(current) = current.filter(|c| c.record().ty != entry.record().ty) {
+                remove_entry(&dest, current.record().ty)?;  // a file where a directory goes, or the other way around
+            }
+            match entry.record().ty {
+                NodeType::Dir => fs::create_dir_all(&dest).context(|| format!("failed to create '{}'", dest.display()))?,
+                NodeType::Symlink => make_symlink(&dest, entry.record().link_target.as_deref().expect("symlink entries record their target"))?,
+                _ => { copy_file(&src, &dest, ReflinkMode::Never, |_| {})?; },
+            }
+            if entry.record().ty == NodeType::File {
+                let copy = ManifestEntry::from_rel_path(roots[to], entry.path_key().to_path_buf(), None, false)?;
+                sync_attributes(&dest, entry.record(), copy.record(), false, compare, false)?;
+            }
+        }
+        log.push(match action {
+            "link" => format!("link {} {} -> {}", SIDES[to], path.display(), entry.record().link_target.as_ref().unwrap().display()),
+            _ => format!("{action} {} {}", SIDES[to], path.display()),
+        });
+    }
+    for (from, path, _) in deletions.into_iter().rev() {  // children before their parent directories
+        let to = 1 - from;
+        let current = by_path[path][to].expect("only entries that are there get deleted");
+        if !dry_run { remove_entry(&roots[to].join(current.decode_path()), current.record().ty)?; }
+        log.push(format!("delete {} {}", SIDES[to], path.display()));
+    }
+
+    if !dry_run {
+        // the new baseline is how master looks now, but with the skipped conflicts still as they were in the old one
+        let mut entries: Vec<ManifestEntry> = Vec::<ManifestEntry>::from(discover_files(master, options)?).into_iter()
+            .filter(|e| !skipped.contains(e.path_key()))
+            .collect();
+        entries.extend(baseline.entries().iter().filter(|e| skipped.contains(e.path_key())).cloned());
+        let mut new_baseline = Manifest::from(entries);
+        new_baseline.sort();
+        let lines = Manifest::serialize(new_baseline)?;
+        for root in roots {
+            let path = tracking_file_path(root, BASELINE_FILENAME)?;
+            fs::write(&path, lines.iter().flat_map(|l| [l.as_str(), "\n"]).collect::<String>())
+                .context(|| format!("failed to write to '{}'", path.display()))?;
+        }
+    }
+    Ok(log)
 }
 
-pub fn read_tracking_file_into_string(tracking_file: &std::path::Path) -> String {
-    std::fs::read_to_string(tracking_file)
-        .unwrap_or_else(|e| panic!("failed to read '{}': {e}", tracking_file.display()))
+/// The text of a tracking file, decompressed first if it's gzipped (told by its content, not its name)
+pub fn read_tracking_file_into_string(tracking_file: &std::path::Path) -> Result<String> {
+    let content = std::fs::read(tracking_file).context(|| format!("failed to read '{}'", tracking_file.display()))?;
+    let content = match gzip::is_gzip(&content) {
+        true => gzip::decompress(&content).context(|| format!("failed to decompress '{}'", tracking_file.display()))?,
+        false => content,
+    };
+    String::from_utf8(content)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        .context(|| format!("failed to read '{}'", tracking_file.display()))
 }
 
 // pub fn read_tracking_file_into_manifest(tracking_file: &std::path::Path) -> Manifest {
 //     Manifest::deserialize_manifests(&read_tracking_file_into_string(&tracking_file))
 // }
 
-pub fn read_tracking_file_into_filepaths(tracking_file: &std::path::Path) -> Vec<String> {
-    let mut strings = read_tracking_file_into_string(&tracking_file).lines()
-        .map(|s| ManifestEntry::deserialize_path_key(&s))
-        .collect::<Vec<_>>();
+pub fn read_tracking_file_into_filepaths(tracking_file: &std::path::Path) -> Result<Vec<String>> {
+    let mut strings = read_tracking_file_into_string(tracking_file)?.lines()
+        .filter(|l| !l.starts_with(MANIFEST_HEADER))
+        .map(ManifestEntry::deserialize_path_key)
+        .collect::<Result<Vec<_>>>()?;
 
     // Escaped strings' order can differ after deserialization. Re-sorting might be necessary.
     strings.sort_unstable();
-    strings
+    Ok(strings)
 }
 
@@ -1,7 +1,92 @@
 use clap::Parser;
-use filesync::{ProgramArgs, run};
+use filesync::{ProgramArgs, SyncObserver, run_with_observer};
+use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-fn main() {
+fn main() -> ExitCode {
     let args = ProgramArgs::parse();
-    println!("{}", run(args));
+    let diffing = args.diff.is_some() || args.check.is_some();
+    let bar = args.progress.then(ProgressBar::default);
+    let result = match &bar {
+        Some(bar) => run_with_observer(args, bar),
+        None => run_with_observer(args, &filesync::NoObserver),
+    };
+    if let Some(bar) = bar { bar.finish(); }
+
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::from(2);  // like diff(1): 1 means "trees differ", 2 means trouble
+        },
+    };
+    if !output.is_empty() { println!("{output}"); }
+
+    // like diff(1): nonzero when the trees differ, so scripts can branch on it
+    if diffing && !output.is_empty() { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+}
+
+
+/// A one-line bar on stderr, redrawn at most every 100ms: `[#####     ]  12.0 MiB / 48.0 MiB  3/10 files`
+#[derive(Default)]
+struct ProgressBar {
+    files: AtomicUsize,
+    files_done: AtomicUsize,
+    bytes: AtomicU64,
+    bytes_done: AtomicU64,
+    last_draw: Mutex<Option<Instant>>,
+}
+
+impl ProgressBar {
+
+    const WIDTH: u64 = 30;
+
+    fn draw(&self, force: bool) {
+        let mut last_draw = self.last_draw.lock().unwrap();
+        if !force && last_draw.is_some_and(|t| t.elapsed() < Duration::from_millis(100)) { return; }
+        *last_draw = Some(Instant::now());
+
+        let (done, total) = (self.bytes_done.load(Ordering::Relaxed), self.bytes.load(Ordering::Relaxed));
+        let filled = (done.min(total) * Self::WIDTH).checked_div(total).unwrap_or(Self::WIDTH);  // nothing to copy: full
+        let bar = format!("{}{}", "#".repeat(filled as usize), " ".repeat((Self::WIDTH - filled) as usize));
+        let mib = |b: u64| b as f64 / (1024.0 * 1024.0);
+        let files = format!("{}/{} files", self.files_done.load(Ordering::Relaxed), self.files.load(Ordering::Relaxed));
+        // progress is best-effort; a closed stderr mustn't abort the sync
+        let _ = write!(std::io::stderr(), "\r[{bar}] {:6.1} MiB / {:.1} MiB  {files}", mib(done), mib(total));
+    }
+
+    /// Draw the final state, and move past the bar's line (if anything was drawn, and it wasn't finished already)
+    fn finish(&self) {
+        if self.last_draw.lock().unwrap().is_none() { return; }
+        self.draw(true);
+        eprintln!();
+        *self.last_draw.lock().unwrap() = None;
+    }
+}
+
+impl SyncObserver for ProgressBar {
+
+    fn on_sync_start(&self, files: usize, bytes: u64) {
+        self.files.store(files, Ordering::Relaxed);
+        self.bytes.store(bytes, Ordering::Relaxed);
+        if files > 0 { self.draw(true); }
+    }
+
+    fn on_bytes(&self, copied: u64) {
+        self.bytes_done.fetch_add(copied, Ordering::Relaxed);
+        self.draw(false);
+    }
+
+    fn on_file_done(&self, _path: &Path) {
+        self.files_done.fetch_add(1, Ordering::Relaxed);
+        self.draw(false);
+    }
+
+    fn on_sync_done(&self) {
+        self.finish();  // before the summary's printed
+    }
 }
@@ -1,8 +1,13 @@
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Serialize};
 
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fmt;
 use std::fs;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[cfg(unix)]
 use std::os::unix::fs::MetadataExt;
@@ -10,6 +15,9 @@ use base64::Engine as _;
 use os_str_bytes::OsStrBytes;
 use rayon::prelude::*;
 use unicode_width::UnicodeWidthStr;
+use crate::error::{FilesyncError, IoContext, Result};
+use crate::hashing::HashAlgo;
+use crate::unicode;
 
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -21,7 +29,11 @@ pub enum NodeType {
     Other,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Display for NodeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", format!("{self:?}").to_lowercase()) }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileMeta {
     // lossless path bytes, base64 (relative path bytes as seen by the OS)
     pub encoded_path_b64: String,
@@ -39,11 +51,24 @@ pub struct FileMeta {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mode: Option<u32>,
 
+    // Optional (Unix): owner user and group ids; only applied by sync with --preserve-owner.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+
     // Only present when ty == Symlink.
     // Store as JSON string (UTF-8). If you later need lossless non-UTF8 targets on Unix,
     // add link_target_b64 as a parallel field.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub link_target: Option<PathBuf>,
+
+    // Only present for regular files, and only when hashing was asked for (lowercase hex).
+    // At most one of these is set; the field name records which algorithm made the digest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_blake3: Option<String>,
 }
 
 
@@ -58,6 +83,17 @@ pub struct ManifestEntry {
 #[derive(Debug, Clone, Default)]
 pub struct Manifest(Vec<ManifestEntry>);
 
+/// What to do when merged manifests share a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Refuse to merge
+    Error,
+    /// Keep the entry from the earliest manifest
+    KeepFirst,
+    /// Keep the entry with the latest modification time
+    KeepNewest,
+}
+
 
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -66,15 +102,21 @@ pub struct Manifest(Vec<ManifestEntry>);
 
 impl ManifestEntry {
 
-    pub fn from_rel_path(root: &Path, rel: PathBuf) -> Self {
+    /// Stat `root/rel`. With `follow_links`, a symlink is recorded as whatever it points to; otherwise as the link itself.
+    /// With `hash`, regular files also get their content hashed.
+    pub fn from_rel_path(root: &Path, rel: PathBuf, hash: Option<HashAlgo>, follow_links: bool) -> Result<Self> {
         let full_path = root.join(&rel);
 
-        let md = fs::symlink_metadata(&full_path).unwrap_or_else(|e| panic!("symlink_metadata failed for '{}': {e}", full_path.display()));
+        let md = if follow_links {
+            fs::metadata(&full_path).context(|| format!("metadata failed for '{}'", full_path.display()))?
+        } else {
+            fs::symlink_metadata(&full_path).context(|| format!("symlink_metadata failed for '{}'", full_path.display()))?
+        };
 
         #[cfg(unix)]
-        let mode = Some(md.mode() & 0o7777);
+        let (mode, uid, gid) = (Some(md.mode() & 0o7777), Some(md.uid()), Some(md.gid()));
         #[cfg(not(unix))]
-        let mode = None;
+        let (mode, uid, gid) = (None, None, None);
 
         let ft = md.file_type();
         let ty =
@@ -83,55 +125,95 @@ impl ManifestEntry {
             else if ft.is_symlink() { NodeType::Symlink }
             else { NodeType::Other };
 
-        fn append_slash_to_path(pb: &PathBuf) -> PathBuf {
-            let mut path = PathBuf::from("/some/directory");
-            path.push("");  // adds "/". Don't actually add "/", because it'll break. It's built to also support Windows, which uses '\'
-            path
-        }
+        let link_target = if ty == NodeType::Symlink {
+            Some(fs::read_link(&full_path).context(|| format!("read_link failed for '{}'", full_path.display()))?)
+        } else { None };
 
-        ManifestEntry {
+        let mut entry = ManifestEntry {
             path_key: if ty == NodeType::Dir { rel.join("") } else { rel.clone() },  // trailing slash for dirs
             record: FileMeta {
                 encoded_path_b64: base64::engine::general_purpose::STANDARD_NO_PAD.encode(&*rel.to_raw_bytes()),
                 ty,
-                size: (ty == NodeType::File).then(|| md.len()),
+                size: (ty == NodeType::File).then_some(md.len()),
                 mtime_ns: mtime_ns(&md),
                 mode,
-                link_target: (ty == NodeType::Symlink).then(|| fs::read_link(&full_path).unwrap_or_else(|e| panic!("read_link failed for '{}': {e}", full_path.display()))),
+                uid,
+                gid,
+                link_target,
+                hash_sha256: None,
+                hash_blake3: None,
             },
+        };
+        if let Some(algo) = hash.filter(|_| ty == NodeType::File) {
+            entry.record.set_content_hash(algo, algo.hash_file(&full_path)?);
         }
+        Ok(entry)
     }
 
-    pub fn deserialize_entry(line: &str) -> Self {
+    pub fn deserialize_entry(line: &str) -> Result<Self> {
         let mut de = serde_json::Deserializer::from_str(line);
 
         let path_key: PathBuf = PathBuf::from(String::deserialize(&mut de)
-            .unwrap_or_else(|e| panic!("invalid path json: {e}; line={line:?}")));
+            .map_err(|_| FilesyncError::BadTrackingLine { line: line.to_string(), reason: "invalid path json" })?);
 
-        let record = FileMeta::deserialize(&mut de)
-            .unwrap_or_else(|e| panic!("invalid record json: {e}; line={line:?}"));
+        let record = FileMeta::deserialize(&mut de).map_err(|source| match source.is_eof() {
+            true => FilesyncError::BadTrackingLine { line: line.to_string(), reason: "tracking line has no metadata (written with --names-only?)" },
+            false => FilesyncError::Serde { context: format!("invalid record json; line={line:?}"), source },
+        })?;
 
-        if !de.end().is_ok() { panic!("tracking line has trailing junk; line={line:?}"); }
+        if de.end().is_err() { return Err(FilesyncError::BadTrackingLine { line: line.to_string(), reason: "tracking line has trailing junk" }); }
+        if decode_path_b64(&record.encoded_path_b64).is_none() {  // so that decode_path() can't fail later on
+            return Err(FilesyncError::BadTrackingLine { line: line.to_string(), reason: "invalid encoded_path_b64" });
+        }
 
-        ManifestEntry { path_key, record }
+        Ok(ManifestEntry { path_key, record })
     }
 
-    fn serialize_entry(&self) -> (String, String) {
-        fn serialize<T>(some_str: &T) -> String where T: ?Sized + Serialize,{
-            serde_json::to_string(&some_str).unwrap_or_else(|e| panic!("failed to serialize: {e}"))
+    fn serialize_entry(&self) -> Result<(String, String)> {
+        fn serialize<T>(value: &T, path: &Path) -> Result<String> where T: ?Sized + Serialize, {
+            serde_json::to_string(value)
+                .map_err(|source| FilesyncError::Serde { context: format!("failed to serialize '{}'", path.display()), source })
         }
 
-        (serialize(&self.path_key.clone()), serialize(&self.record))
+        Ok((serialize(&self.path_key, &self.path_key)?, serialize(&self.record, &self.path_key)?))
     }
 
 
     /// Deserialize only the leading JSON string (path_key) from a line (even if there's nothing after)
-    pub fn deserialize_path_key(line: &str) -> String {
+    pub fn deserialize_path_key(line: &str) -> Result<String> {
         let mut it = serde_json::Deserializer::from_str(line).into_iter::<String>();
 
         it.next()
-            .unwrap_or_else(|| panic!("tracking line missing path key; line={line:?}"))
-            .unwrap_or_else(|e| panic!("invalid path json: {e}; line={line:?}"))
+            .ok_or_else(|| FilesyncError::BadTrackingLine { line: line.to_string(), reason: "tracking line missing path key" })?
+            .map_err(|_| FilesyncError::BadTrackingLine { line: line.to_string(), reason: "invalid path json" })
+    }
+
+    pub fn path_key(&self) -> &Path { &self.path_key }
+
+    /// The byte-exact relative path (no trailing slash for dirs), decoded from `encoded_path_b64`.
+    /// Unlike `path_key`, this survives a trip through a tracking file for names that aren't valid UTF-8.
+    pub fn decode_path(&self) -> PathBuf {
+        decode_path_b64(&self.record.encoded_path_b64).expect("validated when the entry was created")
+    }
+
+    pub fn record(&self) -> &FileMeta { &self.record }
+
+    /// How `other`'s record differs from this one's (for two entries of the same path, in two trees or two scans), or `None` if it doesn't.
+    /// The comparison is exact; tolerances and field masks are up to the caller (see [`CompareOptions::significant`])
+    pub fn diff(&self, other: &ManifestEntry) -> Option<FieldDiff> {
+        let changes = diff_meta(&self.record, &other.record);
+        if changes.is_empty() { return None; }
+        let has = |field: &str| changes.iter().any(|c| c.field == field);
+        Some(FieldDiff {
+            ty: has("type"),
+            size: has("size"),
+            mtime_ns: has("mtime_ns"),
+            mode: has("mode"),
+            owner: has("uid") || has("gid"),
+            link_target: has("link_target"),
+            hash: has("hash_sha256") || has("hash_blake3"),
+            changes,
+        })
     }
 }
 
@@ -139,14 +221,58 @@ impl ManifestEntry {
 //////////////////////////////////////////MANIFEST//////////////////////////////////////////////////
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// `key<spaces>record` lines, with every record starting at the same column (at least two spaces past the widest key)
+fn align(pairs: Vec<(String, String)>) -> Vec<String> {
+    fn get_str_visual_width(s: &str) -> usize { UnicodeWidthStr::width(s) }
+
+    let pad_to = pairs.par_iter()
+        .map(|(k, _)| get_str_visual_width(k))  // align visually, by width of characters, not byte-length
+        .max()
+        .unwrap_or(0)
+        + 2;  // minimum 2 spaces in-between
+
+    pairs.into_par_iter()
+        .map(|(k, r)| [
+            k.as_str(),
+            &" ".repeat(pad_to.saturating_sub(get_str_visual_width(&k))),
+            r.as_str()
+        ].concat())
+        .collect()
+}
+
+/// How a tracking file's first line starts, followed by ` v<version>` and (for hashed ones) ` algo=<name>`.
+/// No other line can start with `#`, since they all start with a JSON string.
+pub const MANIFEST_HEADER: &str = "# filesync-manifest";
+/// The format [`Manifest::serialize`] writes: 1 was path-only lines (still written by `--names-only`, without a header),
+/// 2 added each entry's metadata
+pub const MANIFEST_VERSION: u32 = 2;
+
+/// Validate the ` v2 algo=blake3` that follows [`MANIFEST_HEADER`] on a tracking file's `line`
+fn check_manifest_header(line: &str, fields: &str) -> Result<()> {
+    let bad = |reason| FilesyncError::BadTrackingLine { line: line.to_string(), reason };
+    let mut fields = fields.split_whitespace();
+    let version: u32 = fields.next().and_then(|v| v.strip_prefix('v')).and_then(|v| v.parse().ok())
+        .ok_or_else(|| bad("manifest header has no version"))?;
+    match version {
+        1 => return Err(bad("manifest header says v1, which only lists paths; re-run --track to write v2")),
+        MANIFEST_VERSION => {},
+        _ => return Err(bad("manifest was written by a newer filesync (unsupported version)")),
+    }
+    for field in fields {
+        match field.split_once('=') {
+            Some(("algo", name)) if [HashAlgo::Sha256, HashAlgo::Blake3].iter().any(|a| a.name() == name) => {},
+            Some(("algo", _)) => return Err(bad("manifest header names an unknown hash algorithm")),
+            _ => {},  // unknown fields, from a compatible newer writer, are fine
+        }
+    }
+    Ok(())
+}
+
 // .collect()
 impl FromIterator<ManifestEntry> for Manifest { fn from_iter<I: IntoIterator<Item = ManifestEntry>>(iter: I) -> Self { Manifest(iter.into_iter().collect()) } }
 
-// rayon .par_iter() trait
-trait FromParallelIterator<T>: Sized { fn from_par_iter<I>(par_iter: I) -> Self where I: ParallelIterator<Item = T>; }
-
 // rayon .collect()
-impl FromParallelIterator<ManifestEntry> for Manifest { fn from_par_iter<I>(par_iter: I) -> Self where I: rayon::iter::ParallelIterator<Item = ManifestEntry>, { Manifest(par_iter.collect()) } }
+impl FromParallelIterator<ManifestEntry> for Manifest { fn from_par_iter<I>(par_iter: I) -> Self where I: IntoParallelIterator<Item = ManifestEntry>, { Manifest(par_iter.into_par_iter().collect()) } }
 
 // into()
 impl From<Vec<ManifestEntry>> for Manifest { fn from(v: Vec<ManifestEntry>) -> Self { Manifest(v) } }
@@ -156,51 +282,282 @@ impl From<Manifest> for Vec<ManifestEntry> { fn from(m: Manifest) -> Self { m.0
 
 impl Manifest {
 
-    /// Render entries as aligned lines: `<path_key_json><spaces><record_json>\n`
-    /// where `record_json` starts at the same column for all lines.
-    pub fn serialize(manifest: Manifest) -> Vec<String> {
+    /// Render entries, sorted by path, as aligned lines: `<path_key_json><spaces><record_json>\n`
+    /// where `record_json` starts at the same column for all lines, after a [`MANIFEST_HEADER`] line
+    /// (with `algo=<name>` when the entries are hashed).
+    pub fn serialize(mut manifest: Manifest) -> Result<Vec<String>> {
+        let header = manifest.header();
+        manifest.sort();  // the order `serialize_to` writes in too
+
         // Parallel map: ManifestEntry -> (key, record)
         let pairs: Vec<(String, String)> = Vec::<ManifestEntry>::from(manifest).par_iter()
             .map(ManifestEntry::serialize_entry)
-            .collect();
+            .collect::<Result<_>>()?;
 
-        fn get_str_visual_width(s: &String) -> usize { UnicodeWidthStr::width(s.as_str()) }
+        let mut lines = align(pairs);
+        lines.insert(0, header);
+        Ok(lines)
+    }
 
-        let pad_to = pairs.par_iter()
-            .map(|(k, _)| get_str_visual_width(k))  // align visually, by width of characters, not byte-length
-            .max()
-            .unwrap_or(0)
-            + 2;  // minimum 2 spaces in-between
+    /// Like [`Manifest::serialize`], but written to `writer` a chunk of entries at a time, so that only one chunk's lines
+    /// are ever held in memory, however big the manifest. Entries are written sorted by path, like [`Manifest::serialize`]
+    /// does, and are aligned within their chunk rather than across the whole file
+    pub fn serialize_to(&self, writer: impl Write) -> io::Result<()> {
+        self.serialize_chunked(writer, Self::STREAM_CHUNK)
+    }
 
-        // Build final string (sequential join; avoids contention)
-        let mut lines: Vec<String> = pairs.into_par_iter()
-            .map(|(k, r)| [
-                k.as_str(),
-                &" ".repeat(pad_to.saturating_sub(get_str_visual_width(&k))),
-                r.as_str()
-            ].concat())
-            .collect();
+    /// How many entries [`Manifest::serialize_to`] renders at once
+    pub const STREAM_CHUNK: usize = 4096;
+
+    pub(crate) fn serialize_chunked(&self, mut writer: impl Write, chunk: usize) -> io::Result<()> {
+        writeln!(writer, "{}", self.header())?;
+        let mut sorted: Vec<&ManifestEntry> = self.0.iter().collect();
+        sorted.par_sort_unstable_by(|a, b| a.path_key.cmp(&b.path_key));
+        for entries in sorted.chunks(chunk) {
+            let pairs = entries.par_iter()
+                .map(|entry| entry.serialize_entry())
+                .collect::<Result<Vec<_>>>()
+                .map_err(io::Error::other)?;
+            for line in align(pairs) { writeln!(writer, "{line}")?; }
+        }
+        Ok(())
+    }
 
-        lines.par_sort_unstable();
-        lines
+    /// The first line of a serialized manifest: [`MANIFEST_HEADER`], the version, and `algo=<name>` when the entries are hashed
+    fn header(&self) -> String {
+        match self.0.iter().find_map(|e| e.record.content_hash()).map(|(algo, _)| algo) {
+            Some(algo) => format!("{MANIFEST_HEADER} v{MANIFEST_VERSION} algo={}", algo.name()),
+            None => format!("{MANIFEST_HEADER} v{MANIFEST_VERSION}"),
+        }
     }
 
 
-    pub fn deserialize_manifest(content: &str) -> Manifest {
+    /// Render only the entries' path keys, as sorted JSON strings
+    pub fn serialize_names(manifest: Manifest) -> Result<Vec<String>> {
+        let mut lines: Vec<String> = Vec::<ManifestEntry>::from(manifest).par_iter()
+            .map(|e| e.serialize_entry().map(|(key, _)| key))
+            .collect::<Result<_>>()?;
+        lines.par_sort_unstable();
+        Ok(lines)
+    }
+
+    /// Read what [`Manifest::serialize`] wrote. A missing header means an older file, whose lines are read the same way;
+    /// one from a newer version than this is rejected, as are path-only files (see [`Manifest::serialize_names`]).
+    pub fn deserialize_manifest(content: &str) -> Result<Manifest> {
+        let (first, rest) = content.split_once('\n').unwrap_or((content, ""));
+        let content = match first.strip_prefix(MANIFEST_HEADER) {
+            Some(fields) => { check_manifest_header(first, fields)?; rest },
+            None => content,
+        };
         let mut entries: Manifest = content.par_lines()
             .filter(|l| !l.is_empty())
             .map(ManifestEntry::deserialize_entry)
-            .collect::<Vec<ManifestEntry>>()
+            .collect::<Result<Vec<ManifestEntry>>>()?
             .into();
 
         entries.sort();
-        entries
+        Ok(entries)
     }
     
     pub fn sort(&mut self) {
         self.0.par_sort_unstable_by(|a, b| a.path_key.cmp(&b.path_key));
     }
 
+    pub fn entries(&self) -> &[ManifestEntry] { &self.0 }
+
+    pub fn len(&self) -> usize { self.0.len() }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Combine manifests into one, sorted by path. Also returns the paths found in more than one manifest.
+    pub fn merge(manifests: Vec<Manifest>, policy: DuplicatePolicy) -> Result<(Manifest, Vec<PathBuf>)> {
+        let mut merged: BTreeMap<PathBuf, ManifestEntry> = BTreeMap::new();
+        let mut duplicates: Vec<PathBuf> = Vec::new();
+
+        for entry in manifests.into_iter().flat_map(Vec::<ManifestEntry>::from) {
+            let Some(kept) = merged.get_mut(&entry.path_key) else {
+                merged.insert(entry.path_key.clone(), entry);
+                continue;
+            };
+
+            duplicates.push(entry.path_key.clone());
+            match policy {
+                DuplicatePolicy::Error => return Err(FilesyncError::DuplicatePath(entry.path_key)),
+                DuplicatePolicy::KeepFirst => {},
+                DuplicatePolicy::KeepNewest => if entry.record.mtime_ns > kept.record.mtime_ns { *kept = entry },
+            }
+        }
+
+        Ok((merged.into_values().collect(), duplicates))
+    }
+
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////FILE META//////////////////////////////////////////////////
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl FileMeta {
+
+    /// The recorded content digest (if any), along with the algorithm that made it
+    pub fn content_hash(&self) -> Option<(HashAlgo, &str)> {
+        self.hash_sha256.as_deref().map(|h| (HashAlgo::Sha256, h))
+            .or_else(|| self.hash_blake3.as_deref().map(|h| (HashAlgo::Blake3, h)))
+    }
+
+    fn set_content_hash(&mut self, algo: HashAlgo, digest: String) {
+        match algo {
+            HashAlgo::Sha256 => self.hash_sha256 = Some(digest),
+            HashAlgo::Blake3 => self.hash_blake3 = Some(digest),
+        }
+    }
+}
+
+/// A single metadata field that differs between two records of the same path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+/// Which fields of two records differ (see [`ManifestEntry::diff`])
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub ty: bool,
+    pub size: bool,
+    pub mtime_ns: bool,
+    pub mode: bool,
+    /// uid or gid; only compared when both records have them (older tracking files don't)
+    pub owner: bool,
+    pub link_target: bool,
+    /// The content digest; only compared when both records were hashed with the same algorithm
+    pub hash: bool,
+    /// Every differing field with its two values, in the order above
+    pub changes: Vec<FieldChange>,
+}
+
+// "size 100→120"
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{} {}→{}", self.field, self.old, self.new) }
+}
+
+/// How leniently records are compared when deciding whether two entries differ.
+///
+/// `ignore_mtime`, `ignore_mode` and `size_only` mask fields out of every comparison (see [`CompareOptions::compares`]):
+/// a diff doesn't report them, and a sync neither copies over nor fixes up entries that only differ in them.
+/// `size_only` implies the other two, and also leaves out content digests and owners; with `ignore_mtime` alone, digests
+/// (from `--hash`) still catch same-size content changes, which is what makes it safe after a copy that lost the mtimes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompareOptions {
+    /// Treat mtimes as equal when they're less than this far apart (some filesystems, like FAT, store them coarsely)
+    pub mtime_tolerance: Duration,
+    /// Don't compare mtimes at all
+    pub ignore_mtime: bool,
+    /// Don't compare permission bits
+    pub ignore_mode: bool,
+    /// Only compare types and sizes (and symlink targets, which are all a link holds)
+    pub size_only: bool,
+    /// Match paths regardless of case, as case-insensitive filesystems (macOS, exFAT, ...) do
+    pub ignore_case: bool,
+    /// Match paths by their NFC form, so names stored decomposed (as on macOS) match their composed spelling
+    pub normalize_unicode: bool,
+}
+
+impl CompareOptions {
+
+    /// Whether paths are matched by something other than their exact bytes
+    pub fn folds_paths(&self) -> bool {
+        self.ignore_case || self.normalize_unicode
+    }
+
+    /// What `path` is matched by: itself, or with `normalize_unicode`, its NFC form, and with `ignore_case`, lowercased
+    /// (names that aren't UTF-8 stay as they are). Only matching uses this; the filesystem still gets the exact bytes
+    pub fn match_key<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        let Some(s) = path.to_str().filter(|_| self.folds_paths()) else { return Cow::Borrowed(path) };
+        let normalized = if self.normalize_unicode { unicode::nfc(s) } else { Cow::Borrowed(s) };
+        match (self.ignore_case, normalized) {
+            (true, s) => Cow::Owned(PathBuf::from(s.to_lowercase())),
+            (false, Cow::Owned(s)) => Cow::Owned(PathBuf::from(s)),
+            (false, Cow::Borrowed(_)) => Cow::Borrowed(path),
+        }
+    }
+
+    /// With `ignore_case` or `normalize_unicode`, fail on two entries that would match as the same path, since they can't
+    /// both be matched (or synced)
+    pub fn check_collisions<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) -> Result<()> {
+        if !self.folds_paths() { return Ok(()); }
+        let mut seen: HashMap<Cow<Path>, &Path> = HashMap::new();
+        for path in paths {
+            if let Some(first) = seen.insert(self.match_key(path), path) {
+                let unicode_only = CompareOptions { ignore_case: false, ..*self }.match_key(first) == CompareOptions { ignore_case: false, ..*self }.match_key(path);
+                let option = if self.normalize_unicode && unicode_only { "--normalize-unicode" } else { "--ignore-case" };
+                return Err(FilesyncError::PathCollision { first: first.to_path_buf(), second: path.to_path_buf(), option });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn mtimes_match(&self, a: i128, b: i128) -> bool {
+        a == b || a.abs_diff(b) < self.mtime_tolerance.as_nanos()
+    }
+
+    /// Whether a change to `field` (of [`FileMeta`]) counts at all
+    pub fn compares(&self, field: &str) -> bool {
+        match field {
+            "type" | "size" | "link_target" => true,
+            _ if self.size_only => false,
+            "mtime_ns" => !self.ignore_mtime,
+            "mode" => !self.ignore_mode,
+            _ => true,
+        }
+    }
+
+    /// These options without the field masks, for entries that a sync just created (which should get all of master's attributes)
+    pub fn all_fields(self) -> Self {
+        CompareOptions { ignore_mtime: false, ignore_mode: false, size_only: false, ..self }
+    }
+
+    /// Drop the changes that these options don't count as differences: masked fields, and mtimes within the tolerance
+    pub fn significant(&self, changes: Vec<FieldChange>, old: &FileMeta, new: &FileMeta) -> Vec<FieldChange> {
+        changes.into_iter()
+            .filter(|c| self.compares(c.field))
+            .filter(|c| c.field != "mtime_ns" || !self.mtimes_match(old.mtime_ns, new.mtime_ns))
+            .collect()
+    }
+}
+
+/// Field-by-field comparison of two records (of the same path). Empty when nothing differs.
+/// Hashes are only compared when both records have one.
+pub fn diff_meta(old: &FileMeta, new: &FileMeta) -> Vec<FieldChange> {
+    fn show<T>(value: &Option<T>, render: impl Fn(&T) -> String) -> String { value.as_ref().map_or("-".to_string(), render) }
+    fn both_or_neither<T: ToString>(field: &'static str, old: &Option<T>, new: &Option<T>) -> (&'static str, String, String) {
+        match (old, new) {
+            (Some(o), Some(n)) => (field, o.to_string(), n.to_string()),
+            _ => (field, String::new(), String::new()),
+        }
+    }
+
+    let candidates = [
+        ("type", old.ty.to_string(), new.ty.to_string()),
+        ("size", show(&old.size, u64::to_string), show(&new.size, u64::to_string)),
+        ("mtime_ns", old.mtime_ns.to_string(), new.mtime_ns.to_string()),
+        ("mode", show(&old.mode, |m| format!("{m:o}")), show(&new.mode, |m| format!("{m:o}"))),
+        // older tracking files don't record ownership
+        both_or_neither("uid", &old.uid, &new.uid),
+        both_or_neither("gid", &old.gid, &new.gid),
+        ("link_target", show(&old.link_target, |t| t.display().to_string()), show(&new.link_target, |t| t.display().to_string())),
+        // only comparable when both sides were hashed (with the same algorithm)
+        both_or_neither("hash_sha256", &old.hash_sha256, &new.hash_sha256),
+        both_or_neither("hash_blake3", &old.hash_blake3, &new.hash_blake3),
+    ];
+
+    candidates.into_iter()
+        .filter(|(_, o, n)| o != n)
+        .map(|(field, old, new)| FieldChange { field, old, new })
+        .collect()
 }
 
 
@@ -210,6 +567,12 @@ impl Manifest {
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
 
+// the bytes were made by `to_raw_bytes`, so they're a valid encoding (on Unix, every byte string is)
+fn decode_path_b64(encoded: &str) -> Option<PathBuf> {
+    let bytes = base64::engine::general_purpose::STANDARD_NO_PAD.decode(encoded).ok()?;
+    Some(PathBuf::from(OsStr::assert_from_raw_bytes(bytes).into_owned()))
+}
+
 fn mtime_ns(md: &fs::Metadata) -> i128 {
     let t = md.modified().unwrap_or(SystemTime::UNIX_EPOCH);
     match t.duration_since(UNIX_EPOCH) {
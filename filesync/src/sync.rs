@@ -0,0 +1,614 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use crate::error::{FilesyncError, IoContext, Result};
+use crate::hashing::HashAlgo;
+use crate::structures::{diff_meta, CompareOptions, FieldChange, FileMeta, ManifestEntry, NodeType};
+
+
+/// Settings for syncing a slave directory to its master
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// Where master paths end up in the slave
+    pub rename_map: RenameMap,
+    /// Only reapply mode/mtime to entries whose content already matches
+    pub attrs_only: bool,
+    /// Remove slave entries that aren't in master
+    pub delete: bool,
+    /// Also apply master's uid/gid (needs root)
+    pub preserve_owner: bool,
+    /// Report what would be done, without touching the slave
+    pub dry_run: bool,
+    /// Emit NDJSON progress events (on stderr, from the CLI)
+    pub progress_json: bool,
+    /// How entries are compared to decide whether they need copying or fixing up
+    pub compare: CompareOptions,
+    /// How many files to copy at once (`None` or 0: one per CPU)
+    pub jobs: Option<usize>,
+    /// Most bytes per second to copy, over all the copies together
+    pub bwlimit: Option<u64>,
+    /// Check every copy against its source with this digest
+    pub verify: Option<HashAlgo>,
+    /// Whether copies share their source's data blocks (on filesystems that can do that)
+    pub reflink: ReflinkMode,
+    /// Hardlink files to others of the same content in the slave, instead of copying them (needs hashing)
+    pub hardlink_dupes: bool,
+    /// Move slave entries here (at the same relative path) instead of overwriting or deleting them
+    pub backup_dir: Option<PathBuf>,
+    /// Move slave entries that aren't in master into a timestamped folder under the slave's trash dir, instead of removing them
+    pub trash: bool,
+    /// Have the observer confirm every overwrite and deletion (see [`SyncObserver::confirm`]) before anything is done
+    pub interactive: bool,
+    /// What becomes of symlinks with absolute targets
+    pub symlink_mode: SymlinkMode,
+}
+
+
+/// Which side wins when a two-way sync finds an entry that changed on both sides since the last one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ConflictPolicy {
+    /// The side whose entry has the later mtime (an entry that's still there wins over a deletion)
+    Newer,
+    Master,
+    Slave,
+    /// Leave both sides alone; the conflict is reported again until it's resolved by hand
+    #[default]
+    Skip,
+}
+
+/// Whether a copy should be a reflink (a copy-on-write clone, which shares the source's blocks until either is changed).
+/// Only some filesystems (btrfs, XFS, ...) can make them, and only within themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ReflinkMode {
+    /// Try a reflink, and copy the bytes if it can't be made
+    Auto,
+    /// Fail when a reflink can't be made
+    Always,
+    /// Always copy the bytes
+    #[default]
+    Never,
+}
+
+/// How a sync recreates symlinks with absolute targets, which seldom mean the same thing on another host (relative ones are always kept)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SymlinkMode {
+    /// Recreate every target verbatim
+    #[default]
+    Copy,
+    /// Rewrite absolute targets inside the master tree as relative ones, so they point into the slave; others are kept verbatim
+    Relativize,
+    /// Like `relativize`, but links to absolute targets outside the master tree are skipped, with a warning
+    SkipAbsolute,
+}
+
+impl SymlinkMode {
+
+    /// The target to give the slave's copy of `master/link`, whose own target is `target`; `None` if it's to be skipped
+    pub fn target_for(self, master: &Path, link: &Path, target: &Path) -> Option<PathBuf> {
+        if self == SymlinkMode::Copy || !target.is_absolute() { return Some(target.to_path_buf()); }
+        let roots = [std::path::absolute(master).ok(), fs::canonicalize(master).ok()];  // the target may name master either way
+        match roots.iter().flatten().find_map(|root| target.strip_prefix(root).ok()) {
+            Some(inside) => {
+                let mut relative: PathBuf = std::iter::repeat_n("..", link.parent().map_or(0, |p| p.components().count())).collect();
+                if !inside.as_os_str().is_empty() { relative.push(inside); }
+                Some(if relative.as_os_str().is_empty() { PathBuf::from(".") } else { relative })
+            },
+            None if self == SymlinkMode::SkipAbsolute => None,
+            None => Some(target.to_path_buf()),
+        }
+    }
+}
+
+
+/// Path-prefix substitutions applied to master paths to get their destination in the slave.
+/// Parsed from lines of `from_prefix=to_prefix`; blank lines and `#` comments are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct RenameMap(Vec<(PathBuf, PathBuf)>);
+
+impl RenameMap {
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let rules = content.lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| {
+                let (from, to) = l.split_once('=').ok_or_else(|| FilesyncError::BadRenameRule(l.to_string()))?;
+                Ok((PathBuf::from(from.trim()), PathBuf::from(to.trim())))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(RenameMap(rules))
+    }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    pub fn read(rules_file: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(rules_file).context(|| format!("failed to read '{}'", rules_file.display()))?)
+    }
+
+    /// Destination of `path`. Prefixes match whole components (`old` matches `old/x`, not `older/x`);
+    /// the longest matching prefix wins, and paths matching no rule are returned unchanged.
+    pub fn apply(&self, path: &Path) -> PathBuf {
+        let is_dir = path.as_os_str().to_string_lossy().ends_with('/');
+        self.0.iter()
+            .filter_map(|(from, to)| path.strip_prefix(from).ok().map(|rest| match rest.as_os_str().is_empty() && !is_dir {
+                true => (from, to.clone()),  // a file mapped by its whole path; joining "" would give it a dir's trailing slash
+                false => (from, to.join(rest)),
+            }))
+            .max_by_key(|(from, _)| from.components().count())
+            .map_or_else(|| path.to_path_buf(), |(_, dest)| dest)
+    }
+
+    /// Map every path to its destination (returned as destination -> source).
+    /// Fails if two different paths end up at the same destination.
+    pub fn destinations<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) -> Result<BTreeMap<PathBuf, PathBuf>> {
+        let mut out: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+        for src in paths {
+            let dest = self.apply(src);
+            if let Some(other) = out.get(&dest) {
+                return Err(FilesyncError::RenameConflict { first: other.clone(), second: src.to_path_buf(), dest });
+            }
+            out.insert(dest, src.to_path_buf());
+        }
+        Ok(out)
+    }
+}
+
+
+/// Make `dest` (currently described by `current`) take the mode and mtime recorded in `wanted`, and its uid/gid too
+/// with `preserve_owner`, leaving its content alone. An mtime within `compare`'s tolerance is left as it is.
+/// Returns the fields that were changed (or would be, with `dry_run`).
+pub fn sync_attributes(dest: &Path, wanted: &FileMeta, current: &FileMeta, preserve_owner: bool, compare: &CompareOptions, dry_run: bool) -> Result<Vec<FieldChange>> {
+    let changes: Vec<FieldChange> = diff_meta(current, wanted).into_iter()
+        .filter(|c| matches!(c.field, "mode" | "mtime_ns") || (preserve_owner && matches!(c.field, "uid" | "gid")))
+        .collect();
+    let changes = compare.significant(changes, current, wanted);
+    if dry_run || changes.is_empty() { return Ok(changes); }
+
+    let changed = |field: &str| changes.iter().any(|c| c.field == field);
+    // before the mode: changing the owner can clear setuid/setgid bits
+    #[cfg(unix)]
+    if changed("uid") || changed("gid") {
+        std::os::unix::fs::chown(dest, wanted.uid, wanted.gid)
+            .context(|| format!("failed to change owner of '{}'", dest.display()))?;
+    }
+
+    #[cfg(unix)]
+    if let Some(mode) = wanted.mode.filter(|_| changed("mode")) {
+        fs::set_permissions(dest, fs::Permissions::from_mode(mode))
+            .context(|| format!("failed to set mode of '{}'", dest.display()))?;
+    }
+
+    if changed("mtime_ns") && wanted.mtime_ns >= 0 {
+        set_mtime(dest, wanted.mtime_ns).context(|| format!("failed to set mtime of '{}'", dest.display()))?;
+    }
+
+    Ok(changes)
+}
+
+/// Set `path`'s mtime by its path, so it works whatever its mode (a file master keeps at 0o200 can't be opened to do it)
+#[cfg(unix)]
+fn set_mtime(path: &Path, mtime_ns: i128) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    let times = [
+        libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_OMIT },  // atime stays
+        libc::timespec { tv_sec: (mtime_ns / 1_000_000_000) as libc::time_t, tv_nsec: (mtime_ns % 1_000_000_000) as _ },
+    ];
+    // SAFETY: `c_path` is NUL-terminated and `times` holds the two entries utimensat reads; both outlive the call
+    match unsafe { libc::utimensat(libc::AT_FDCWD, c_path.as_ptr(), times.as_ptr(), 0) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_mtime(path: &Path, mtime_ns: i128) -> io::Result<()> {
+    fs::File::open(path).and_then(|f| f.set_modified(UNIX_EPOCH + Duration::from_nanos(mtime_ns as u64)))
+}
+
+
+/// Whether this process may give files away to other users. If not, warns (once per call) that ownership won't be preserved.
+pub fn can_change_owner() -> bool {
+    #[cfg(unix)]
+    let privileged = unsafe { libc::geteuid() } == 0;
+    #[cfg(not(unix))]
+    let privileged = false;
+
+    if !privileged { eprintln!("warning: --preserve-owner needs root; leaving ownership unchanged"); }
+    privileged
+}
+
+
+/// Suffix of the file a copy is written to before it's moved over its destination
+const TMP_SUFFIX: &str = ".filesync.tmp";
+
+/// Copy the file at `src` over `dest` (replacing whatever file or symlink is there), along with its permissions.
+/// Returns the number of bytes copied, and reports every written chunk's size to `on_bytes` along the way
+/// (a reflinked file, with `reflink`, counts as copied all at once).
+/// The copy is written (and fsynced) next to `dest` as `<dest>.filesync.tmp`, then renamed over it, so an interrupted
+/// copy never leaves a truncated `dest` behind. If that rename can't be done, it falls back to copying directly (with a warning).
+pub fn copy_file(src: &Path, dest: &Path, reflink: ReflinkMode, mut on_bytes: impl FnMut(u64)) -> Result<u64> {
+    if let Some(parent) = dest.parent() {  // renamed destinations may land in directories master doesn't have
+        fs::create_dir_all(parent).context(|| format!("failed to create '{}'", parent.display()))?;
+    }
+    let mut tmp = dest.as_os_str().to_owned();
+    tmp.push(TMP_SUFFIX);
+    let tmp = PathBuf::from(tmp);
+    let _ = fs::remove_file(&tmp);  // left over from an interrupted run (and maybe read-only)
+
+    let attempt = copy_contents(src, &tmp, reflink, &mut on_bytes).and_then(|bytes| {
+        fs::rename(&tmp, dest)?;
+        Ok(bytes)
+    });
+    match attempt {
+        Ok(bytes) => return Ok(bytes),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            if e.kind() != std::io::ErrorKind::CrossesDevices {
+                return Err(e).context(|| format!("failed to copy '{}' to '{}' (via '{}')", src.display(), dest.display(), tmp.display()));
+            }
+        },
+    }
+
+    eprintln!("warning: can't replace '{}' atomically (cross-device rename); copying over it directly", dest.display());
+    if fs::symlink_metadata(dest).is_ok_and(|md| !md.is_dir()) {  // don't write through a symlink, or into a read-only file
+        fs::remove_file(dest).context(|| format!("failed to remove '{}'", dest.display()))?;
+    }
+    // its bytes were already reported while writing the temp file
+    copy_contents(src, dest, reflink, |_| {}).context(|| format!("failed to copy '{}' to '{}'", src.display(), dest.display()))
+}
+
+/// [`copy_file`], then check that `dest` hashes (with `algo`) to `expected`, the source's digest (worked out here if `None`).
+/// A copy that doesn't match is made once more, before giving up with [`FilesyncError::VerifyFailed`].
+pub fn copy_file_verified(src: &Path, dest: &Path, reflink: ReflinkMode, algo: HashAlgo, expected: Option<&str>, mut on_bytes: impl FnMut(u64)) -> Result<u64> {
+    let expected = match expected {
+        Some(digest) => digest.to_string(),
+        None => algo.hash_file(src)?,
+    };
+    for _attempt in 0..2 {
+        let bytes = copy_file(src, dest, reflink, &mut on_bytes)?;
+        if algo.hash_file(dest)? == expected { return Ok(bytes); }
+    }
+    Err(FilesyncError::VerifyFailed(dest.to_path_buf()))
+}
+
+fn copy_contents(src: &Path, dest: &Path, reflink: ReflinkMode, mut on_bytes: impl FnMut(u64)) -> io::Result<u64> {
+    let mut reader = fs::File::open(src)?;
+    let mut writer = fs::File::create(dest)?;
+    let cloned = match reflink {
+        ReflinkMode::Never => false,
+        ReflinkMode::Auto => clone_contents(&reader, &writer).is_ok(),
+        ReflinkMode::Always => clone_contents(&reader, &writer)
+            .map(|_| true)
+            .map_err(|e| io::Error::new(e.kind(), format!("can't make a reflink: {e}")))?,
+    };
+
+    let total = if cloned {
+        let len = writer.metadata()?.len();
+        on_bytes(len);
+        len
+    } else {
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut total = 0;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break total,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            writer.write_all(&buf[..n])?;
+            total += n as u64;
+            on_bytes(n as u64);
+        }
+    };
+    writer.set_permissions(reader.metadata()?.permissions())?;
+    writer.sync_all()?;
+    Ok(total)
+}
+
+/// Make `dest` share all of `src`'s data blocks (FICLONE). Fails where the filesystem can't, or across filesystems.
+#[cfg(target_os = "linux")]
+fn clone_contents(src: &fs::File, dest: &fs::File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    // SAFETY: plain ioctl on two descriptors that stay open for the whole call
+    match unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clone_contents(_src: &fs::File, _dest: &fs::File) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+
+/// A token bucket shared by all the copies of a sync, which keeps their combined rate to `rate` bytes per second.
+/// It starts empty, and holds at most a second's worth of bytes, so idle time can't be saved up into a long burst.
+pub struct Throttle {
+    rate: f64,
+    state: Mutex<(f64, Instant)>,  // available bytes (negative: owed), when that was last worked out
+}
+
+impl Throttle {
+
+    pub fn new(rate: u64) -> Self {
+        Throttle { rate: rate as f64, state: Mutex::new((0.0, Instant::now())) }
+    }
+
+    /// Account for `bytes` just written, sleeping until they fit within the rate
+    pub fn take(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let (tokens, last) = &mut *state;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.rate) - bytes as f64;
+            *last = now;
+            Duration::from_secs_f64((-*tokens).max(0.0) / self.rate)
+        };
+        // sleep without the lock; others joining meanwhile owe more, so they wait longer
+        if !wait.is_zero() { std::thread::sleep(wait); }
+    }
+}
+
+
+/// Hooks for following a sync's file copies as they happen (to draw a progress bar, say).
+/// Files are copied in parallel, so calls about different files can interleave. Every `on_` method does nothing by default.
+pub trait SyncObserver: Sync {
+    /// Called once, before any copying: how many files are about to be copied, and their total size
+    fn on_sync_start(&self, _files: usize, _bytes: u64) {}
+    fn on_file_start(&self, _path: &Path, _size: u64) {}
+    /// Another `copied` bytes were written, to whichever file is being copied
+    fn on_bytes(&self, _copied: u64) {}
+    fn on_file_done(&self, _path: &Path) {}
+    /// Called once, after the last copy
+    fn on_sync_done(&self) {}
+
+    /// With `SyncOptions::interactive`: whether to go ahead with `action` (`overwrite`, `delete` or `trash`) on the slave's `path`.
+    /// Every question is asked after planning, before anything is changed. By default, asks on the terminal (see [`ask_on_terminal`])
+    fn confirm(&self, action: &str, path: &Path) -> Result<Answer> {
+        ask_on_terminal(action, path)
+    }
+}
+
+/// A reply to [`SyncObserver::confirm`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    Yes,
+    No,
+    /// Yes, to this and everything that's left
+    All,
+    /// Cancel the whole sync
+    Quit,
+}
+
+/// Ask `[y/N/a/q]` about `action` on `path`, on stderr, reading the answer from stdin (until it's one of those).
+/// Fails when stdin isn't a terminal, rather than waiting on a pipe; end of input counts as quitting
+pub fn ask_on_terminal(action: &str, path: &Path) -> Result<Answer> {
+    use std::io::{BufRead, IsTerminal};
+
+    let stdin = io::stdin();
+    if !stdin.is_terminal() { return Err(FilesyncError::NotATerminal); }
+    loop {
+        eprint!("{action} '{}'? [y/N/a/q] ", path.display());
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).context(|| "failed to read an answer".to_string())? == 0 { return Ok(Answer::Quit); }
+        match line.trim().to_ascii_lowercase().as_str() {
+            "y" | "yes" => return Ok(Answer::Yes),
+            "" | "n" | "no" => return Ok(Answer::No),
+            "a" | "all" => return Ok(Answer::All),
+            "q" | "quit" => return Ok(Answer::Quit),
+            _ => {},
+        }
+    }
+}
+
+/// A [`SyncObserver`] that ignores everything
+pub struct NoObserver;
+
+impl SyncObserver for NoObserver {}
+
+/// Make `dest` another name for the file `existing` (a hardlink), replacing a file or symlink that's already there.
+/// Fails across filesystems.
+pub fn make_hardlink(dest: &Path, existing: &Path) -> Result<()> {
+    if fs::symlink_metadata(dest).is_ok() {
+        fs::remove_file(dest).context(|| format!("failed to remove '{}'", dest.display()))?;
+    }
+    fs::hard_link(existing, dest).context(|| format!("failed to hardlink '{}' to '{}'", dest.display(), existing.display()))
+}
+
+/// Make `dest` a symlink pointing at `target`, replacing a file or symlink that's already there
+pub fn make_symlink(dest: &Path, target: &Path) -> Result<()> {
+    if fs::symlink_metadata(dest).is_ok() {
+        fs::remove_file(dest).context(|| format!("failed to remove '{}'", dest.display()))?;
+    }
+    #[cfg(unix)]
+    let made = std::os::unix::fs::symlink(target, dest);
+    #[cfg(not(unix))]
+    let made = Err(std::io::Error::from(std::io::ErrorKind::Unsupported));
+    made.context(|| format!("failed to create symlink '{}' -> '{}'", dest.display(), target.display()))
+}
+
+/// Move an entry within the same tree, creating missing parent directories of `dest`
+pub fn move_entry(src: &Path, dest: &Path) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).context(|| format!("failed to create '{}'", parent.display()))?;
+    }
+    fs::rename(src, dest).context(|| format!("failed to move '{}' to '{}'", src.display(), dest.display()))
+}
+
+/// Remove a single entry. Directories are expected to already be empty (their children are removed first).
+pub fn remove_entry(path: &Path, ty: NodeType) -> Result<()> {
+    let removed = if ty == NodeType::Dir { fs::remove_dir(path) } else { fs::remove_file(path) };
+    removed.context(|| format!("failed to remove '{}'", path.display()))
+}
+
+/// `time` as a UTC `YYYY-MM-DDTHH-MM-SSZ`, for naming trash folders (no colons, which some filesystems don't allow)
+pub fn trash_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    // civil date from days since 1970-01-01 (Howard Hinnant's days_from_civil, inverted)
+    let z = days + 719_468;
+    let (era, doe) = (z.div_euclid(146_097), z.rem_euclid(146_097));
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}T{:02}-{:02}-{:02}Z", rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+/// Move the entry at `path` (described by `meta`) to `backup`, making its parent directories, and replacing an older backup file.
+/// Across filesystems, it's copied (keeping its mode and mtime, and its owner with `preserve_owner`) and then removed.
+/// A directory's children are expected to be backed up already, so it's only made in `backup`, and removed.
+pub fn move_to_backup(path: &Path, meta: &FileMeta, backup: &Path, preserve_owner: bool) -> Result<()> {
+    if meta.ty == NodeType::Dir {
+        fs::create_dir_all(backup).context(|| format!("failed to create '{}'", backup.display()))?;
+        return remove_entry(path, meta.ty);
+    }
+    if let Some(parent) = backup.parent() {
+        fs::create_dir_all(parent).context(|| format!("failed to create '{}'", parent.display()))?;
+    }
+    match fs::rename(path, backup) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {},
+        Err(e) => return Err(e).context(|| format!("failed to move '{}' to '{}'", path.display(), backup.display())),
+    }
+
+    match &meta.link_target {
+        Some(target) => make_symlink(backup, target)?,
+        None => {
+            copy_file(path, backup, ReflinkMode::Never, |_| {})?;
+            let copied = ManifestEntry::from_rel_path(Path::new(""), backup.to_path_buf(), None, false)?;
+            sync_attributes(backup, meta, copied.record(), preserve_owner, &CompareOptions::default(), false)?;
+        },
+    }
+    remove_entry(path, meta.ty)
+}
+
+
+/// Machine-readable progress, written as one JSON object per line:
+/// a `start` event, then an event per handled entry (e.g. `copy`, `attrs`), then `finish`.
+pub struct ProgressJson<W: Write> {
+    out: W,
+    done: usize,
+    total: usize,
+}
+
+impl<W: Write> ProgressJson<W> {
+
+    pub fn new(out: W, total: usize) -> Self {
+        let mut progress = ProgressJson { out, done: 0, total };
+        progress.emit(serde_json::json!({"event": "start", "total": total}));
+        progress
+    }
+
+    /// Count an entry as done, and report it as `event` if it was acted upon (`bytes` = bytes written, if any)
+    pub fn advance(&mut self, acted: Option<(&str, &Path, Option<u64>)>) {
+        self.done += 1;
+        if let Some((event, path, bytes)) = acted {
+            let mut line = serde_json::json!({"event": event, "path": path.to_string_lossy(), "done": self.done, "total": self.total});
+            if let Some(bytes) = bytes { line["bytes"] = bytes.into(); }
+            self.emit(line);
+        }
+    }
+
+    pub fn finish(mut self) {
+        self.emit(serde_json::json!({"event": "finish", "done": self.done, "total": self.total}));
+    }
+
+    fn emit(&mut self, event: serde_json::Value) {
+        // progress is best-effort; a closed stderr mustn't abort the sync
+        let _ = writeln!(self.out, "{event}").and_then(|_| self.out.flush());
+    }
+}
+
+
+/// Tallies of what a sync did (or, in a dry run, would do), kept as it goes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncStats {
+    pub dirs_created: usize,
+    pub copied: usize,
+    pub updated: usize,
+    pub renamed: usize,
+    pub hardlinked: usize,
+    pub symlinks: usize,
+    pub deleted: usize,
+    pub trashed: usize,
+    pub attrs_fixed: usize,
+    /// Total size of the copied and updated files
+    pub bytes: u64,
+    pub elapsed: Duration,
+    pub dry_run: bool,
+}
+
+impl SyncStats {
+
+    /// Count one `action` (as in the sync log) on an entry of `size` bytes
+    pub fn count(&mut self, action: &str, size: Option<u64>) {
+        match action {
+            "mkdir" => self.dirs_created += 1,
+            "copy" => { self.copied += 1; self.bytes += size.unwrap_or(0); },
+            "update" => { self.updated += 1; self.bytes += size.unwrap_or(0); },
+            "rename" => self.renamed += 1,
+            "hardlink" => self.hardlinked += 1,
+            "link" => self.symlinks += 1,
+            "delete" => self.deleted += 1,
+            "trash" => self.trashed += 1,
+            "attrs" => self.attrs_fixed += 1,
+            _ => {},
+        }
+    }
+
+    /// Every tally on its own line (`--stats`)
+    pub fn breakdown(&self) -> String {
+        let rows = [
+            ("directories created", self.dirs_created.to_string()),
+            ("files copied", self.copied.to_string()),
+            ("files updated", self.updated.to_string()),
+            ("files renamed", self.renamed.to_string()),
+            ("files hardlinked", self.hardlinked.to_string()),
+            ("symlinks created", self.symlinks.to_string()),
+            ("entries deleted", self.deleted.to_string()),
+            ("entries trashed", self.trashed.to_string()),
+            ("attributes fixed", self.attrs_fixed.to_string()),
+            ("bytes transferred", format!("{} ({})", self.bytes, human_bytes(self.bytes))),
+            ("elapsed", format!("{:.2}s", self.elapsed.as_secs_f64())),
+        ];
+        let mut out: Vec<String> = rows.iter().map(|(name, value)| format!("{:<22}{value}", format!("{name}:"))).collect();
+        if self.dry_run { out.insert(0, "dry run, nothing was changed".to_string()); }
+        out.join("\n")
+    }
+}
+
+/// The one-line summary: `3 copied, 1 updated, 2 deleted, 1 symlinks created, 12.0 KiB transferred in 0.04s`
+impl std::fmt::Display for SyncStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} copied, {} updated, {} deleted, {} symlinks created, {} transferred in {:.2}s",
+            self.copied, self.updated, self.deleted + self.trashed, self.symlinks, human_bytes(self.bytes), self.elapsed.as_secs_f64())?;
+        if self.dry_run { write!(f, " (dry run)")?; }
+        Ok(())
+    }
+}
+
+/// `bytes` in the largest binary unit that keeps it at 1 or more: `512 B`, `12.0 KiB`, `3.5 GiB`
+pub(crate) fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 { return format!("{bytes} B"); }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
@@ -1,4 +1,4 @@
-use crate::{read_tracking_file_into_filepaths, read_tracking_file_into_string, run, write_tracking_file, write_tracking_file_with_content, ProgramArgs, TRACKING_FILENAME};
+use crate::{build_manifest, merge_tracking_files, read_tracking_file_into_filepaths, read_tracking_file_into_string, run, run_with_observer, sync_attributes_only, write_tracking_file, write_tracking_file_with_content, DuplicatePolicy, FilesyncError, ManifestEntry, ProgramArgs, RenameMap, ScanOptions, SyncObserver, SyncOptions, TRACKING_FILENAME};
 use std::{env, io};
 use std::collections::HashSet;
 use std::fs;
@@ -11,7 +11,7 @@ use rayon::prelude::*;
 
 
 use test_case::test_case;
-use crate::structures::{Manifest, ManifestEntry};
+use crate::structures::Manifest;
 
 
 #[test_case("$HOME/Downloads")]
@@ -19,7 +19,7 @@ fn test_write_tracking_file(dir_spec: &str) {
     let base_dir = expand_home(dir_spec);
 
     // write empty file
-    let (_, mut file) = write_tracking_file(&base_dir);  // if this doesn't panic, we're good
+    let (_, mut file) = write_tracking_file(&base_dir).unwrap();  // if this doesn't panic, we're good
 
     fn assert_file_empty(file: &fs::File) { assert_eq!(file.metadata().expect("can't check file metadata!").len(), 0) }
     fn assert_file_non_empty(file: &fs::File) { assert!(file.metadata().expect("can't check file metadata!!").len() > 0) }
@@ -35,12 +35,12 @@ fn test_write_tracking_file(dir_spec: &str) {
     file.flush().expect("failed to flush"); // harmless for File; required if buffered somewhere
     assert_file_non_empty(&file);
 
-    let (same_path, same_file) = write_tracking_file(&base_dir);  // writing again
+    let (same_path, same_file) = write_tracking_file(&base_dir).unwrap();  // writing again
     assert_file_non_empty(&same_file);  // checking file wasn't overwritten
-    assert!(read_tracking_file_into_string(&same_path).contains(our_string));
+    assert!(read_tracking_file_into_string(&same_path).unwrap().contains(our_string));
 
-    let filled_file_path = write_tracking_file_with_content(&base_dir, None);  // rewrite file contents
-    assert!(!read_tracking_file_into_string(&filled_file_path).contains(our_string));  // make sure previous string is overwritten
+    let filled_file_path = write_tracking_file_with_content(&base_dir, &ScanOptions::default()).unwrap();  // rewrite file contents
+    assert!(!read_tracking_file_into_string(&filled_file_path).unwrap().contains(our_string));  // make sure previous string is overwritten
 
     let _ = fs::remove_file(&filled_file_path);  // cleanup - remove tracking-file
 }
@@ -52,40 +52,1533 @@ fn tracking_file_compare_with_shell_command() {
     let baseline_out = find_escaped_output(&define_tmp_dir("S"));
 
     assert_eq!(tracker_content, baseline_out);
+
+    // --names-only lists the same paths, just without their metadata
+    let root = define_tmp_dir("S");
+    let names_only = run(ProgramArgs::parse_from(["filesync", "--track", root.to_str().unwrap(), "--names-only"])).unwrap();
+    assert_eq!(read_tracking_file_into_filepaths(Path::new(&names_only)).unwrap(), baseline_out);
+    let content = read_tracking_file_into_string(Path::new(&names_only)).unwrap();
+    assert!(content.lines().all(|l| l.starts_with('"') && l.ends_with('"')), "{content}");
+    let err = run(ProgramArgs::parse_from(["filesync", "--check", &names_only, root.to_str().unwrap()])).unwrap_err();
+    assert!(matches!(err, FilesyncError::BadTrackingLine { reason, .. } if reason.contains("--names-only")));
+}
+
+#[test]
+fn build_manifest_lists_whole_tree() {
+    let root = create_tree_and_tracker("build_manifest", None).parent().unwrap().to_path_buf();  // leaves a tracking file behind
+
+    let manifest = build_manifest(&root).unwrap();
+    let mut paths: Vec<String> = manifest.iter().map(|e| e.path_key().to_str().unwrap().to_string()).collect();
+    paths.sort_unstable();
+
+    assert!(manifest.windows(2).all(|w| w[0].path_key() <= w[1].path_key()), "entries are sorted by path_key");
+    assert_eq!(paths, find_escaped_output(&root));
+    assert_eq!(manifest.iter().find(|e| e.path_key() == Path::new("f1/b.txt")).unwrap().record().size, Some(11));
+}
+
+#[test]
+fn update_only_rehashes_entries_of_changed_dirs() {
+    let root = define_tmp_dir("track_update");
+    let _ = fs::remove_dir_all(&root);
+    for (rel, content) in [("a/x", "x"), ("a/y", "y"), ("b/z", "z"), ("top", "t")] { create_entry(&root, rel, content.as_bytes()); }
+    let track = |extra: &[&str]| {
+        let tracking_file = run(ProgramArgs::parse_from(["filesync", "--track", root.to_str().unwrap(), "--hash"].iter().chain(extra))).unwrap();
+        Manifest::deserialize_manifest(&read_tracking_file_into_string(Path::new(&tracking_file)).unwrap()).unwrap()
+    };
+    let hash_of = |manifest: &Manifest, rel: &str| manifest.entries().iter()
+        .find(|e| e.path_key() == Path::new(rel)).map(|e| e.record().content_hash().unwrap().1.to_string());
+    let first = track(&[]);
+
+    // scribble over every recorded digest: whatever --update hashes again is visible by getting its real digest back
+    let tracking_file = root.join(TRACKING_FILENAME);
+    let mut content = fs::read_to_string(&tracking_file).unwrap();
+    for rel in ["a/x", "a/y", "b/z", "top"] { content = content.replace(&hash_of(&first, rel).unwrap(), &"0".repeat(64)); }
+    fs::write(&tracking_file, content).unwrap();
+    fs::write(root.join("b/z.new"), "changed").unwrap();
+    fs::rename(root.join("b/z.new"), root.join("b/z")).unwrap();  // replaced, as editors tend to save: that changes b's mtime
+
+    let updated = track(&["--update"]);
+    let zeroes = Some("0".repeat(64));
+    assert_eq!(hash_of(&updated, "a/x"), zeroes, "a is unchanged, so its files keep what was recorded");
+    assert_eq!(hash_of(&updated, "a/y"), zeroes);
+    assert_ne!(hash_of(&updated, "b/z"), zeroes, "b changed");
+    assert_ne!(hash_of(&updated, "b/z"), hash_of(&first, "b/z"));
+    assert_eq!(hash_of(&updated, "top"), hash_of(&first, "top"), "the root's mtime isn't recorded, so its files are always looked at");
+    assert_eq!(updated.entries().iter().find(|e| e.path_key() == Path::new("b/z")).unwrap().record().size, Some(7));
+
+    create_entry(&root, "a/new", b"new");
+    fs::remove_file(root.join("a/x")).unwrap();
+    let updated = track(&["--update"]);
+    assert_eq!(hash_of(&updated, "a/x"), None);
+    assert_eq!(hash_of(&updated, "a/y"), hash_of(&first, "a/y"), "a changed now");
+    assert!(hash_of(&updated, "a/new").is_some());
+
+    fs::write(&tracking_file, fs::read_to_string(&tracking_file).unwrap().replace(&hash_of(&first, "a/y").unwrap(), &"0".repeat(64))).unwrap();
+    assert_eq!(hash_of(&track(&["--update", "--full"]), "a/y"), hash_of(&first, "a/y"), "--full starts over");
+    let unhashed = run(ProgramArgs::parse_from(["filesync", "--track", root.to_str().unwrap(), "--update"])).unwrap();
+    assert!(read_tracking_file_into_string(Path::new(&unhashed)).unwrap().lines().skip(1).all(|l| !l.contains("hash_")), "digests aren't reused without --hash");
+}
+
+#[test]
+fn parallel_walk_lists_wide_and_deep_trees_like_find() {
+    let root = define_tmp_dir("wide_and_deep");
+    let _ = fs::remove_dir_all(&root);
+    for i in 0..40 {
+        create_entry(&root, &format!("d{i}/file"), b"x");
+        let deep: String = (0..12).map(|depth| format!("n{depth}/")).collect();
+        create_entry(&root, &format!("d{i}/{deep}leaf{i}"), b"");
+        create_entry(&root, &format!("d{i}/empty/"), b"");
+    }
+    create_symlink(&root, "d0/n0/up", "../..");
+
+    let (first, second) = (build_manifest(&root).unwrap(), build_manifest(&root).unwrap());
+    let paths: Vec<String> = first.iter().map(|e| e.path_key().to_str().unwrap().to_string()).collect();
+    assert_eq!(paths.len(), 40 * 16 + 1);
+    assert_eq!(paths, second.iter().map(|e| e.path_key().to_str().unwrap().to_string()).collect::<Vec<_>>(), "the same every time");
+    let mut sorted = paths.clone();
+    sorted.sort_unstable();
+    assert_eq!(sorted, find_escaped_output(&root));
+}
+
+#[test]
+fn decode_path_round_trips_through_tracking_file() {
+    use crate::structures::Manifest;
+
+    let tracking_file = create_tree_and_tracker("decode_path", None);
+    let root = tracking_file.parent().unwrap();
+    let content = read_tracking_file_into_string(&tracking_file).unwrap();
+    let manifest = Manifest::deserialize_manifest(&content).unwrap();
+
+    for entry in manifest.entries() {
+        let path = entry.decode_path();
+        assert_eq!(path.join(""), entry.path_key().join(""), "only dirs' trailing slash differs");
+        assert!(fs::symlink_metadata(root.join(&path)).is_ok(), "{path:?} doesn't exist");
+    }
+    let decoded: Vec<PathBuf> = manifest.entries().iter().map(ManifestEntry::decode_path).collect();
+    for name in ["f2/with\nnewline", "f2/unicode_ハンバーガー_🍣", "f2/escaped_\\\'\"\'\'\\\\\t\\\'", "ハwハwハ", "empty_dir"] {
+        assert!(decoded.contains(&PathBuf::from(name)), "missing {name:?}");
+    }
+
+    let line = content.lines().find(|l| l.starts_with("\"f1/a.txt\"")).unwrap();
+    let corrupted = line.replace(&format!("\"{}\"", manifest.entries().iter().find(|e| e.path_key() == Path::new("f1/a.txt")).unwrap().record().encoded_path_b64), "\"not base64!\"");
+    assert!(matches!(ManifestEntry::deserialize_entry(&corrupted), Err(FilesyncError::BadTrackingLine { .. })));
 }
 
 #[test]
 fn check_serialized_deserialization_is_same() {
     let tracker_filepath = create_tree_and_tracker("serialization_test", None);
 
-    let file_content: String = read_tracking_file_into_string(&tracker_filepath);
+    let file_content: String = read_tracking_file_into_string(&tracker_filepath).unwrap();
 
     // deserialize from String, then serialize into String
-    let undeserailized = Manifest::serialize(Manifest::deserialize_manifest(&file_content));
+    let undeserailized = Manifest::serialize(Manifest::deserialize_manifest(&file_content).unwrap()).unwrap();
 
     assert_eq!(file_content.lines().collect::<Vec<_>>(), undeserailized);
 }
 
 
+#[test]
+fn entry_diff_names_each_differing_field() {
+    use crate::{FieldDiff, FileMeta, NodeType};
+
+    let base = FileMeta {
+        encoded_path_b64: "YS50eHQ".to_string(),  // a.txt
+        ty: NodeType::File,
+        size: Some(5),
+        mtime_ns: 1_000,
+        mode: Some(0o644),
+        uid: Some(1000),
+        gid: Some(1000),
+        link_target: None,
+        hash_sha256: Some("aa".repeat(32)),
+        hash_blake3: None,
+    };
+    let entry = |record: &FileMeta| ManifestEntry::deserialize_entry(&format!("\"a.txt\" {}", serde_json::to_string(record).unwrap())).unwrap();
+    let diff_with = |change: fn(&mut FileMeta)| {
+        let mut other = base.clone();
+        change(&mut other);
+        entry(&base).diff(&entry(&other))
+    };
+
+    assert_eq!(entry(&base).diff(&entry(&base)), None);
+    assert_eq!(entry(&base).record(), entry(&base.clone()).record(), "FileMeta compares by value");
+    let only = |diff: Option<FieldDiff>| { let d = diff.expect("a difference"); ([d.ty, d.size, d.mtime_ns, d.mode, d.owner, d.link_target, d.hash], d.changes.len()) };
+    assert_eq!(only(diff_with(|m| m.size = Some(6))), ([false, true, false, false, false, false, false], 1));
+    assert_eq!(only(diff_with(|m| m.mtime_ns = 2_000)), ([false, false, true, false, false, false, false], 1));
+    assert_eq!(only(diff_with(|m| m.mode = Some(0o600))), ([false, false, false, true, false, false, false], 1));
+    assert_eq!(only(diff_with(|m| m.gid = Some(0))), ([false, false, false, false, true, false, false], 1));
+    assert_eq!(only(diff_with(|m| m.hash_sha256 = Some("bb".repeat(32)))), ([false, false, false, false, false, false, true], 1));
+    assert_eq!(only(diff_with(|m| m.link_target = Some("b.txt".into()))), ([false, false, false, false, false, true, false], 1));
+    assert_eq!(only(diff_with(|m| { m.ty = NodeType::Symlink; m.size = None; })), ([true, true, false, false, false, false, false], 2));
+    assert_eq!(diff_with(|m| m.mode = Some(0o600)).unwrap().changes[0].to_string(), "mode 644→600");
+
+    assert_eq!(diff_with(|m| m.uid = None), None, "owners are only compared when both sides have them");
+    assert_eq!(diff_with(|m| { m.hash_sha256 = None; m.hash_blake3 = Some("cc".repeat(32)); }), None, "and digests when both used the same algorithm");
+}
+
+#[test]
+fn streamed_manifests_read_back_the_same() {
+    use crate::{discover_files, HashAlgo};
+    use unicode_width::UnicodeWidthStr;
+
+    let root = creates_complicated_testing_tree("streamed_manifest", None);
+    let manifest = discover_files(&root, &ScanOptions { hash: Some(HashAlgo::Sha256), ..Default::default() }).unwrap();
+    let records = |m: &Manifest| m.entries().iter().map(|e| (e.path_key().to_path_buf(), e.record().clone())).collect::<Vec<_>>();
+
+    let mut streamed = Vec::new();
+    manifest.serialize_to(&mut streamed).unwrap();
+    let streamed = String::from_utf8(streamed).unwrap();
+    assert_eq!(streamed.lines().next(), Some("# filesync-manifest v2 algo=sha256"));
+    assert_eq!(records(&Manifest::deserialize_manifest(&streamed).unwrap()), records(&manifest));
+
+    let mut chunked = Vec::new();
+    manifest.serialize_chunked(&mut chunked, 3).unwrap();
+    let chunked = String::from_utf8(chunked).unwrap();
+    assert_eq!(records(&Manifest::deserialize_manifest(&chunked).unwrap()), records(&manifest), "chunk boundaries don't matter to readers");
+    let record_column = |line: &str| UnicodeWidthStr::width(&line[..line.find("  {").unwrap() + 2]);
+    let lines: Vec<&str> = chunked.lines().skip(1).collect();
+    assert!(lines.len() > 3);
+    for chunk in lines.chunks(3) {
+        assert!(chunk.iter().all(|l| record_column(l) == record_column(chunk[0])), "records line up within a chunk: {chunk:#?}");
+    }
+
+    // both writers put the entries in the same (path) order, which isn't that of their JSON strings
+    let root = define_tmp_dir("manifest_order");
+    let _ = fs::remove_dir_all(&root);
+    for rel in ["a-b", "a/b", "a.txt"] { create_entry(&root, rel, b"x"); }
+    let manifest = discover_files(&root, &ScanOptions::default()).unwrap();
+    let keys = |lines: Vec<String>| lines.iter().skip(1).map(|l| ManifestEntry::deserialize_path_key(l).unwrap()).collect::<Vec<_>>();
+    let mut streamed = Vec::new();
+    manifest.serialize_to(&mut streamed).unwrap();
+    let streamed = keys(String::from_utf8(streamed).unwrap().lines().map(String::from).collect());
+    assert_eq!(streamed, keys(Manifest::serialize(manifest).unwrap()));
+    assert_eq!(streamed, ["a/", "a/b", "a-b", "a.txt"]);
+}
+
+#[test]
+fn manifest_header_tells_versions_apart() {
+    use crate::structures::Manifest;
+    use crate::MANIFEST_HEADER;
+
+    const RECORD: &str = r#"{"encoded_path_b64":"YS50eHQ","ty":"file","size":3,"mtime_ns":0,"mode":420}"#;
+    let v1 = "\"a.txt\"\n\"b/\"\n";  // path-only, from before there was metadata (or a header)
+    let legacy = format!("\"a.txt\"  {RECORD}\n");  // metadata, but from before there was a header
+    let v2 = format!("{MANIFEST_HEADER} v2 algo=blake3\n\"a.txt\"  {RECORD}\n");
+
+    for content in [&legacy, &v2] {
+        let manifest = Manifest::deserialize_manifest(content).unwrap();
+        assert_eq!(manifest.entries().len(), 1);
+        assert_eq!(manifest.entries()[0].record().size, Some(3));
+    }
+    let err = Manifest::deserialize_manifest(v1).unwrap_err();
+    assert!(matches!(err, FilesyncError::BadTrackingLine { reason, .. } if reason.contains("--names-only")));
+    for (content, complaint) in [(format!("{MANIFEST_HEADER} v3\n"), "newer"), (format!("{MANIFEST_HEADER} v1\n"), "only lists paths"),
+                                 (format!("{MANIFEST_HEADER} v2 algo=md5\n"), "unknown hash"), (format!("{MANIFEST_HEADER}\n"), "no version")] {
+        let err = Manifest::deserialize_manifest(&content).unwrap_err();
+        assert!(matches!(&err, FilesyncError::BadTrackingLine { reason, .. } if reason.contains(complaint)), "{err}");
+    }
+
+    let root = define_tmp_dir("manifest_versions");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    for (name, content) in [("v1.txt", v1.to_string()), ("v2.txt", v2)] {
+        fs::write(root.join(name), content).unwrap();
+        assert_eq!(read_tracking_file_into_filepaths(&root.join(name)).unwrap()[0], "a.txt", "both list their paths");
+    }
+
+    let written = Manifest::serialize(build_manifest(creates_complicated_testing_tree("manifest_header", None)).unwrap().into()).unwrap();
+    assert_eq!(written[0], format!("{MANIFEST_HEADER} v2"), "no algo=, since nothing was hashed");
+}
+
+#[test]
+fn bad_input_is_reported_as_errors() {
+    use crate::structures::ManifestEntry;
+
+    let root = creates_complicated_testing_tree("errors", None);
+    assert!(matches!(write_tracking_file(root.join("f1/a.txt")), Err(FilesyncError::NotADirectory(_))));
+    assert!(matches!(build_manifest(root.join("missing")), Err(FilesyncError::Io { .. })));
+    assert!(matches!(ManifestEntry::deserialize_entry(r#""a.txt"  {"ty":"file"} junk"#), Err(FilesyncError::Serde { .. })));
+    assert!(matches!(ManifestEntry::deserialize_path_key("not json"), Err(FilesyncError::BadTrackingLine { .. })));
+
+    let err = run(ProgramArgs::parse_from(["filesync", "--track", root.join("f1/a.txt").to_str().unwrap()])).unwrap_err();
+    assert_eq!(err.to_string(), format!("not a directory: '{}'", root.join("f1/a.txt").display()));
+}
+
 #[test]
 fn test_args_cli_track() {
 
-    fn run_w_args(args: &[&str]) -> String { run(ProgramArgs::parse_from(args)) }
+    fn run_w_args(args: &[&str]) -> String { run(ProgramArgs::parse_from(args)).unwrap() }
 
     let cli_path = "CLI";
 
     let root = creates_complicated_testing_tree(cli_path, None);
     // let tracker = run_w_args(&["filesync", "--track", expand_home("$HOME/Downloads").to_str().unwrap()]);
     // let tracker1 = run_w_args(&["filesync", "--track", &root.to_str().unwrap()]);
-    let tracker2 = run_w_args(&["filesync", "--track", &root.to_str().unwrap(), "-p", "f3"]);
+    run_w_args(&["filesync", "--track", root.to_str().unwrap(), "-p", "f3"]);
 
     let _ = remove_entries_with_prefix(&root, "f-");
 
     // TODO - test a prefix that catches nothing
 
     // check that this is indeed what's happening here directly in the tests and program
-    let tracking_file = write_tracking_file_with_content(root, None);
-    read_tracking_file_into_filepaths(&tracking_file);
+    let tracking_file = write_tracking_file_with_content(root, &ScanOptions::default()).unwrap();
+    read_tracking_file_into_filepaths(&tracking_file).unwrap();
+}
+
+#[test]
+fn track_dry_run_reports_field_changes() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = creates_complicated_testing_tree("dry_run", None);
+    let a_txt = root.join("f1/a.txt");
+    fs::set_permissions(&a_txt, fs::Permissions::from_mode(0o644)).unwrap();
+    let tracking_file = write_tracking_file_with_content(&root, &ScanOptions::default()).unwrap();
+    let before = read_tracking_file_into_string(&tracking_file).unwrap();
+
+    fs::set_permissions(&a_txt, fs::Permissions::from_mode(0o755)).unwrap();
+    let preview = run(ProgramArgs::parse_from(["filesync", "--track", root.to_str().unwrap(), "--dry-run"])).unwrap();
+
+    assert_eq!(preview, "~ f1/a.txt: mode 644→755");
+    assert_eq!(read_tracking_file_into_string(&tracking_file).unwrap(), before, "dry-run must not rewrite the tracking file");
+}
+
+#[test]
+fn track_skips_unreadable_dir_when_asked() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = creates_complicated_testing_tree("unreadable", None);
+    create_entry(&root, "locked/secret.txt", b"secret");
+    let locked = root.join("locked");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+    if fs::read_dir(&locked).is_ok() {  // privileged user; nothing is unreadable
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        return;
+    }
+
+    let strict = write_tracking_file_with_content(&root, &ScanOptions::default());
+    let lenient = ScanOptions { ignore_permission_errors: true, ..Default::default() };
+    let paths = read_tracking_file_into_filepaths(&write_tracking_file_with_content(&root, &lenient).unwrap()).unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+    assert!(strict.is_err(), "permission errors must fail the scan by default");
+    assert!(paths.contains(&"locked/".to_string()));
+    assert!(!paths.contains(&"locked/secret.txt".to_string()));
+}
+
+#[test]
+fn scan_reports_skipped_entries_instead_of_failing() {
+    use crate::{scan_tree, SkippedEntry};
+    use std::io::ErrorKind;
+    use std::os::unix::fs::PermissionsExt;
+
+    let lenient = ScanOptions { ignore_permission_errors: true, ..Default::default() };
+    let vanished = SkippedEntry::skippable(Path::new("gone.txt"), Some(ErrorKind::NotFound), &ScanOptions::default());
+    assert_eq!(vanished.map(|s| s.reason), Some("vanished during the scan"), "an entry deleted mid-scan is never fatal");
+    assert!(SkippedEntry::skippable(Path::new("x"), Some(ErrorKind::PermissionDenied), &ScanOptions::default()).is_none());
+    assert!(SkippedEntry::skippable(Path::new("x"), Some(ErrorKind::PermissionDenied), &lenient).is_some());
+    assert!(SkippedEntry::skippable(Path::new("x"), Some(ErrorKind::InvalidData), &lenient).is_none());
+    assert!(scan_tree(&define_tmp_dir("no_such_root"), &lenient).is_err(), "a missing root still fails");
+
+    let root = define_tmp_dir("skipped_entries");
+    let _ = fs::remove_dir_all(&root);
+    create_entry(&root, "readable.txt", b"fine");
+    create_entry(&root, "locked/secret.txt", b"secret");
+    let locked = root.join("locked");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    if fs::read_dir(&locked).is_ok() {  // privileged user; nothing is unreadable
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        return;
+    }
+    let scanned = scan_tree(&root, &lenient);
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let (manifest, skipped) = scanned.unwrap();
+    assert_eq!(manifest.entries().len(), 2, "readable.txt and locked/ itself");
+    assert_eq!(skipped.iter().map(|s| (s.path.as_path(), s.reason)).collect::<Vec<_>>(), [(Path::new("locked"), "permission denied")]);
+}
+
+#[test]
+fn merge_manifests_with_overlapping_path() {
+    use std::time::{Duration, SystemTime};
+
+    // "shared.txt" is in both trees; the copy in B is newer
+    let tracker_of = |subdir: &str, own_file: &str, shared_mtime: SystemTime| {
+        let root = define_tmp_dir(subdir);
+        let _ = fs::remove_dir_all(&root);
+        create_entry(&root, own_file, b"own");
+        let shared = create_entry(&root, "shared.txt", b"shared");
+        fs::File::options().write(true).open(shared).unwrap().set_modified(shared_mtime).unwrap();
+        write_tracking_file_with_content(&root, &ScanOptions::default()).unwrap()
+    };
+    let older = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let newer = older + Duration::from_secs(60);
+    let trackers = [tracker_of("merge_a", "a_only.txt", older), tracker_of("merge_b", "b_only.txt", newer)];
+    let output = define_tmp_dir("merge_out.txt");
+
+    let shared_mtime_after_merge = |policy| {
+        let merged = Manifest::deserialize_manifest(&read_tracking_file_into_string(&merge_tracking_files(&trackers, &output, policy).unwrap()).unwrap()).unwrap();
+        let paths: Vec<_> = merged.entries().iter().map(|e| e.path_key().to_str().unwrap().to_string()).collect();
+        assert_eq!(paths, ["a_only.txt", "b_only.txt", "shared.txt"]);
+        merged.entries()[2].record().mtime_ns
+    };
+
+    assert_eq!(shared_mtime_after_merge(DuplicatePolicy::KeepFirst), older.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos() as i128);
+    assert_eq!(shared_mtime_after_merge(DuplicatePolicy::KeepNewest), newer.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos() as i128);
+    assert!(matches!(merge_tracking_files(&trackers, &output, DuplicatePolicy::Error), Err(FilesyncError::DuplicatePath(p)) if p == Path::new("shared.txt")));
+
+    // through the CLI
+    let cli_output = run(ProgramArgs::parse_from(["filesync", "--manifest-merge", trackers[0].to_str().unwrap(), trackers[1].to_str().unwrap(),
+        "-o", output.to_str().unwrap(), "--on-duplicate", "keep-first"])).unwrap();
+    assert_eq!(PathBuf::from(cli_output), output);
+}
+
+#[test]
+fn track_excludes_dirs_with_marker() {
+    let root = creates_complicated_testing_tree("marker", None);
+    create_entry(&root, "f-3/f4/.nobackup", b"");
+
+    let tracking_file = run(ProgramArgs::parse_from(["filesync", "--track", root.to_str().unwrap(), "--exclude-if-present", ".nobackup"])).unwrap();
+    let paths = read_tracking_file_into_filepaths(Path::new(&tracking_file)).unwrap();
+
+    assert!(paths.contains(&"f-3/".to_string()));
+    assert!(paths.contains(&"f-3/inner1".to_string()));
+    assert!(paths.iter().all(|p| !p.starts_with("f-3/f4")), "marked subtree must be excluded: {paths:?}");
+}
+
+#[test]
+fn glob_patterns() {
+    use crate::Glob;
+
+    let matches = |pattern: &str, path: &str| Glob::new(pattern).matches(Path::new(path));
+
+    assert!(matches("*.tmp", "a.tmp") && matches("*.tmp", "deep/down/b.tmp"), "names match at any depth");
+    assert!(!matches("*.tmp", "a.tmp.keep"));
+    assert!(matches("node_modules", "web/node_modules/") && !matches("node_modules", "web/node_modules_old"));
+    assert!(matches("f?/a.txt", "f1/a.txt") && !matches("f?/a.txt", "x/f1/a.txt"), "a '/' anchors to the root");
+    assert!(matches("/f1", "f1") && !matches("/f1", "x/f1"));
+    assert!(!matches("f*/a.txt", "f1/deeper/a.txt"), "'*' stays within a component");
+    assert!(matches("f2/**/x", "f2/x") && matches("f2/**/x", "f2/a/b/x") && matches("**/*.log", "a/b.log"));
+    assert!(matches("[a-c]*.txt", "b.txt") && !matches("[!a-c]*.txt", "b.txt") && matches("[!a-c]*.txt", "d.txt"));
+    assert!(matches("with\\*", "with*") && !matches("with\\*", "without"));
+    assert!(matches("[oops", "[oops"), "an unterminated class is literal");
+}
+
+#[test]
+fn track_diff_and_sync_skip_excluded_globs() {
+    let root = creates_complicated_testing_tree("exclude", None);
+    for rel in ["web/node_modules/pkg/index.js", "web/app.js", "web/cache.tmp", ".git/HEAD", "f1/notes.tmp"] {
+        create_entry(&root, rel, b"x");
+    }
+    let excludes = ["--exclude", "node_modules", "--exclude", "*.tmp", "--exclude", ".git"];
+
+    let tracking_file = run(ProgramArgs::parse_from([&["filesync", "--track", root.to_str().unwrap()], &excludes[..]].concat())).unwrap();
+    let paths = read_tracking_file_into_filepaths(Path::new(&tracking_file)).unwrap();
+    assert!(paths.contains(&"web/app.js".to_string()) && paths.contains(&"web/".to_string()));
+    for gone in ["web/node_modules/", "web/node_modules/pkg/index.js", "web/cache.tmp", ".git/", ".git/HEAD", "f1/notes.tmp"] {
+        assert!(!paths.contains(&gone.to_string()), "{gone} must be excluded: {paths:?}");
+    }
+
+    let slave = define_tmp_dir("exclude_slave");
+    let _ = fs::remove_dir_all(&slave);
+    fs::create_dir_all(slave.join("keep.tmp")).unwrap();  // excluded on the slave side too, so --delete leaves it alone
+    let sync = run(ProgramArgs::parse_from([&["filesync", "--sync", root.to_str().unwrap(), slave.to_str().unwrap(), "--delete"], &excludes[..]].concat())).unwrap();
+    assert!(sync.lines().any(|l| l == "copy web/app.js"));
+    assert!(!slave.join("web/node_modules").exists() && !slave.join(".git").exists() && !slave.join("f1/notes.tmp").exists());
+    assert!(slave.join("keep.tmp").is_dir());
+
+    let diff = run(ProgramArgs::parse_from([&["filesync", "--diff", root.to_str().unwrap(), slave.to_str().unwrap()], &excludes[..]].concat())).unwrap();
+    assert_eq!(diff, "");
+}
+
+#[test]
+fn ignore_file_follows_gitignore_rules() {
+    use crate::{IgnoreRules, IGNORE_FILENAME};
+
+    let rules = IgnoreRules::parse("# build output\n*.log\n!keep.log\nbuild/\n/top_only\n\\#literal\n");
+    assert!(rules.is_ignored(Path::new("a/debug.log"), false));
+    assert!(!rules.is_ignored(Path::new("a/keep.log"), false), "a later '!' rule re-includes");
+    assert!(rules.is_ignored(Path::new("x/build"), true) && !rules.is_ignored(Path::new("x/build"), false), "'build/' only matches directories");
+    assert!(rules.is_ignored(Path::new("top_only"), false) && !rules.is_ignored(Path::new("sub/top_only"), false));
+    assert!(rules.is_ignored(Path::new("#literal"), false));
+
+    let root = creates_complicated_testing_tree("ignore_file", None);
+    for rel in ["logs/a.log", "logs/keep.log", "build/out.bin", "src/build", "src/main.rs"] {
+        create_entry(&root, rel, b"x");
+    }
+    fs::write(root.join(IGNORE_FILENAME), "*.log\n!keep.log\nbuild/\nf2\n").unwrap();
+    let track = |extra: &[&str]| {
+        let tracking_file = run(ProgramArgs::parse_from([&["filesync", "--track", root.to_str().unwrap()], extra].concat())).unwrap();
+        read_tracking_file_into_filepaths(Path::new(&tracking_file)).unwrap()
+    };
+
+    let paths = track(&[]);
+    for kept in ["logs/keep.log", "src/build", "src/main.rs", IGNORE_FILENAME] {
+        assert!(paths.contains(&kept.to_string()), "{kept} must be kept: {paths:?}");
+    }
+    assert!(paths.iter().all(|p| p != "logs/a.log" && !p.starts_with("build/") && !p.starts_with("f2/")), "{paths:?}");
+    assert!(!track(&["--exclude", "keep.log"]).contains(&"logs/keep.log".to_string()), "--exclude wins over the ignore file's '!'");
+}
+
+#[test]
+fn follow_links_records_targets_without_looping() {
+    use crate::{discover_files, Glob, NodeType};
+
+    let root = creates_complicated_testing_tree("follow_links", None);
+    create_symlink(&root, "f7/linked_f1", "../f1");
+    // sl4 leads outside the fixture, into a directory other tests write to
+    let scan = |follow_links| discover_files(&root, &ScanOptions { follow_links, excludes: vec![Glob::new("f5/f6/sl4")], ..Default::default() }).unwrap();
+    let ty_of = |manifest: &Manifest, rel: &str| manifest.entries().iter().find(|e| e.path_key() == Path::new(rel)).map(|e| e.record().ty);
+
+    let plain = scan(false);
+    assert_eq!(ty_of(&plain, "f5/sl1"), Some(NodeType::Symlink));
+    assert_eq!(ty_of(&plain, "f5/f6/sl3"), Some(NodeType::Symlink));
+
+    let followed = scan(true);
+    assert_eq!(ty_of(&followed, "f5/sl1"), Some(NodeType::File));
+    assert_eq!(ty_of(&followed, "f5/sl2"), Some(NodeType::File), "chains of links resolve too");
+    assert_eq!(followed.entries().iter().find(|e| e.path_key() == Path::new("f5/sl1")).unwrap().record().size, Some(11));
+    assert_eq!(ty_of(&followed, "f5/f6/sl3"), Some(NodeType::Symlink), "a link back to an ancestor is kept as a link, not walked");
+    assert_eq!(ty_of(&followed, "f5/f6/broken"), Some(NodeType::Symlink));
+    assert_eq!(ty_of(&followed, "f7/linked_f1/"), Some(NodeType::Dir));
+    assert_eq!(ty_of(&followed, "f7/linked_f1/b.txt"), Some(NodeType::File), "a linked directory's content is listed under the link");
+    assert_eq!(ty_of(&plain, "f7/linked_f1/b.txt"), None);
+}
+
+#[test]
+fn max_depth_limits_recursion() {
+    let root = creates_complicated_testing_tree("max_depth", None);
+    let track = |depth: &str| {
+        let tracking_file = run(ProgramArgs::parse_from(["filesync", "--track", root.to_str().unwrap(), "--max-depth", depth])).unwrap();
+        read_tracking_file_into_filepaths(Path::new(&tracking_file)).unwrap()
+    };
+
+    let paths = track("2");
+    assert!(paths.contains(&"f-3/f4/".to_string()));
+    assert!(!paths.contains(&"f-3/f4/inner2".to_string()));
+    assert!(paths.iter().all(|p| p.trim_end_matches('/').matches('/').count() < 2), "{paths:?}");
+    assert!(track("1").iter().all(|p| !p.trim_end_matches('/').contains('/')));
+    assert!(track("3").contains(&"f-3/f4/inner2".to_string()));
+}
+
+#[test]
+fn rename_map_remaps_prefixes() {
+    let map = RenameMap::parse("# migrate the old layout\nold/path = new/path\nold/path/deeper=elsewhere\n\nf2=renamed_f2\n").unwrap();
+
+    assert_eq!(map.apply(Path::new("old/path/a.txt")), PathBuf::from("new/path/a.txt"));
+    assert_eq!(map.apply(Path::new("old/path/deeper/b.txt")), PathBuf::from("elsewhere/b.txt"));  // longest prefix wins
+    assert_eq!(map.apply(Path::new("f2/")), PathBuf::from("renamed_f2/"));
+    assert_eq!(map.apply(Path::new("f2")).as_os_str(), "renamed_f2", "a file keeps its lack of a trailing slash");
+    assert_eq!(map.apply(Path::new("f22/x")), PathBuf::from("f22/x"));  // whole components only
+    assert_eq!(map.apply(Path::new("f1/a.txt")), PathBuf::from("f1/a.txt"));
+
+    let conflicting = RenameMap::parse("a=b").unwrap();
+    let paths = [Path::new("a/x"), Path::new("b/x")];
+    assert!(matches!(conflicting.destinations(paths), Err(FilesyncError::RenameConflict { .. })));
+    assert!(matches!(RenameMap::parse("no equals sign"), Err(FilesyncError::BadRenameRule(_))));
+    assert_eq!(conflicting.destinations([Path::new("a/x"), Path::new("b/y")]).unwrap().len(), 2);
+}
+
+#[test]
+fn sync_preserves_owner_when_asked() {
+    use std::os::unix::fs::MetadataExt;
+
+    let master = creates_complicated_testing_tree("owner_master", None);
+    let slave = define_tmp_dir("owner_slave");
+    let _ = fs::remove_dir_all(&slave);
+    fs::create_dir_all(&slave).unwrap();
+    let sync = |extra: &[&str]| run(ProgramArgs::parse_from(
+        [&["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap()], extra].concat())).unwrap();
+    let me = fs::metadata(&master).unwrap();
+
+    if std::os::unix::fs::chown(master.join("f1/b.txt"), Some(1234), Some(5678)).is_err() {  // unprivileged: only a warning
+        sync(&["--preserve-owner"]);
+        assert_eq!(fs::metadata(slave.join("f1/b.txt")).unwrap().uid(), me.uid());
+        return;
+    }
+    let tracking_file = write_tracking_file_with_content(&master, &ScanOptions::default()).unwrap();
+    assert!(read_tracking_file_into_string(&tracking_file).unwrap().contains(r#""uid":1234,"gid":5678"#));
+
+    sync(&[]);
+    assert_eq!(fs::metadata(slave.join("f1/b.txt")).unwrap().uid(), me.uid(), "ownership is left alone by default");
+    assert!(sync(&["--preserve-owner", "--dry-run"]).lines().any(|l| l == format!("attrs f1/b.txt: uid {}→1234, gid {}→5678", me.uid(), me.gid())));
+    sync(&["--preserve-owner"]);
+    let md = fs::metadata(slave.join("f1/b.txt")).unwrap();
+    assert_eq!((md.uid(), md.gid()), (1234, 5678));
+    assert_eq!(sync(&["--preserve-owner"]), "");
+}
+
+#[test]
+fn mtime_tolerance_hides_coarse_timestamps() {
+    use std::time::{Duration, SystemTime};
+
+    let master = define_tmp_dir("tolerance_master");
+    let slave = define_tmp_dir("tolerance_slave");
+    for root in [&master, &slave] {
+        let _ = fs::remove_dir_all(root);
+        create_entry(root, "a.txt", b"same");
+        create_entry(root, "b.txt", b"same");
+    }
+    fs::write(slave.join("b.txt"), b"longer").unwrap();
+    let now = SystemTime::now();
+    let set_mtime = |path: PathBuf, t: SystemTime| fs::File::open(path).unwrap().set_modified(t).unwrap();
+    for (path, t) in [(master.join("a.txt"), now), (slave.join("a.txt"), now + Duration::from_millis(1500)), (master.join("b.txt"), now), (slave.join("b.txt"), now)] {
+        set_mtime(path, t);
+    }
+    let run_on_both = |mode: &str, extra: &[&str]| run(ProgramArgs::parse_from(
+        [&["filesync", mode, master.to_str().unwrap(), slave.to_str().unwrap()], extra].concat())).unwrap();
+
+    assert!(run_on_both("--diff", &[]).contains("  a.txt: mtime_ns"));
+    assert!(run_on_both("--diff", &["--mtime-tolerance", "1000"]).contains("  a.txt: mtime_ns"));
+    let lenient = run_on_both("--diff", &["--mtime-tolerance", "2000"]);
+    assert!(!lenient.contains("a.txt") && lenient.contains("  b.txt: size 4→6"), "a size change still counts: {lenient}");
+
+    assert_eq!(run_on_both("--sync", &["--mtime-tolerance", "2000", "--dry-run"]), "update b.txt");
+    run_on_both("--sync", &["--mtime-tolerance", "2000"]);
+    assert_eq!(fs::metadata(slave.join("a.txt")).unwrap().modified().unwrap(), now + Duration::from_millis(1500), "within tolerance, left alone");
+}
+
+#[test]
+fn sync_attrs_only_fixes_mode_without_copying() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let master = creates_complicated_testing_tree("attrs_master", None);
+    let slave = creates_complicated_testing_tree("attrs_slave", None);
+    fs::write(slave.join("f1/b.txt"), b"HELLO WORLD").unwrap();  // same size, different bytes: left as-is
+    let sync_attrs = |extra: &[&str]| run(ProgramArgs::parse_from(
+        [&["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(), "--attrs-only"], extra].concat())).unwrap();
+
+    sync_attrs(&[]);  // align all mtimes/modes first
+    fs::set_permissions(master.join("f1/a.txt"), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::set_permissions(slave.join("f1/a.txt"), fs::Permissions::from_mode(0o644)).unwrap();
+
+    assert_eq!(sync_attrs(&["--dry-run"]), "attrs f1/a.txt: mode 644→755");
+    assert_eq!(fs::metadata(slave.join("f1/a.txt")).unwrap().permissions().mode() & 0o7777, 0o644, "dry-run must not touch anything");
+
+    assert_eq!(sync_attrs(&[]), "attrs f1/a.txt: mode 644→755");
+    assert_eq!(fs::metadata(slave.join("f1/a.txt")).unwrap().permissions().mode() & 0o7777, 0o755);
+    assert_eq!(fs::read(slave.join("f1/b.txt")).unwrap(), b"HELLO WORLD");
+    assert_eq!(sync_attrs(&[]), "", "nothing left to fix");
+
+    // a mode without read permission doesn't keep the mtime from being set after it (set by path, not through an open file)
+    let mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    fs::File::open(master.join("f1/a.txt")).unwrap().set_modified(mtime).unwrap();
+    fs::set_permissions(master.join("f1/a.txt"), fs::Permissions::from_mode(0o200)).unwrap();
+    assert!(sync_attrs(&[]).starts_with("attrs f1/a.txt: "));
+    let md = fs::metadata(slave.join("f1/a.txt")).unwrap();
+    assert_eq!((md.permissions().mode() & 0o7777, md.modified().unwrap()), (0o200, mtime));
+}
+
+#[test]
+fn sync_progress_json_events() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let master = creates_complicated_testing_tree("progress_master", None);
+    let slave = creates_complicated_testing_tree("progress_slave", None);
+    fs::set_permissions(master.join("f1/a.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+
+    let args = ProgramArgs::parse_from(["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(), "--attrs-only", "--progress-json"]);
+    let mut stderr: Vec<u8> = Vec::new();
+    sync_attributes_only(&master, &slave, &ScanOptions::from(&args), &SyncOptions::try_from(&args).unwrap(), Some(&mut stderr)).unwrap();
+
+    let events: Vec<serde_json::Value> = String::from_utf8(stderr).unwrap().lines()
+        .map(|l| serde_json::from_str(l).unwrap_or_else(|e| panic!("not a JSON line ({e}): {l:?}")))
+        .collect();
+    let total = events[0]["total"].as_u64().unwrap();
+
+    assert_eq!(events[0]["event"], "start");
+    assert!(events.iter().any(|e| e["event"] == "attrs" && e["path"] == "f1/a.txt" && e["total"] == total));
+    assert_eq!(events.last().unwrap()["event"], "finish");
+    assert_eq!(events.last().unwrap()["done"], total);
+}
+
+#[test]
+fn diff_lists_one_sided_and_changed_paths() {
+    use std::time::{Duration, SystemTime};
+
+    let tree = |subdir: &str, files: &[(&str, &[u8])]| {
+        let root = define_tmp_dir(subdir);
+        let _ = fs::remove_dir_all(&root);
+        files.iter().for_each(|(rel, contents)| { create_entry(&root, rel, contents); });
+        root
+    };
+    let master = tree("diff_master", &[("same.txt", b"same"), ("grown.txt", b"short"), ("dir/master_only.txt", b"m")]);
+    let slave = tree("diff_slave", &[("same.txt", b"same"), ("grown.txt", b"longer"), ("dir/slave_only.txt", b"s"), ("slave_only/", b"")]);
+    let fixed = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    for root in [&master, &slave] {  // only content should tell the trees apart
+        for rel in ["same.txt", "grown.txt", "dir"] {
+            fs::File::open(root.join(rel)).unwrap().set_modified(fixed).unwrap();
+        }
+    }
+
+    let diff = |a: &Path, b: &Path| run(ProgramArgs::parse_from(["filesync", "--diff", a.to_str().unwrap(), b.to_str().unwrap()])).unwrap();
+
+    assert_eq!(diff(&master, &slave), [
+        "only in master:", "  dir/master_only.txt",
+        "only in slave:", "  dir/slave_only.txt", "  slave_only/",
+        "differing:", "  grown.txt: size 5→6",
+    ].join("\n"));
+    assert_eq!(diff(&master, &master), "", "identical trees have no differences");
+}
+
+#[test]
+fn sync_mirrors_master_into_slave() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let master = creates_complicated_testing_tree("mirror_master", None);
+    fs::set_permissions(master.join("f1/b.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+    let slave = define_tmp_dir("mirror_slave");
+    let _ = fs::remove_dir_all(&slave);
+    create_entry(&slave, "stale/old.txt", b"old");
+    create_entry(&slave, "f1/b.txt", b"outdated");
+    let sync = |extra: &[&str]| run(ProgramArgs::parse_from(
+        [&["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap()], extra].concat())).unwrap();
+
+    let plan = sync(&["--delete", "--dry-run"]);
+    assert!(plan.contains("delete stale/old.txt\ndelete stale/\n"), "children are deleted before their parents: {plan}");
+    for line in ["mkdir f2/", "copy f1/a.txt", "update f1/b.txt", "mkdir empty_dir/", "link f5/sl1 -> ../f1/b.txt"] {
+        assert!(plan.lines().any(|l| l == line), "missing {line:?} in plan:\n{plan}");
+    }
+    assert_eq!(fs::read(slave.join("f1/b.txt")).unwrap(), b"outdated", "dry-run must not touch anything");
+    assert!(slave.join("stale/old.txt").exists());
+
+    assert_eq!(sync(&[]).lines().filter(|l| l.starts_with("delete")).count(), 0, "nothing is deleted without --delete");
+    assert!(slave.join("stale/old.txt").exists());
+    assert_eq!(fs::read(slave.join("f1/b.txt")).unwrap(), b"hello world");
+    assert_eq!(fs::metadata(slave.join("f1/b.txt")).unwrap().permissions().mode() & 0o7777, 0o600);
+    assert_eq!(fs::read_link(slave.join("f5/f6/sl3")).unwrap(), PathBuf::from("../.."));
+    assert!(slave.join("empty_dir").is_dir());
+
+    sync(&["--delete"]);
+    assert!(!slave.join("stale").exists());
+    let diff = run(ProgramArgs::parse_from(["filesync", "--diff", master.to_str().unwrap(), slave.to_str().unwrap()])).unwrap();
+    assert_eq!(diff, "", "a synced slave matches its master");
+    assert_eq!(sync(&["--delete"]), "", "nothing left to do");
+}
+
+#[test]
+fn sync_creates_empty_dirs_and_removes_stale_ones_children_first() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let master = creates_complicated_testing_tree("empty_dirs_master", None);
+    let slave = define_tmp_dir("empty_dirs_slave");
+    let _ = fs::remove_dir_all(&slave);
+    create_entry(&master, "nested/deeper/", b"");
+    fs::set_permissions(master.join("nested/deeper"), fs::Permissions::from_mode(0o700)).unwrap();
+    fs::set_permissions(master.join("nested"), fs::Permissions::from_mode(0o750)).unwrap();
+    create_entry(&slave, "stale/a/b/", b"");
+    let (m, s) = (master.to_str().unwrap(), slave.to_str().unwrap());
+
+    let dry = run(ProgramArgs::parse_from(["filesync", "--sync", m, s, "--delete", "--dry-run"])).unwrap();
+    assert!(dry.lines().any(|l| l == "mkdir empty_dir/") && !slave.join("empty_dir").exists(), "{dry}");
+
+    let log = run(ProgramArgs::parse_from(["filesync", "--sync", m, s, "--delete"])).unwrap();
+    let mode = |rel: &str| fs::metadata(slave.join(rel)).unwrap().permissions().mode() & 0o7777;
+    assert!(slave.join("empty_dir").is_dir() && fs::read_dir(slave.join("empty_dir")).unwrap().next().is_none());
+    assert_eq!((mode("nested"), mode("nested/deeper")), (0o750, 0o700), "directories without files get master's mode too");
+    let deletions: Vec<&str> = log.lines().filter(|l| l.starts_with("delete ")).collect();
+    assert_eq!(deletions, ["delete stale/a/b/", "delete stale/a/", "delete stale/"]);
+    assert!(!slave.join("stale").exists());
+}
+
+#[test]
+fn watch_syncs_each_change_once_it_settles() {
+    use crate::watch::{rescan_fallback, watch, Trees};
+    use crate::NoObserver;
+    use std::sync::{atomic::{AtomicBool, Ordering}, Mutex};
+    use std::time::{Duration, Instant};
+
+    let master = define_tmp_dir("watch_master");
+    let slave = define_tmp_dir("watch_slave");
+    for dir in [&master, &slave] { let _ = fs::remove_dir_all(dir); }
+    create_entry(&master, "a/x", b"v1");
+    fs::create_dir_all(&slave).unwrap();
+    run(ProgramArgs::parse_from(["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap()])).unwrap();
+    let eventually = |what: &str, done: &dyn Fn() -> bool| {
+        let started = Instant::now();
+        while !done() {
+            assert!(started.elapsed() < Duration::from_secs(10), "timed out waiting for {what}");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    };
+    let slave_has = |rel: &str, content: &str| fs::read_to_string(slave.join(rel)).is_ok_and(|c| c == content);
+
+    let sync = SyncOptions { delete: true, ..Default::default() };
+    let (stop, logs) = (AtomicBool::new(false), Mutex::new(Vec::new()));
+    std::thread::scope(|scope| {
+        let watching = scope.spawn(|| watch(&master, &slave, &ScanOptions::default(), &sync, &NoObserver, &stop, |log, _| logs.lock().unwrap().extend(log)));
+        std::thread::sleep(Duration::from_millis(200));  // for the watches to be in place
+
+        create_entry(&master, "b/c/new.txt", b"new");
+        eventually("a new file in a new dir", &|| slave_has("b/c/new.txt", "new"));
+        fs::write(master.join("a/x"), "v2").unwrap();
+        eventually("a modified file", &|| slave_has("a/x", "v2"));
+        fs::rename(master.join("a/x"), master.join("a/y")).unwrap();
+        eventually("a renamed file", &|| slave_has("a/y", "v2") && !slave.join("a/x").exists());
+        fs::remove_dir_all(master.join("b")).unwrap();
+        eventually("a removed dir", &|| !slave.join("b").exists());
+
+        for i in 0..50 { fs::write(master.join("burst.txt"), "x".repeat(i)).unwrap(); }
+        eventually("a file written many times", &|| slave_has("burst.txt", &"x".repeat(49)));
+        stop.store(true, Ordering::Relaxed);
+        watching.join().unwrap().unwrap();
+    });
+    let logs = logs.into_inner().unwrap();
+    assert_eq!(logs.iter().filter(|l| l.ends_with("burst.txt")).collect::<Vec<_>>(), ["copy burst.txt"], "synced once, after the writes settled: {logs:?}");
+
+    // where inotify can't be used, the trees are rescanned instead
+    let (stop, logs) = (AtomicBool::new(false), Mutex::new(Vec::new()));
+    let trees = Trees { master: &master, slave: &slave, sync: &sync, observer: &NoObserver };
+    let limit = io::Error::from_raw_os_error(28);  // ENOSPC, as inotify_add_watch fails past fs.inotify.max_user_watches
+    std::thread::scope(|scope| {
+        let rescanning = scope.spawn(|| rescan_fallback(&trees, &ScanOptions::default(), &stop, |log, _| logs.lock().unwrap().extend(log), &limit, Duration::from_millis(50)));
+        fs::remove_file(master.join("burst.txt")).unwrap();
+        eventually("a rescan", &|| !slave.join("burst.txt").exists());
+        stop.store(true, Ordering::Relaxed);
+        rescanning.join().unwrap().unwrap();
+    });
+    assert_eq!(logs.into_inner().unwrap(), ["delete burst.txt"]);
+}
+
+#[test]
+fn symlink_mode_rewrites_or_skips_absolute_links() {
+    let master = define_tmp_dir("symlink_mode_master");
+    let _ = fs::remove_dir_all(&master);
+    create_entry(&master, "f1/b.txt", b"hello world");
+    create_symlink(&master, "links/relative", "../f1/b.txt");
+    create_symlink(&master, "links/inside", master.join("f1/b.txt").to_str().unwrap());
+    create_symlink(&master, "links/root", master.to_str().unwrap());
+    create_symlink(&master, "outside", "/nonexistent/elsewhere");
+    let sync = |mode: &str| {
+        let slave = define_tmp_dir(&format!("symlink_mode_{mode}"));
+        let _ = fs::remove_dir_all(&slave);
+        fs::create_dir_all(&slave).unwrap();
+        let args = ProgramArgs::parse_from(["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(), "--symlink-mode", mode]);
+        run(args).unwrap();
+        let target = |rel: &str| fs::read_link(slave.join(rel)).ok();
+        (slave.clone(), [target("links/relative"), target("links/inside"), target("links/root"), target("outside")])
+    };
+
+    let (_, copied) = sync("copy");
+    assert_eq!(copied[1], Some(master.join("f1/b.txt")), "verbatim, even though it points back into master");
+    assert_eq!(copied[3], Some(PathBuf::from("/nonexistent/elsewhere")));
+
+    let (slave, relative) = sync("relativize");
+    assert_eq!(relative, [Some("../f1/b.txt".into()), Some("../f1/b.txt".into()), Some("..".into()), Some("/nonexistent/elsewhere".into())]);
+    assert_eq!(fs::read(slave.join("links/inside")).unwrap(), b"hello world", "the slave's own copy");
+    let again = run(ProgramArgs::parse_from(["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(), "--symlink-mode", "relativize"])).unwrap();
+    assert_eq!(again, "", "rewritten links are up to date");
+
+    let (_, skipped) = sync("skip-absolute");
+    assert_eq!(skipped[..3], relative[..3]);
+    assert_eq!(skipped[3], None, "absolute links out of the tree are left out");
+}
+
+#[test]
+fn comparison_flags_mask_fields() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, SystemTime};
+
+    let master = define_tmp_dir("masks_master");
+    let slave = define_tmp_dir("masks_slave");
+    let setup = || {
+        for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+        for (root, content, mode, secs) in [(&master, b"abcd", 0o644, 1_000_000), (&slave, b"wxyz", 0o600, 2_000_000)] {
+            let file = create_entry(root, "same_size", content);
+            fs::set_permissions(&file, fs::Permissions::from_mode(mode)).unwrap();
+            fs::File::options().write(true).open(&file).unwrap().set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+        }
+    };
+    let (m, s) = (master.to_str().unwrap(), slave.to_str().unwrap());
+    let cmd = |command: &str, extra: &[&str]| run(ProgramArgs::parse_from([&["filesync", command, m, s], extra].concat())).unwrap();
+
+    setup();
+    assert!(cmd("--diff", &[]).contains("same_size: mtime_ns"));
+    assert_eq!(cmd("--diff", &["--ignore-mtime"]), "", "same size, and nothing else is compared without --hash");
+    assert!(cmd("--diff", &["--ignore-mtime", "--hash"]).contains("hash_sha256"), "digests still catch the content change");
+    assert_eq!(cmd("--diff", &["--size-only", "--hash"]), "", "--size-only ignores digests too");
+
+    assert_eq!(cmd("--sync", &["--attrs-only", "--ignore-mode"]), "attrs same_size: mtime_ns 2000000000000000→1000000000000000");
+    assert_eq!(fs::metadata(slave.join("same_size")).unwrap().permissions().mode() & 0o777, 0o600, "the mode is left alone");
+    setup();
+    assert_eq!(cmd("--sync", &["--attrs-only", "--ignore-mtime"]), "attrs same_size: mode 600→644");
+    setup();
+    assert_eq!(cmd("--sync", &["--size-only"]), "", "nothing counts as different");
+    assert_eq!(fs::read(slave.join("same_size")).unwrap(), b"wxyz");
+
+    setup();
+    fs::write(master.join("same_size"), b"longer").unwrap();
+    assert_eq!(cmd("--sync", &["--ignore-mtime", "--ignore-mode"]), "update same_size");
+    let copied = fs::metadata(slave.join("same_size")).unwrap();
+    assert_eq!(copied.permissions().mode() & 0o777, 0o644, "a fresh copy still gets master's attributes");
+    assert_eq!(cmd("--diff", &[]), "");
+}
+
+#[test]
+fn parallel_copies_report_every_failure() {
+    let master = define_tmp_dir("jobs_master");
+    let slave = define_tmp_dir("jobs_slave");
+    for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+    let names: Vec<String> = (0..20).map(|i| format!("dir{}/file{i}.txt", i % 3)).collect();
+    names.iter().for_each(|rel| { create_entry(&master, rel, rel.as_bytes()); });
+    create_entry(&master, "blocked1", b"a file in master");
+    create_entry(&master, "blocked2", b"a file in master");
+    create_entry(&slave, "wall", b"");  // a file where the renamed copies need a directory
+    let map = define_tmp_dir("jobs_rename_map.txt");
+    fs::write(&map, "blocked1=wall/blocked1\nblocked2=wall/blocked2\n").unwrap();
+    let sync = |jobs: &str| run(ProgramArgs::parse_from(["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(), "--jobs", jobs, "--rename-map", map.to_str().unwrap()]));
+
+    match sync("4") {
+        Err(FilesyncError::CopyFailures(errors)) => assert_eq!(errors.len(), 2, "{errors:?}"),
+        other => panic!("expected both failures, got {other:?}"),
+    }
+    for rel in &names {
+        assert_eq!(fs::read(slave.join(rel)).unwrap(), rel.as_bytes(), "other copies still happen");
+    }
+
+    fs::remove_file(slave.join("wall")).unwrap();
+    assert_eq!(sync("1").unwrap(), "copy wall/blocked1\ncopy wall/blocked2", "the rest of the first sync was completed");
+
+    create_entry(&master, "blocked3", b"a file in master");
+    fs::write(&map, "blocked1=blocked2\n").unwrap();  // which is where blocked2 goes too
+    assert!(matches!(sync("4"), Err(FilesyncError::RenameConflict { .. })));
+    assert!(!slave.join("blocked3").exists(), "nothing is synced with a conflicting map");
+}
+
+#[test]
+fn sync_observer_hears_about_every_copy() {
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder { start: Mutex<Option<(usize, u64)>>, started: Mutex<Vec<(PathBuf, u64)>>, bytes: Mutex<u64>, done: Mutex<Vec<PathBuf>> }
+    impl SyncObserver for Recorder {
+        fn on_sync_start(&self, files: usize, bytes: u64) { *self.start.lock().unwrap() = Some((files, bytes)); }
+        fn on_file_start(&self, path: &Path, size: u64) { self.started.lock().unwrap().push((path.to_path_buf(), size)); }
+        fn on_bytes(&self, copied: u64) { *self.bytes.lock().unwrap() += copied; }
+        fn on_file_done(&self, path: &Path) { self.done.lock().unwrap().push(path.to_path_buf()); }
+    }
+
+    let master = creates_complicated_testing_tree("observer_master", None);
+    create_entry(&master, "big.bin", &vec![7u8; 200 * 1024]);  // several chunks
+    let slave = define_tmp_dir("observer_slave");
+    let _ = fs::remove_dir_all(&slave);
+    fs::create_dir_all(&slave).unwrap();
+    let args = |extra: &[&str]| ProgramArgs::parse_from([&["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap()], extra].concat());
+
+    let dry = Recorder::default();
+    run_with_observer(args(&["--dry-run"]), &dry).unwrap();
+    assert!(dry.start.lock().unwrap().is_none() && dry.started.lock().unwrap().is_empty(), "nothing is copied in a dry run");
+
+    let recorder = Recorder::default();
+    let log = run_with_observer(args(&[]), &recorder).unwrap();
+    let copied: Vec<&str> = log.lines().filter_map(|l| l.strip_prefix("copy ")).collect();
+    let (files, bytes) = recorder.start.lock().unwrap().unwrap();
+    assert_eq!(files, copied.len());
+    assert_eq!(*recorder.bytes.lock().unwrap(), bytes, "every byte that was announced got reported");
+    assert_eq!(recorder.started.lock().unwrap().iter().map(|(_, size)| size).sum::<u64>(), bytes);
+    assert!(recorder.started.lock().unwrap().contains(&(PathBuf::from("big.bin"), 200 * 1024)));
+    assert_eq!(recorder.done.lock().unwrap().len(), files);
+}
+
+#[test]
+fn interactive_sync_asks_before_overwriting_or_deleting() {
+    use crate::Answer;
+    use std::sync::Mutex;
+
+    /// Answers from a script, remembering what it was asked
+    struct Script { answers: Mutex<Vec<Answer>>, asked: Mutex<Vec<String>> }
+    impl SyncObserver for Script {
+        fn confirm(&self, action: &str, path: &Path) -> crate::Result<Answer> {
+            self.asked.lock().unwrap().push(format!("{action} {}", path.display()));
+            Ok(self.answers.lock().unwrap().remove(0))
+        }
+    }
+    let script = |answers: &[Answer]| Script { answers: Mutex::new(answers.to_vec()), asked: Mutex::default() };
+
+    let master = define_tmp_dir("interactive_master");
+    let slave = define_tmp_dir("interactive_slave");
+    for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+    create_entry(&master, "new.txt", b"new");
+    create_entry(&master, "a.txt", b"master a");
+    create_entry(&master, "b.txt", b"master b");
+    create_entry(&slave, "a.txt", b"slave a");
+    create_entry(&slave, "b.txt", b"slave b");
+    create_entry(&slave, "stale/keep.txt", b"keep");
+    create_entry(&slave, "stale/drop.txt", b"drop");
+    let args = || ProgramArgs::parse_from(["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(), "--delete", "--interactive"]);
+
+    let quitter = script(&[Answer::Yes, Answer::Quit]);
+    assert!(matches!(run_with_observer(args(), &quitter), Err(FilesyncError::Cancelled)));
+    assert!(!slave.join("new.txt").exists() && fs::read(slave.join("a.txt")).unwrap() == b"slave a", "quitting changes nothing");
+
+    let picky = script(&[Answer::No, Answer::Yes, Answer::Yes, Answer::No]);
+    let log = run_with_observer(args(), &picky).unwrap();
+    assert_eq!(*picky.asked.lock().unwrap(), ["overwrite a.txt", "overwrite b.txt", "delete stale/keep.txt", "delete stale/drop.txt"],
+        "copies to new paths aren't asked about, and the stale dir is kept along with a file in it");
+    assert!(log.contains("copy new.txt") && log.contains("update b.txt") && log.contains("delete stale/keep.txt"), "{log}");
+    assert!(!log.contains("a.txt") && !log.lines().any(|l| l == "delete stale"), "{log}");
+    assert_eq!(fs::read(slave.join("a.txt")).unwrap(), b"slave a");
+    assert_eq!(fs::read(slave.join("stale/drop.txt")).unwrap(), b"drop");
+    assert!(!slave.join("stale/keep.txt").exists());
+
+    let agreeable = script(&[Answer::All]);
+    run_with_observer(args(), &agreeable).unwrap();
+    assert_eq!(agreeable.asked.lock().unwrap().len(), 1);
+    assert_eq!(fs::read(slave.join("a.txt")).unwrap(), b"master a");
+    assert!(!slave.join("stale").exists());
+}
+
+#[test]
+fn sync_stats_tally_what_was_done() {
+    use crate::{sync_trees, NoObserver};
+
+    let master = define_tmp_dir("stats_master");
+    let slave = define_tmp_dir("stats_slave");
+    for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+    create_entry(&master, "dir/new.txt", b"12345");
+    create_entry(&master, "changed.txt", b"master version");
+    create_symlink(&master, "link", "changed.txt");
+    create_entry(&slave, "changed.txt", b"older");
+    create_entry(&slave, "stale.txt", b"stale");
+    let args = |extra: &[&str]| ProgramArgs::parse_from([&["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(), "--delete"], extra].concat());
+    let stats = |extra: &[&str]| {
+        let args = args(extra);
+        let (log, stats) = sync_trees(&master, &slave, &ScanOptions::from(&args), &SyncOptions::try_from(&args).unwrap(), None::<io::Sink>, &NoObserver).unwrap();
+        (log.len(), stats)
+    };
+
+    let (actions, dry) = stats(&["--dry-run"]);
+    assert_eq!((dry.dirs_created, dry.copied, dry.updated, dry.symlinks, dry.deleted), (1, 1, 1, 1, 1));
+    assert_eq!(dry.bytes, 5 + 14, "the sizes of the copied and updated files");
+    assert_eq!(actions, 5);
+    assert!(dry.dry_run && dry.to_string().starts_with("1 copied, 1 updated, 1 deleted, 1 symlinks created, 19 B transferred in "));
+    assert!(dry.breakdown().lines().any(|l| l == "bytes transferred:    19 (19 B)"), "{}", dry.breakdown());
+
+    assert_eq!(run(args(&["--quiet"])).unwrap(), "", "--quiet leaves only the summary, on stderr");
+    assert!(slave.join("dir/new.txt").exists() && !slave.join("stale.txt").exists());
+    let (actions, after) = stats(&[]);
+    assert_eq!((actions, after.copied + after.updated + after.deleted, after.bytes), (0, 0, 0), "nothing left to do");
+}
+
+#[test]
+fn ignore_case_matches_paths_that_only_differ_in_case() {
+    let master = define_tmp_dir("ignore_case_master");
+    let slave = define_tmp_dir("ignore_case_slave");
+    for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+    create_entry(&master, "Docs/Notes.txt", b"new notes");
+    create_entry(&master, "Docs/added.txt", b"added");
+    create_entry(&slave, "docs/notes.txt", b"old");
+    let (m, s) = (master.to_str().unwrap(), slave.to_str().unwrap());
+    let diff = |extra: &[&str]| run(ProgramArgs::parse_from([&["filesync", "--diff", m, s], extra].concat())).unwrap();
+    assert!(diff(&[]).contains("docs/notes.txt"), "case-sensitive by default: {}", diff(&[]));
+    let folded = diff(&["--ignore-case"]);
+    assert!(folded.contains("Docs/Notes.txt") && folded.contains("Docs/added.txt") && !folded.contains("docs/notes.txt"), "{folded}");
+
+    run(ProgramArgs::parse_from(["filesync", "--sync", m, s, "--delete", "--ignore-case"])).unwrap();
+    assert_eq!(fs::read(slave.join("docs/notes.txt")).unwrap(), b"new notes", "updated in place, keeping the slave's spelling");
+    assert!(slave.join("docs/added.txt").exists() && !slave.join("Docs").exists(), "new entries go into the slave's spelling of their dir");
+    assert_eq!(diff(&["--ignore-case"]), "");
+
+    create_entry(&master, "docs/notes.txt", b"a second spelling");
+    let err = run(ProgramArgs::parse_from(["filesync", "--diff", m, s, "--ignore-case"])).unwrap_err();
+    assert!(matches!(err, FilesyncError::PathCollision { option: "--ignore-case", .. }), "{err}");
+    let err = run(ProgramArgs::parse_from(["filesync", "--sync", m, s, "--ignore-case"])).unwrap_err();
+    assert!(matches!(err, FilesyncError::PathCollision { option: "--ignore-case", .. }), "{err}");
+}
+
+#[test]
+fn normalize_unicode_matches_composed_and_decomposed_names() {
+    let (composed, decomposed) = ("caf\u{e9}.txt", "cafe\u{301}.txt");
+    assert_eq!(crate::unicode::nfc(decomposed), composed);
+    assert_eq!(crate::unicode::nfc("\u{1100}\u{1161}\u{11a8}"), "\u{ac01}", "Hangul jamo compose arithmetically");
+    assert_eq!(crate::unicode::nfc("a\u{323}\u{302}"), crate::unicode::nfc("a\u{302}\u{323}"), "marks are put in order first");
+
+    let master = define_tmp_dir("normalize_master");
+    let slave = define_tmp_dir("normalize_slave");
+    for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+    create_entry(&master, composed, b"from linux");
+    create_entry(&slave, decomposed, b"from a macbook");
+    let (m, s) = (master.to_str().unwrap(), slave.to_str().unwrap());
+    let diff = |extra: &[&str]| run(ProgramArgs::parse_from([&["filesync", "--diff", m, s], extra].concat())).unwrap();
+    assert!(diff(&[]).contains(composed) && diff(&[]).contains(decomposed), "byte-exact by default");
+    assert!(!diff(&["--normalize-unicode"]).contains(decomposed), "{}", diff(&["--normalize-unicode"]));
+
+    run(ProgramArgs::parse_from(["filesync", "--sync", m, s, "--delete", "--normalize-unicode"])).unwrap();
+    assert_eq!(fs::read(slave.join(decomposed)).unwrap(), b"from linux", "updated under the slave's own bytes");
+    assert!(!slave.join(composed).exists());
+
+    create_entry(&master, decomposed, b"both forms");
+    let err = run(ProgramArgs::parse_from(["filesync", "--diff", m, s, "--normalize-unicode", "--ignore-case"])).unwrap_err();
+    assert!(matches!(err, FilesyncError::PathCollision { option: "--normalize-unicode", .. }), "{err}");
+}
+
+#[test]
+fn find_dupes_groups_files_by_content() {
+    let root = creates_complicated_testing_tree("find_dupes", None);
+    create_entry(&root, "big/one", &[7; 3000]);
+    create_entry(&root, "big/two", &[7; 3000]);
+    let output = run(ProgramArgs::parse_from(["filesync", "--find-dupes", root.to_str().unwrap()])).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(&lines[..3], ["2 copies of 2.9 KiB (2.9 KiB wasted):", "  big/one", "  big/two"], "the most wasted space first:\n{output}");
+    let group = |header: &str| lines.iter().skip_while(|l| **l != header).skip(1).take_while(|l| l.starts_with("  ")).map(|l| l.trim_start()).collect::<Vec<_>>();
+    assert_eq!(group("2 copies of 14 B (14 B wasted):"), ["f-4/inner2", &format!("f4/{TRACKING_FILENAME}")], "only a root tracking file is left out");
+    assert_eq!(group("6 copies of 7 B (35 B wasted):").len(), 6, "{output}");
+    assert!(!output.contains("empty_file") && !output.contains("f-3/f4/inner2"), "empty and unique files aren't listed");
+    assert_eq!(lines.last(), Some(&"3 groups, 3.0 KiB wasted in total"));
+
+    let blake3 = run(ProgramArgs::parse_from(["filesync", "--find-dupes", root.to_str().unwrap(), "--hash-algo", "blake3"])).unwrap();
+    assert_eq!(blake3, output, "the same groups with another digest");
+}
+
+#[test]
+fn type_changes_are_reported_and_replaced_by_sync() {
+    let master = define_tmp_dir("retype_master");
+    let slave = define_tmp_dir("retype_slave");
+    for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+    create_entry(&master, "was_dir", b"now a file");
+    create_entry(&slave, "was_dir/nested/keep.txt", b"slave only");
+    create_entry(&master, "was_file/inside.txt", b"inside");
+    create_entry(&slave, "was_file", b"a file");
+    create_entry(&master, "was_link", b"a file in master");
+    create_symlink(&slave, "was_link", "was_file");
+    let (m, s) = (master.to_str().unwrap(), slave.to_str().unwrap());
+
+    let diff = run(ProgramArgs::parse_from(["filesync", "--diff", m, s])).unwrap();
+    assert!(diff.contains("type changed:\n  was_dir: file→dir\n  was_file: dir→file\n  was_link: file→symlink"), "{diff}");
+    assert!(!diff.contains("differing:"), "no size or mtime noise for entries of different types: {diff}");
+
+    let log = run(ProgramArgs::parse_from(["filesync", "--sync", m, s, "--trash"])).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    let at = |line: &str| lines.iter().position(|l| *l == line).unwrap_or_else(|| panic!("no {line:?} in:\n{log}"));
+    assert!(at("trash was_dir/nested/keep.txt") < at("replace was_dir: dir→file") && at("replace was_dir: dir→file") < at("copy was_dir"));
+    assert!(at("replace was_file: file→dir") < at("mkdir was_file/") && at("replace was_link: symlink→file") < at("copy was_link"));
+
+    assert_eq!(fs::read(slave.join("was_dir")).unwrap(), b"now a file");
+    assert_eq!(fs::read(slave.join("was_file/inside.txt")).unwrap(), b"inside");
+    assert!(!slave.join("was_link").is_symlink() && fs::read(slave.join("was_link")).unwrap() == b"a file in master");
+    let trashed: Vec<PathBuf> = fs::read_dir(slave.join(crate::TRASH_DIRNAME)).unwrap().map(|e| e.unwrap().path()).collect();
+    assert_eq!(fs::read(trashed[0].join("was_dir/nested/keep.txt")).unwrap(), b"slave only", "replaced entries go where deleted ones would");
+    assert_eq!(run(ProgramArgs::parse_from(["filesync", "--diff", m, s])).unwrap(), "");
+}
+
+#[test]
+fn check_reports_drift_since_tracking() {
+    let root = define_tmp_dir("check_drift");
+    let _ = fs::remove_dir_all(&root);
+    create_entry(&root, "same.txt", b"same");
+    create_entry(&root, "edited.txt", b"before");
+    create_entry(&root, "gone.txt", b"gone");
+    let tracking_file = run(ProgramArgs::parse_from(["filesync", "--track", root.to_str().unwrap(), "--hash"])).unwrap();
+    let check = || run(ProgramArgs::parse_from(["filesync", "--check", &tracking_file, root.to_str().unwrap()])).unwrap();
+    assert_eq!(check(), "", "nothing changed yet");
+
+    let mtime = fs::metadata(root.join("edited.txt")).unwrap().modified().unwrap();
+    fs::write(root.join("edited.txt"), b"after!").unwrap();
+    fs::File::options().write(true).open(root.join("edited.txt")).unwrap().set_modified(mtime).unwrap();
+    fs::remove_file(root.join("gone.txt")).unwrap();
+    create_entry(&root, "new.txt", b"new");
+
+    let report = check();
+    assert!(report.starts_with("vanished:\n  gone.txt\nappeared:\n  new.txt\ndiffering:\n  edited.txt: "), "{report}");
+    assert!(report.contains("hash_sha256"), "the tracking file's digests get the live tree hashed too: {report}");
+    assert!(!report.contains("same.txt") && !report.contains(TRACKING_FILENAME));
+}
+
+#[test]
+fn sync_both_carries_one_sided_changes_and_reports_conflicts() {
+    use crate::BASELINE_FILENAME;
+    use std::time::{Duration, SystemTime};
+
+    let master = define_tmp_dir("sync_both_master");
+    let slave = define_tmp_dir("sync_both_slave");
+    for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+    let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+    let write = |root: &Path, name: &str, content: &[u8], mtime: SystemTime| {
+        let path = create_entry(root, name, content);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+    };
+    write(&master, "shared.txt", b"shared", at(1_000_000));
+    write(&slave, "shared.txt", b"shared", at(1_000_000));
+    write(&master, "from_master.txt", b"m", at(1_000_000));
+    write(&slave, "dir/from_slave.txt", b"s", at(1_000_000));
+    write(&master, "clash.txt", b"master's", at(2_000_000));
+    write(&slave, "clash.txt", b"slave's!!", at(1_000_000));
+    let sync = |extra: &[&str]| run(ProgramArgs::parse_from([&["filesync", "--sync-both", master.to_str().unwrap(), slave.to_str().unwrap()], extra].concat())).unwrap();
+
+    let plan = sync(&["--dry-run"]);
+    assert!(!master.join(BASELINE_FILENAME).exists(), "a dry run writes no baseline");
+    assert_eq!(sync(&[]), plan);
+    for line in ["conflict clash.txt: changed on both sides, skipped", "mkdir master dir/", "copy master dir/from_slave.txt", "copy slave from_master.txt"] {
+        assert!(plan.lines().any(|l| l == line), "missing {line:?} in:\n{plan}");
+    }
+    assert!(!plan.contains("shared.txt"));
+    assert_eq!(fs::read(master.join("dir/from_slave.txt")).unwrap(), b"s");
+    assert_eq!(fs::metadata(master.join("dir/from_slave.txt")).unwrap().modified().unwrap(), at(1_000_000));
+    assert!(slave.join(BASELINE_FILENAME).is_file());
+
+    // one-sided changes since the baseline go the other way, including deletions
+    write(&slave, "shared.txt", b"edited in slave", at(3_000_000));
+    fs::remove_file(master.join("from_master.txt")).unwrap();
+    let log = sync(&[]);
+    for line in ["update master shared.txt", "delete slave from_master.txt", "conflict clash.txt: changed on both sides, skipped"] {
+        assert!(log.lines().any(|l| l == line), "missing {line:?} in:\n{log}");
+    }
+    assert_eq!(fs::read(master.join("shared.txt")).unwrap(), b"edited in slave");
+    assert!(!slave.join("from_master.txt").exists());
+
+    // a deleted directory stays while a conflict inside it does
+    fs::remove_dir_all(slave.join("dir")).unwrap();
+    write(&master, "dir/from_slave.txt", b"edited in master", at(4_000_000));
+    let log = sync(&[]);
+    assert!(log.contains("conflict dir/from_slave.txt") && !log.contains("delete master dir"), "{log}");
+
+    assert_eq!(sync(&["--conflict", "newer"]).lines().filter(|l| l.starts_with("conflict")).collect::<Vec<_>>(),
+        ["conflict clash.txt: changed on both sides, kept master's", "conflict dir/from_slave.txt: changed on both sides, kept master's"]);
+    assert_eq!(fs::read(slave.join("clash.txt")).unwrap(), b"master's");
+    assert_eq!(fs::read(slave.join("dir/from_slave.txt")).unwrap(), b"edited in master");
+    assert_eq!(sync(&[]), "", "in sync");
+}
+
+#[test]
+fn bwlimit_caps_the_combined_copy_rate() {
+    use crate::args_parse::parse_rate;
+    use std::time::Instant;
+
+    assert_eq!(parse_rate("10M"), Ok(10_000_000));
+    assert_eq!(parse_rate("512KiB/s"), Ok(512 * 1024));
+    assert_eq!(parse_rate("1.5gi"), Ok(3 * 512 * 1024 * 1024));
+    assert_eq!(parse_rate("4096"), Ok(4096));
+    assert!(parse_rate("fast").is_err() && parse_rate("10X").is_err() && parse_rate("0").is_err());
+
+    let master = define_tmp_dir("bwlimit_master");
+    let slave = define_tmp_dir("bwlimit_slave");
+    for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+    for i in 0..4 { create_entry(&master, &format!("file{i}"), &vec![0u8; 100 * 1024]); }
+    fs::create_dir_all(&slave).unwrap();
+
+    let started = Instant::now();
+    run(ProgramArgs::parse_from(["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(), "--jobs", "4", "--bwlimit", "2M"])).unwrap();
+    let elapsed = started.elapsed().as_secs_f64();
+    assert!(elapsed >= 0.18, "400KiB at 2MB/s should take about 0.2s, even over 4 jobs; took {elapsed}s");
+    assert_eq!(fs::read(slave.join("file3")).unwrap().len(), 100 * 1024);
+}
+
+#[test]
+fn verify_rechecks_copies_against_their_source() {
+    use crate::sync::copy_file_verified;
+    use crate::{HashAlgo, ReflinkMode};
+
+    let master = creates_complicated_testing_tree("verify_master", None);
+    let slave = define_tmp_dir("verify_slave");
+    let _ = fs::remove_dir_all(&slave);
+    fs::create_dir_all(&slave).unwrap();
+    let sync = |extra: &[&str]| ProgramArgs::try_parse_from([&["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap()], extra].concat());
+
+    assert!(sync(&["--hash-algo", "blake3"]).is_err(), "--hash-algo needs --hash or --verify");
+    run(sync(&["--verify", "--hash-algo", "blake3"]).unwrap()).unwrap();
+    assert_eq!(fs::read(slave.join("f1/b.txt")).unwrap(), b"hello world");
+    fs::write(slave.join("f1/b.txt"), b"changed").unwrap();
+    assert_eq!(run(sync(&["--verify", "--hash"]).unwrap()).unwrap(), "update f1/b.txt", "uses the digests --hash recorded");
+
+    let (src, dest) = (master.join("f1/b.txt"), slave.join("f1/b.txt"));
+    let mut reported = 0;
+    let wrong = "0".repeat(64);
+    assert!(matches!(copy_file_verified(&src, &dest, ReflinkMode::Never, HashAlgo::Sha256, Some(&wrong), |n| reported += n), Err(FilesyncError::VerifyFailed(p)) if p == dest));
+    assert_eq!(reported, 22, "a mismatching copy is retried once");
+    reported = 0;
+    assert_eq!(copy_file_verified(&src, &dest, ReflinkMode::Never, HashAlgo::Sha256, None, |n| reported += n).unwrap(), 11);
+    assert_eq!(reported, 11);
+}
+
+#[test]
+fn reflink_modes_fall_back_or_fail() {
+    use crate::sync::copy_file;
+    use crate::ReflinkMode;
+
+    let root = define_tmp_dir("reflink");
+    let _ = fs::remove_dir_all(&root);
+    let src = create_entry(&root, "src.bin", &vec![3u8; 100 * 1024]);
+    let copy = |mode, name: &str| copy_file(&src, &root.join(name), mode, |_| {});
+
+    assert_eq!(copy(ReflinkMode::Never, "never.bin").unwrap(), 100 * 1024);
+    assert_eq!(copy(ReflinkMode::Auto, "auto.bin").unwrap(), 100 * 1024, "a reflink, or a byte copy where that isn't supported");
+    match copy(ReflinkMode::Always, "always.bin") {
+        Ok(bytes) => assert_eq!(bytes, 100 * 1024),  // a filesystem that can clone (btrfs, XFS, ...)
+        Err(e) => {
+            assert!(e.to_string().contains("can't make a reflink"), "{e}");
+            assert!(!root.join("always.bin").exists() && !root.join("always.bin.filesync.tmp").exists());
+        },
+    }
+    for name in ["never.bin", "auto.bin"] {
+        assert_eq!(fs::read(root.join(name)).unwrap(), fs::read(&src).unwrap());
+    }
+}
+
+#[test]
+fn hardlink_dupes_links_identical_files() {
+    use std::os::unix::fs::MetadataExt;
+    use std::time::{Duration, SystemTime};
+
+    let master = define_tmp_dir("hardlink_master");
+    let slave = define_tmp_dir("hardlink_slave");
+    for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+    let fixed = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    for (rel, contents) in [("a/dup.txt", "same"), ("b/dup.txt", "same"), ("c/dup.txt", "same"), ("kept.txt", "kept"), ("also_kept.txt", "kept")] {
+        let path = create_entry(&master, rel, contents.as_bytes());
+        fs::File::open(path).unwrap().set_modified(fixed).unwrap();
+    }
+    fs::File::open(master.join("c/dup.txt")).unwrap().set_modified(fixed + Duration::from_secs(1)).unwrap();  // can't share an inode
+    create_entry(&slave, "kept.txt", b"kept");
+    fs::File::open(slave.join("kept.txt")).unwrap().set_modified(fixed).unwrap();
+    let sync = |extra: &[&str]| run(ProgramArgs::parse_from(
+        [&["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(), "--hash", "--hardlink-dupes"], extra].concat())).unwrap();
+
+    assert!(ProgramArgs::try_parse_from(["filesync", "--sync", "m", "s", "--hardlink-dupes"]).is_err(), "needs --hash");
+    let log = sync(&[]);
+    for line in ["copy a/dup.txt", "hardlink b/dup.txt => a/dup.txt", "copy c/dup.txt", "hardlink also_kept.txt => kept.txt"] {
+        assert!(log.lines().any(|l| l == line), "missing {line:?} in:\n{log}");
+    }
+    let inode = |rel: &str| fs::metadata(slave.join(rel)).unwrap().ino();
+    assert_eq!(inode("a/dup.txt"), inode("b/dup.txt"));
+    assert_ne!(inode("a/dup.txt"), inode("c/dup.txt"));
+    assert_eq!(inode("kept.txt"), inode("also_kept.txt"), "files already in the slave are linked to as well");
+    assert_eq!(fs::read(slave.join("b/dup.txt")).unwrap(), b"same");
+    assert_eq!(sync(&[]), "", "linked files stay in sync");
+}
+
+#[test]
+fn backup_dir_keeps_overwritten_and_deleted_files() {
+    use std::time::{Duration, SystemTime};
+
+    let master = define_tmp_dir("backup_master");
+    let slave = define_tmp_dir("backup_slave");
+    for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+    create_entry(&master, "changed.txt", b"new");
+    create_entry(&master, "link", b"now a file");
+    create_entry(&slave, "changed.txt", b"old version");
+    create_symlink(&slave, "link", "changed.txt");
+    create_entry(&slave, "stale/old.txt", b"stale");
+    let old_mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    fs::File::open(slave.join("changed.txt")).unwrap().set_modified(old_mtime).unwrap();
+    let backup = slave.join(".backup");
+    let sync = || run(ProgramArgs::parse_from(["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(),
+        "--delete", "--backup-dir", backup.to_str().unwrap()])).unwrap();
+
+    let log = sync();
+    assert!(log.contains("update changed.txt") && log.contains("delete stale/old.txt"), "{log}");
+    assert_eq!(fs::read(slave.join("changed.txt")).unwrap(), b"new");
+    assert_eq!(fs::read(backup.join("changed.txt")).unwrap(), b"old version");
+    assert_eq!(fs::metadata(backup.join("changed.txt")).unwrap().modified().unwrap(), old_mtime, "backups keep their mtime");
+    assert_eq!(fs::read_link(backup.join("link")).unwrap(), PathBuf::from("changed.txt"));
+    assert_eq!(fs::read(backup.join("stale/old.txt")).unwrap(), b"stale");
+    assert!(!slave.join("stale").exists());
+
+    assert_eq!(sync(), "", "the backup dir inside the slave is neither synced nor deleted");
+    assert!(backup.join("changed.txt").exists());
+}
+
+#[test]
+fn trash_keeps_stale_entries_out_of_later_scans() {
+    use crate::sync::trash_timestamp;
+    use std::time::{Duration, SystemTime};
+
+    assert_eq!(trash_timestamp(SystemTime::UNIX_EPOCH), "1970-01-01T00-00-00Z");
+    assert_eq!(trash_timestamp(SystemTime::UNIX_EPOCH + Duration::from_secs(951_827_696)), "2000-02-29T12-34-56Z");
+
+    let master = define_tmp_dir("trash_master");
+    let slave = define_tmp_dir("trash_slave");
+    for root in [&master, &slave] { let _ = fs::remove_dir_all(root); }
+    create_entry(&master, "kept.txt", b"kept");
+    create_entry(&slave, "kept.txt", b"kept");
+    create_entry(&slave, "stale/old.txt", b"stale");
+    let sync = || run(ProgramArgs::parse_from(["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(), "--trash"])).unwrap();
+
+    let log = sync();
+    assert!(log.contains("trash stale/old.txt") && log.contains("trash stale"), "{log}");
+    assert!(!slave.join("stale").exists());
+    let trashed: Vec<PathBuf> = fs::read_dir(slave.join(".filesync-trash")).unwrap().map(|e| e.unwrap().path()).collect();
+    assert_eq!(trashed.len(), 1, "one timestamped folder per sync");
+    assert_eq!(fs::read(trashed[0].join("stale/old.txt")).unwrap(), b"stale");
+
+    assert_eq!(sync(), "", "the trash is neither synced nor trashed itself");
+    let diff = run(ProgramArgs::parse_from(["filesync", "--diff", master.to_str().unwrap(), slave.to_str().unwrap()])).unwrap();
+    assert_eq!(diff, "");
+}
+
+#[test]
+fn copies_replace_their_destination_atomically() {
+    use crate::sync::copy_file;
+    use crate::ReflinkMode;
+
+    let root = define_tmp_dir("atomic_copy");
+    let _ = fs::remove_dir_all(&root);
+    let src = create_entry(&root, "src.txt", b"new content");
+    let dest = create_entry(&root, "dest.txt", b"old");
+    let other_name = root.join("hardlink_to_dest.txt");
+    fs::hard_link(&dest, &other_name).unwrap();
+    create_entry(&root, "dest.txt.filesync.tmp", b"left over from a crash");
+
+    assert_eq!(copy_file(&src, &dest, ReflinkMode::Never, |_| {}).unwrap(), 11);
+    assert_eq!(fs::read(&dest).unwrap(), b"new content");
+    assert_eq!(fs::read(&other_name).unwrap(), b"old", "dest was replaced by a rename, not rewritten in place");
+    assert!(!root.join("dest.txt.filesync.tmp").exists());
+
+    let blocked = create_entry(&root, "blocked/", b"");
+    create_entry(&root, "blocked/keep.txt", b"keep");
+    assert!(matches!(copy_file(&src, &blocked, ReflinkMode::Never, |_| {}), Err(FilesyncError::Io { .. })));
+    assert!(!root.join("blocked.filesync.tmp").exists(), "the temp file is cleaned up on failure");
+    assert_eq!(fs::read(blocked.join("keep.txt")).unwrap(), b"keep");
+}
+
+#[test]
+fn gzip_round_trips_and_matches_the_gzip_tool() {
+    use crate::gzip::{compress, decompress};
+
+    let mut seed = 12345u32;
+    let noise: Vec<u8> = (0..70_000).map(|_| { seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345); (seed >> 16) as u8 }).collect();
+    let repetitive = "\"f1/a.txt\"  {\"ty\":\"file\",\"size\":12}\n".repeat(5000).into_bytes();
+    for data in [Vec::new(), b"a".to_vec(), vec![0u8; 100_000], noise, repetitive.clone()] {
+        let packed = compress(&data);
+        assert_eq!(decompress(&packed).unwrap(), data);
+
+        let unpacked = Command::new("gzip").arg("-dc").stdin(std::process::Stdio::piped()).stdout(std::process::Stdio::piped()).spawn()
+            .and_then(|mut child| { child.stdin.take().unwrap().write_all(&packed)?; child.wait_with_output() }).unwrap();
+        assert!(unpacked.status.success() && unpacked.stdout == data, "gzip(1) reads what we write");
+    }
+    assert!(compress(&repetitive).len() < repetitive.len() / 10);
+
+    // and we read what gzip(1) writes: dynamic Huffman blocks, and a file name in the header
+    let root = define_tmp_dir("gzip_tool");
+    let _ = fs::remove_dir_all(&root);
+    create_entry(&root, "listing.txt", &repetitive);
+    assert!(Command::new("gzip").args(["-9", "listing.txt"]).current_dir(&root).status().unwrap().success());
+    assert_eq!(decompress(&fs::read(root.join("listing.txt.gz")).unwrap()).unwrap(), repetitive);
+    let mut corrupt = fs::read(root.join("listing.txt.gz")).unwrap();
+    let middle = corrupt.len() / 2;
+    corrupt[middle] ^= 0x55;
+    assert!(decompress(&corrupt).is_err());
+}
+
+#[test]
+fn compressed_tracking_files_are_read_transparently() {
+    use crate::COMPRESSED_TRACKING_FILENAME;
+
+    let root = creates_complicated_testing_tree("compressed_tracking", None);
+    let plain = write_tracking_file_with_content(&root, &ScanOptions::default()).unwrap();
+    let expected = read_tracking_file_into_filepaths(&plain).unwrap();
+
+    let tracking_file = run(ProgramArgs::parse_from(["filesync", "--track", root.to_str().unwrap(), "--compress"])).unwrap();
+    assert!(tracking_file.ends_with(COMPRESSED_TRACKING_FILENAME));
+    assert!(!plain.exists(), "the uncompressed tracking file it replaces is removed");
+    assert_eq!(read_tracking_file_into_filepaths(Path::new(&tracking_file)).unwrap(), expected, "the tracking file itself isn't listed");
+    assert_eq!(run(ProgramArgs::parse_from(["filesync", "--check", &tracking_file, root.to_str().unwrap()])).unwrap(), "");
+    assert_eq!(run(ProgramArgs::parse_from(["filesync", "--track", root.to_str().unwrap(), "--dry-run"])).unwrap(), "", "the preview finds it too");
+}
+
+#[test]
+fn sha256_matches_known_digests() {
+    use crate::hashing::Sha256;
+
+    let digest = |chunks: &[&[u8]]| {
+        let mut hasher = Sha256::new();
+        chunks.iter().for_each(|c| hasher.update(c));
+        hasher.finalize().iter().map(|b| format!("{b:02x}")).collect::<String>()
+    };
+
+    assert_eq!(digest(&[]), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(digest(&[b"abc"]), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    let two_blocks = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    assert_eq!(digest(&[two_blocks]), "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+    assert_eq!(digest(&[&two_blocks[..10], &two_blocks[10..]]), digest(&[two_blocks]), "chunking doesn't matter");
+}
+
+#[test]
+fn hash_catches_same_size_same_mtime_changes() {
+    use std::time::{Duration, SystemTime};
+
+    let master = creates_complicated_testing_tree("hash_master", None);
+    let slave = creates_complicated_testing_tree("hash_slave", None);
+    let sync = |extra: &[&str]| run(ProgramArgs::parse_from(
+        [&["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap()], extra].concat())).unwrap();
+    sync(&[]);  // align mtimes
+    let fixed = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    fs::write(slave.join("f1/b.txt"), b"HELLO WORLD").unwrap();
+    for root in [&master, &slave] {
+        fs::File::options().write(true).open(root.join("f1/b.txt")).unwrap().set_modified(fixed).unwrap();
+        fs::File::open(root.join("f1")).unwrap().set_modified(fixed).unwrap();
+    }
+    let diff = |extra: &[&str]| run(ProgramArgs::parse_from(
+        [&["filesync", "--diff", master.to_str().unwrap(), slave.to_str().unwrap()], extra].concat())).unwrap();
+
+    assert_eq!(diff(&[]), "", "size and mtime alone can't tell the files apart");
+    assert!(diff(&["--hash"]).starts_with("differing:\n  f1/b.txt: hash_sha256 "), "{}", diff(&["--hash"]));
+
+    let entry = build_manifest(&master).unwrap().into_iter().find(|e| e.path_key() == Path::new("f1/b.txt")).unwrap();
+    assert_eq!(entry.record().hash_sha256, None, "hashing is opt-in");
+
+    assert_eq!(sync(&["--hash"]), "update f1/b.txt");
+    assert_eq!(fs::read(slave.join("f1/b.txt")).unwrap(), b"hello world");
+}
+
+#[test]
+fn hash_algorithms_are_stable_and_content_specific() {
+    use crate::{discover_files, HashAlgo};
+    use std::collections::HashMap;
+    use std::time::Instant;
+
+    let root = creates_complicated_testing_tree("hash_algos", None);
+    let digests = |algo: HashAlgo| -> HashMap<PathBuf, String> {
+        let started = Instant::now();
+        let manifest = discover_files(&root, &ScanOptions { hash: Some(algo), ..Default::default() }).unwrap();
+        eprintln!("{}: hashed {} entries in {:?}", algo.name(), manifest.entries().len(), started.elapsed());
+        manifest.entries().iter()
+            .filter_map(|e| e.record().content_hash().map(|(used, digest)| {
+                assert_eq!(used, algo);
+                (e.path_key().to_path_buf(), digest.to_string())
+            }))
+            .collect()
+    };
+
+    for algo in [HashAlgo::Sha256, HashAlgo::Blake3] {
+        let first = digests(algo);
+        assert_eq!(first, digests(algo), "{} digests must be stable across runs", algo.name());
+        assert!(!first.contains_key(Path::new("f1")) && !first.contains_key(Path::new("f5/sl1")), "only regular files are hashed");
+        // same content <=> same digest
+        for (a, b) in [("f1/a.txt", "f-3/inner1"), ("f2/unicode_ハハハハハハハハハ", "ハwハwハ")] {
+            assert_eq!(first[Path::new(a)], first[Path::new(b)]);
+        }
+        assert_ne!(first[Path::new("f-3/f4/inner2")], first[Path::new("f-4/inner2")]);
+    }
+    assert_ne!(digests(HashAlgo::Sha256)[Path::new("f1/b.txt")], digests(HashAlgo::Blake3)[Path::new("f1/b.txt")]);
+
+    let master_sha = discover_files(&root, &ScanOptions { hash: Some(HashAlgo::Sha256), ..Default::default() }).unwrap();
+    let slave_blake = discover_files(&root, &ScanOptions { hash: Some(HashAlgo::Blake3), ..Default::default() }).unwrap();
+    let b_txt = |m: &Manifest| m.entries().iter().find(|e| e.path_key() == Path::new("f1/b.txt")).unwrap().clone();
+    assert!(matches!(crate::content_changes(&b_txt(&master_sha), &b_txt(&slave_blake), &Default::default()), Err(FilesyncError::HashAlgoMismatch { .. })),
+        "digests of different algorithms can't be compared");
+}
+
+#[test]
+fn renamed_files_are_detected_and_moved() {
+    let master = creates_complicated_testing_tree("rename_master", None);
+    let slave = creates_complicated_testing_tree("rename_slave", None);
+    let sync = |extra: &[&str]| run(ProgramArgs::parse_from(
+        [&["filesync", "--sync", master.to_str().unwrap(), slave.to_str().unwrap(), "--delete"], extra].concat())).unwrap();
+    let diff = |extra: &[&str]| run(ProgramArgs::parse_from(
+        [&["filesync", "--diff", master.to_str().unwrap(), slave.to_str().unwrap()], extra].concat())).unwrap();
+    sync(&[]);  // align mtimes
+    fs::create_dir(master.join("moved")).unwrap();
+    fs::rename(master.join("f1/b.txt"), master.join("moved/b.txt")).unwrap();
+    fs::File::open(master.join("f1")).unwrap().set_modified(fs::metadata(slave.join("f1")).unwrap().modified().unwrap()).unwrap();
+
+    assert_eq!(diff(&[]), "only in master:\n  moved/\n  moved/b.txt\nonly in slave:\n  f1/b.txt", "without hashes, a move looks like add + delete");
+    assert_eq!(diff(&["--hash"]), "only in master:\n  moved/\nrenamed:\n  f1/b.txt -> moved/b.txt");
+
+    assert_eq!(sync(&["--hash", "--dry-run"]), "mkdir moved/\nrename f1/b.txt -> moved/b.txt");
+    assert_eq!(sync(&["--hash"]), "mkdir moved/\nrename f1/b.txt -> moved/b.txt");
+    assert!(!slave.join("f1/b.txt").exists());
+    assert_eq!(fs::read(slave.join("moved/b.txt")).unwrap(), b"hello world");
+    assert_eq!(diff(&["--hash"]), "");
 }
 
 /// tracking with specific prefixes (rather than all files)
@@ -98,6 +1591,39 @@ fn test_picked_track_scans() {
 }
 
 
+#[test]
+fn only_restricts_track_diff_and_sync() {
+    let master = creates_complicated_testing_tree("only_master", None);
+    let track = |only: &[&str]| {
+        let args = [&["filesync", "--track", master.to_str().unwrap()], only].concat();
+        read_tracking_file_into_filepaths(Path::new(&run(ProgramArgs::parse_from(args)).unwrap())).unwrap()
+    };
+
+    let paths = track(&["--only", "f2/"]);
+    assert!(paths.contains(&"f2/".to_string()) && paths.contains(&"f2/a.txt".to_string()), "{paths:?}");
+    assert!(paths.iter().all(|p| p.starts_with("f2/")), "{paths:?}");
+    assert_eq!(track(&["--only", "f-3/f4", "--prefix", "f1"]), ["f-3/f4/", "f-3/f4/inner2", "f1/", "f1/a.txt", "f1/b.txt"],
+        "a nested subdir's parents aren't recorded; several subdirs are combined");
+    assert!(track(&["--only", "f"]).is_empty(), "subdirs are matched by whole path components");
+
+    let slave = define_tmp_dir("only_slave");
+    let _ = fs::remove_dir_all(&slave);
+    create_entry(&slave, "f1/outside.txt", b"not in master");
+    let run_on_both = |mode: &str, extra: &[&str]| run(ProgramArgs::parse_from(
+        [&["filesync", mode, master.to_str().unwrap(), slave.to_str().unwrap(), "--only", "f2/"], extra].concat())).unwrap();
+
+    let diff = run_on_both("--diff", &[]);
+    assert!(diff.starts_with("only in master:\n  f2/\n"), "{diff}");
+    assert!(!diff.contains("f1/"), "{diff}");
+
+    let sync = run_on_both("--sync", &["--delete"]);
+    assert!(sync.contains("copy f2/a.txt") && !sync.contains("f1/"), "{sync}");
+    assert!(slave.join("f1/outside.txt").exists(), "--delete leaves paths outside of --only alone");
+    assert_eq!(fs::read(slave.join("f2/a.txt")).unwrap(), b"another a");
+    assert!(!slave.join("f1/a.txt").exists());
+    assert_eq!(run_on_both("--diff", &[]), "");
+}
+
 #[test]
 fn detect_differences_between_filetrees() {
     // need to read only paths because the creation date would be different
@@ -112,13 +1638,13 @@ fn detect_differences_between_filetrees() {
 /// returns the path of the newly created tracking file
 fn create_tree_and_tracker(subdir: &str, extra: Option<&[String]>) -> PathBuf {
     let new_dir = creates_complicated_testing_tree(subdir, extra);
-    write_tracking_file_with_content(&new_dir, None)
+    write_tracking_file_with_content(&new_dir, &ScanOptions::default()).unwrap()
 }
 
 /// returns the newly made and listed files within the new tracking file
 fn create_tree_and_tracker_and_read_paths(subdir: &str, extra: Option<&[String]>) -> Vec<String> {
     let tracker_filepath = create_tree_and_tracker(subdir, extra);
-    read_tracking_file_into_filepaths(&tracker_filepath)
+    read_tracking_file_into_filepaths(&tracker_filepath).unwrap()
 }
 
 
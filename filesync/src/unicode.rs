@@ -0,0 +1,111 @@
+use crate::unicode_tables::{COMBINING_CLASSES, COMPOSITIONS, DECOMPOSITIONS};
+use std::borrow::Cow;
+
+
+/// `s` in Normalization Form C (UAX #15): canonically decomposed, combining marks put in order, then recomposed.
+/// Names on macOS are stored decomposed (NFD) and most others composed, so this is what lets them match
+pub fn nfc(s: &str) -> Cow<'_, str> {
+    if s.chars().all(|c| (c as u32) < 0x300) { return Cow::Borrowed(s); }  // nothing below U+0300 combines or decomposes
+
+    let mut chars = Vec::with_capacity(s.len());
+    for c in s.chars() { decompose(c, &mut chars); }
+    reorder(&mut chars);
+    compose(&mut chars);
+    let composed: String = chars.into_iter().collect();
+    match composed == s {
+        true => Cow::Borrowed(s),
+        false => Cow::Owned(composed),
+    }
+}
+
+
+// Hangul syllables are laid out arithmetically (Unicode ch. 3.12), instead of being listed in the tables
+const S_BASE: u32 = 0xAC00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11A7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const N_COUNT: u32 = V_COUNT * T_COUNT;
+const S_COUNT: u32 = L_COUNT * N_COUNT;
+
+fn decompose(c: char, out: &mut Vec<char>) {
+    let code = c as u32;
+    if (S_BASE..S_BASE + S_COUNT).contains(&code) {
+        let index = code - S_BASE;
+        out.push(char_at(L_BASE + index / N_COUNT));
+        out.push(char_at(V_BASE + (index % N_COUNT) / T_COUNT));
+        if !index.is_multiple_of(T_COUNT) { out.push(char_at(T_BASE + index % T_COUNT)); }
+        return;
+    }
+    match DECOMPOSITIONS.binary_search_by_key(&code, |&(from, _, _)| from) {
+        Ok(i) => {
+            let (_, first, second) = DECOMPOSITIONS[i];
+            decompose(char_at(first), out);  // decompositions can nest, but only in their first character
+            if second != 0 { out.push(char_at(second)); }
+        },
+        Err(_) => out.push(c),
+    }
+}
+
+fn combining_class(c: char) -> u8 {
+    let code = c as u32;
+    match COMBINING_CLASSES.binary_search_by(|&(start, end, _)| {
+        if end < code { std::cmp::Ordering::Less } else if start > code { std::cmp::Ordering::Greater } else { std::cmp::Ordering::Equal }
+    }) {
+        Ok(i) => COMBINING_CLASSES[i].2,
+        Err(_) => 0,
+    }
+}
+
+/// Sort each run of combining marks by class, keeping marks of the same class in order (the Canonical Ordering Algorithm)
+fn reorder(chars: &mut [char]) {
+    let mut start = 0;
+    while start < chars.len() {
+        let len = chars[start..].iter().take_while(|&&c| combining_class(c) != 0).count();
+        chars[start..start + len].sort_by_key(|&c| combining_class(c));  // a stable sort
+        start += len.max(1);
+    }
+}
+
+/// Canonical composition: every mark that isn't blocked from its starter (by an earlier mark of the same or a higher class)
+/// is combined with it, where there's a primary composite for the pair
+fn compose(chars: &mut Vec<char>) {
+    let mut out: Vec<char> = Vec::with_capacity(chars.len());
+    let mut starter: Option<usize> = None;  // index in `out`
+    let mut last_class: Option<u8> = None;  // of the last character since the starter that wasn't combined into it
+    for &c in chars.iter() {
+        let class = combining_class(c);
+        if let Some(s) = starter {
+            let blocked = last_class.is_some_and(|last| last == 0 || last >= class);
+            if let Some(composite) = composite(out[s], c).filter(|_| !blocked) {
+                out[s] = composite;
+                continue;
+            }
+        }
+        if class == 0 {
+            starter = Some(out.len());
+            last_class = None;
+        } else {
+            last_class = Some(class);
+        }
+        out.push(c);
+    }
+    *chars = out;
+}
+
+fn composite(first: char, second: char) -> Option<char> {
+    let (a, b) = (first as u32, second as u32);
+    if (L_BASE..L_BASE + L_COUNT).contains(&a) && (V_BASE..V_BASE + V_COUNT).contains(&b) {
+        return Some(char_at(S_BASE + ((a - L_BASE) * V_COUNT + (b - V_BASE)) * T_COUNT));
+    }
+    if (S_BASE..S_BASE + S_COUNT).contains(&a) && (a - S_BASE).is_multiple_of(T_COUNT) && (T_BASE + 1..T_BASE + T_COUNT).contains(&b) {
+        return Some(char_at(a + (b - T_BASE)));
+    }
+    COMPOSITIONS.binary_search_by_key(&(a, b), |&(pair, _)| pair).ok().map(|i| char_at(COMPOSITIONS[i].1))
+}
+
+fn char_at(code: u32) -> char {
+    char::from_u32(code).expect("the normalization tables only hold valid characters")
+}
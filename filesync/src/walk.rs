@@ -0,0 +1,107 @@
+use rayon::prelude::*;
+use std::ffi::OsStr;
+use std::fs::{self, FileType};
+use std::io;
+use std::path::{Path, PathBuf};
+
+
+/// Something found below the walked root. With `follow_links`, a symlink's `file_type` is that of what it points to
+pub struct WalkEntry {
+    pub path: PathBuf,
+    /// 1 for the root's direct children
+    pub depth: usize,
+    pub file_type: FileType,
+}
+
+impl WalkEntry {
+    pub fn file_name(&self) -> &OsStr { self.path.file_name().unwrap_or_default() }
+}
+
+/// A directory that couldn't be listed (which still is itself), or (with `follow_links`) a symlink that couldn't be followed: broken, or looping back to
+/// a directory it's inside of. The root's own failures have depth 0
+pub struct WalkError {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub source: io::Error,
+}
+
+/// Every entry below `root` (not the root itself), parents before their children, each directory's entries in the order the
+/// filesystem lists them. Sibling directories are listed in parallel, on rayon's pool.
+/// An entry `keep` returns false for is left out, and if it's a directory, so is everything inside it.
+/// Nothing deeper than `max_depth` is visited. A symlinked root is always followed
+pub fn walk(root: &Path, follow_links: bool, max_depth: usize, keep: impl Fn(&WalkEntry) -> bool + Sync) -> Vec<Result<WalkEntry, WalkError>> {
+    let root_error = |source| vec![Err(WalkError { path: root.to_path_buf(), depth: 0, source })];
+    match fs::metadata(root) {
+        Err(source) => root_error(source),
+        Ok(md) if !md.is_dir() => Vec::new(),
+        Ok(md) => {
+            let walker = Walker { follow_links, max_depth, keep };
+            let ancestors = if follow_links { dir_id(&md).into_iter().collect() } else { Vec::new() };
+            walker.list(root, 0, &ancestors).unwrap_or_else(|err| vec![Err(err)])
+        },
+    }
+}
+
+/// The (device, inode) a directory is told apart by, for spotting symlink loops.
+/// None where there's no such thing, which leaves loops to fail once their paths get too long
+#[cfg(unix)]
+fn dir_id(md: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((md.dev(), md.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_id(_md: &fs::Metadata) -> Option<(u64, u64)> { None }
+
+struct Walker<F> {
+    follow_links: bool,
+    max_depth: usize,
+    keep: F,
+}
+
+impl<F: Fn(&WalkEntry) -> bool + Sync> Walker<F> {
+
+    /// The entries below `dir` (which is at `depth`). `ancestors` are the (device, inode) of `dir` and the directories above it,
+    /// which are only tracked when following links, since only a symlink can lead back up
+    fn list(&self, dir: &Path, depth: usize, ancestors: &[(u64, u64)]) -> Result<Vec<Result<WalkEntry, WalkError>>, WalkError> {
+        if depth >= self.max_depth { return Ok(Vec::new()); }
+        let children = fs::read_dir(dir)
+            .map_err(|source| WalkError { path: dir.to_path_buf(), depth, source })?
+            .map(|child| self.entry(child, dir, depth + 1, ancestors))
+            .collect::<Vec<_>>();
+
+        Ok(children.into_par_iter()
+            .flat_map_iter(|child| {
+                let entry = match child {
+                    Ok(entry) if (self.keep)(&entry) => entry,
+                    Ok(_) => return Vec::new(),
+                    Err(err) => return vec![Err(err)],
+                };
+                let below = match entry.file_type.is_dir() {
+                    true if self.follow_links => fs::metadata(&entry.path)
+                        .map_err(|source| WalkError { path: entry.path.clone(), depth: entry.depth, source })
+                        .and_then(|md| self.list(&entry.path, entry.depth, &[ancestors, &Vec::from_iter(dir_id(&md))].concat())),
+                    true => self.list(&entry.path, entry.depth, ancestors),
+                    false => Ok(Vec::new()),
+                };
+                let below = below.unwrap_or_else(|err| vec![Err(err)]);
+                std::iter::once(Ok(entry)).chain(below).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>())
+    }
+
+    fn entry(&self, child: io::Result<fs::DirEntry>, dir: &Path, depth: usize, ancestors: &[(u64, u64)]) -> Result<WalkEntry, WalkError> {
+        let child = child.map_err(|source| WalkError { path: dir.to_path_buf(), depth, source })?;
+        let path = child.path();
+        let fail = |source| WalkError { path: path.clone(), depth, source };
+        let mut file_type = child.file_type().map_err(fail)?;
+        if self.follow_links && file_type.is_symlink() {
+            let target = fs::metadata(&path).map_err(fail)?;
+            if target.is_dir() && dir_id(&target).is_some_and(|id| ancestors.contains(&id)) {
+                return Err(fail(io::Error::other("symlink loops back to a directory it's inside of")));
+            }
+            file_type = target.file_type();
+        }
+        Ok(WalkEntry { path, depth, file_type })
+    }
+}
@@ -0,0 +1,217 @@
+use crate::error::{IoContext, Result};
+use crate::sync::{SyncObserver, SyncOptions, SyncStats};
+use crate::walk::walk;
+use crate::{sync_trees, ScanOptions};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+
+/// How long a changed path has to stay quiet before it's synced, so a file being written is copied once it's done
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+/// How often the trees are rescanned when they can't be watched (see [`watch`])
+pub const RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+/// How long a wait for events lasts at most, which is also how soon `stop` is noticed
+const TICK: Duration = Duration::from_millis(100);
+
+/// Keep `slave` in sync with `master` until `stop` is set, `report`ing each sync that did anything.
+/// Meant to follow a full sync: from then on, only where inotify reports master paths as created, changed, removed or renamed
+/// is synced again, each once it's been quiet for [`DEBOUNCE`]. That's their parent directories, as if they had been given
+/// to `--only` (since the parent's mtime changes with them), or the whole trees with a rename map, which can put them anywhere
+/// in the slave.
+/// Where master can't be watched (not on Linux, or once the inotify watch limit is reached) a warning is printed, and both
+/// trees are rescanned and synced whole every [`RESCAN_INTERVAL`] instead. A failed sync is reported as a warning, and
+/// watching goes on
+pub fn watch(master: &Path, slave: &Path, scan: &ScanOptions, sync: &SyncOptions, observer: &dyn SyncObserver, stop: &AtomicBool,
+             mut report: impl FnMut(Vec<String>, SyncStats)) -> Result<()> {
+    let trees = Trees { master, slave, sync, observer };
+    let mut changed: HashMap<PathBuf, Instant> = HashMap::new();  // dirs, and when something in them was last reported
+    let mut watcher = match Inotify::new().and_then(|mut w| w.add_tree(master, Path::new(""), scan).map(|_| w)) {
+        Ok(watcher) => watcher,
+        Err(err) => return rescan_fallback(&trees, scan, stop, report, &err, RESCAN_INTERVAL),
+    };
+
+    while !stop.load(Ordering::Relaxed) {
+        for event in watcher.read(TICK).context(|| format!("failed to watch '{}'", master.display()))? {
+            if event.overflowed { changed.insert(PathBuf::new(), Instant::now()); continue; }  // events were lost: recheck everything
+            if event.new_dir {
+                if let Err(err) = watcher.add_tree(master, &event.path, scan) {
+                    return rescan_fallback(&trees, scan, stop, report, &err, RESCAN_INTERVAL);
+                }
+            }
+            changed.insert(event.path.parent().map(Path::to_path_buf).unwrap_or_default(), Instant::now());
+        }
+
+        let settled: Vec<PathBuf> = changed.iter().filter(|(_, at)| at.elapsed() >= DEBOUNCE).map(|(rel, _)| rel.clone()).collect();
+        if settled.is_empty() { continue; }
+        settled.iter().for_each(|rel| { changed.remove(rel); });
+        let scan = match narrowed(&settled, scan) {
+            _ if !sync.rename_map.is_empty() => scan.clone(),
+            only if only.is_empty() => continue,
+            only => ScanOptions { only, ..scan.clone() },
+        };
+        trees.sync(&scan, &mut report);
+    }
+    Ok(())
+}
+
+/// What [`watch`] does where master can't be watched
+pub(crate) fn rescan_fallback(trees: &Trees, scan: &ScanOptions, stop: &AtomicBool, mut report: impl FnMut(Vec<String>, SyncStats),
+                              why: &io::Error, interval: Duration) -> Result<()> {
+    eprintln!("warning: can't watch '{}' for changes ({why}), rescanning it every {}s instead", trees.master.display(), interval.as_secs_f64());
+    let mut last = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        std::thread::sleep(TICK.min(interval));
+        if last.elapsed() < interval { continue; }
+        trees.sync(scan, &mut report);
+        last = Instant::now();
+    }
+    Ok(())
+}
+
+/// The two sides of a [`watch`], and how to sync them
+pub(crate) struct Trees<'a> {
+    pub master: &'a Path,
+    pub slave: &'a Path,
+    pub sync: &'a SyncOptions,
+    pub observer: &'a dyn SyncObserver,
+}
+
+impl Trees<'_> {
+    fn sync(&self, scan: &ScanOptions, report: &mut impl FnMut(Vec<String>, SyncStats)) {
+        match sync_trees(self.master, self.slave, scan, self.sync, None::<io::Stderr>, self.observer) {
+            Ok((log, _)) if log.is_empty() => {},
+            Ok((log, stats)) => report(log, stats),
+            Err(err) => eprintln!("warning: sync failed: {err}"),
+        }
+    }
+}
+
+/// The changed dirs as `--only` subdirs, kept within the user's own `--only` ones:
+/// a path that leads to some of those stands for them, one outside all of them is dropped
+fn narrowed(changed: &[PathBuf], scan: &ScanOptions) -> Vec<PathBuf> {
+    let mut only: Vec<PathBuf> = changed.iter()
+        .flat_map(|rel| match scan.only.is_empty() || scan.is_selected(rel) {
+            true => vec![rel.clone()],
+            false => scan.only.iter().filter(|p| p.starts_with(rel)).cloned().collect(),
+        })
+        .collect();
+    only.sort();
+    only.dedup();
+    only
+}
+
+
+/// Something inotify reported about master, with `path` relative to it
+struct Event {
+    path: PathBuf,
+    /// A directory was created or moved in, which needs watching too
+    new_dir: bool,
+    /// The kernel's event queue overflowed, so some changes are unknown
+    overflowed: bool,
+}
+
+#[cfg(target_os = "linux")]
+struct Inotify {
+    fd: std::os::fd::OwnedFd,
+    dirs: HashMap<i32, PathBuf>,  // watch descriptor -> watched dir, relative to master
+}
+
+#[cfg(target_os = "linux")]
+impl Inotify {
+    const MASK: u32 = libc::IN_CREATE | libc::IN_DELETE | libc::IN_MODIFY | libc::IN_ATTRIB | libc::IN_CLOSE_WRITE
+        | libc::IN_MOVED_FROM | libc::IN_MOVED_TO | libc::IN_ONLYDIR;
+
+    fn new() -> io::Result<Self> {
+        use std::os::fd::FromRawFd;
+        // SAFETY: no pointers involved; a non-negative result is a descriptor that nothing else owns
+        match unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) } {
+            -1 => Err(io::Error::last_os_error()),
+            fd => Ok(Inotify { fd: unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) }, dirs: HashMap::new() }),
+        }
+    }
+
+    /// Watch `rel` (under `master`) and every directory inside it. Fails with the kernel's error once the watch limit
+    /// (`fs.inotify.max_user_watches`) is reached; directories that vanish in the meantime are silently left out
+    fn add_tree(&mut self, master: &Path, rel: &Path, scan: &ScanOptions) -> io::Result<()> {
+        let top = master.join(rel);
+        let below = walk(&top, scan.follow_links, usize::MAX, |e| e.file_type.is_dir());
+        for dir in std::iter::once(top.clone()).chain(below.into_iter().filter_map(|e| e.ok().map(|e| e.path))) {
+            match self.add(&dir, scan.follow_links) {
+                Ok(wd) => { self.dirs.insert(wd, dir.strip_prefix(master).unwrap_or(&dir).to_path_buf()); },
+                Err(err) if matches!(err.kind(), io::ErrorKind::NotFound | io::ErrorKind::NotADirectory) => {},
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&self, dir: &Path, follow_links: bool) -> io::Result<i32> {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).map_err(io::Error::other)?;
+        let mask = if follow_links { Self::MASK } else { Self::MASK | libc::IN_DONT_FOLLOW };
+        // SAFETY: `path` is NUL-terminated and outlives the call
+        match unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), mask) } {
+            -1 => Err(io::Error::last_os_error()),
+            wd => Ok(wd),
+        }
+    }
+
+    /// The events that arrive within `timeout` (none, if nothing happens)
+    fn read(&mut self, timeout: Duration) -> io::Result<Vec<Event>> {
+        use std::os::fd::AsRawFd;
+        let mut poll = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        // SAFETY: `poll` is a single valid pollfd for the whole call
+        match unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => return Ok(Vec::new()),
+            -1 => return Err(io::Error::last_os_error()),
+            0 => return Ok(Vec::new()),
+            _ => {},
+        }
+
+        let mut buf = vec![0u8; 64 * 1024];
+        // SAFETY: reads into `buf`, which is as long as we say
+        let len = match unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock => return Ok(Vec::new()),
+            -1 => return Err(io::Error::last_os_error()),
+            len => len as usize,
+        };
+
+        // each record: wd (i32), mask, cookie, name length (u32s), then the NUL-padded name
+        let field = |at: usize| u32::from_ne_bytes(buf[at..at + 4].try_into().unwrap());
+        let mut events = Vec::new();
+        let mut at = 0;
+        while at + 16 <= len {
+            let (wd, mask, name_len) = (field(at) as i32, field(at + 4), field(at + 12) as usize);
+            let name = &buf[at + 16..at + 16 + name_len];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            at += 16 + name_len;
+
+            if mask & libc::IN_Q_OVERFLOW != 0 {
+                events.push(Event { path: PathBuf::new(), new_dir: false, overflowed: true });
+                continue;
+            }
+            if mask & libc::IN_IGNORED != 0 { self.dirs.remove(&wd); continue; }  // its dir is gone
+            let Some(dir) = self.dirs.get(&wd) else { continue };
+            use std::os::unix::ffi::OsStrExt;
+            let path = dir.join(std::ffi::OsStr::from_bytes(name));
+            let new_dir = mask & libc::IN_ISDIR != 0 && mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0;
+            events.push(Event { path, new_dir, overflowed: false });
+        }
+        Ok(events)
+    }
+}
+
+/// Stands in where there's no inotify, so that [`watch`] always falls back to rescanning
+#[cfg(not(target_os = "linux"))]
+struct Inotify;
+
+#[cfg(not(target_os = "linux"))]
+impl Inotify {
+    fn new() -> io::Result<Self> { Err(io::Error::from(io::ErrorKind::Unsupported)) }
+    fn add_tree(&mut self, _master: &Path, _rel: &Path, _scan: &ScanOptions) -> io::Result<()> { unreachable!() }
+    fn read(&mut self, _timeout: Duration) -> io::Result<Vec<Event>> { unreachable!() }
+}
@@ -1,40 +1,398 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser, ValueEnum};
 use rayon::prelude::*;
 use regex::Regex;
-use std::io::{self, BufRead, BufReader, Read};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write as IoWrite};
 use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::LazyLock;
 use itertools::izip;
 use std::fmt::Write;
 use std::iter::repeat;
+use std::cmp::Ordering;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use ordered_float::OrderedFloat;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 
 // ——— Configuration ——————————————————————————————
 const DEFAULT_SEPARATOR: usize = 2;
+const DEFAULT_TAB_WIDTH: usize = 8;
+const DEFAULT_TOTALS_LABEL: &str = "TOTAL";
+const DEFAULT_BAR_WIDTH: usize = 20;
+const BAR_EIGHTHS: [&str; 8] = ["", "▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+const OUTPUT_BUFFER_SIZE: usize = 1 << 16;  // flush output in large chunks rather than per line
 
 // Regular expression patterns
 static SPLIT_PATTERN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s{2,}|\t+").unwrap());
 static NUMERIC_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"^[+-]?[0-9]+(?:\.[0-9]+)?\s?[pKkMmGgTt]?(?:i?[bB]?(/s)?|%|Hz|@[0-9]+Hz)?$").unwrap()
+    Regex::new(r"^(?:[+-]?0[xX][0-9A-Fa-f]+|[+-]?[0-9]+(?:\.[0-9]+)?(?:[eE][+-]?[0-9]+)?\s?(?:m?s|[pKkMmGgTt]?(?:i?[bB]?(/s)?|%|Hz|@[0-9]+Hz)?))$").unwrap()
 });
 
+const BOX_SPACER: &str = " │ ";
+const CURRENCY_SYMBOLS: &[char] = &['$', '€', '£', '¥'];
+// Approximation/comparison markers that --loose-numeric ignores in front of a number (`~5ms`, `>100`, `<=1%`)
+const LOOSE_NUMERIC_MARKERS: &[char] = &['~', '≈', '<', '>'];
+
+/// How cells are placed within their column
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+    /// Numeric columns to the right, everything else to the left
+    #[default]
+    Auto,
+}
+
+/// Direction of `--sort`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Whether the output has colors: those of the input cells, and our own (`--heatmap`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorMode {
+    /// Only when printing to a terminal (and NO_COLOR isn't set)
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// How `--group-by` combines a column's numbers within a group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    /// Number of rows in the group (whichever the column)
+    Count,
+}
+
+impl Aggregate {
+    fn name(self) -> &'static str {
+        match self {
+            Aggregate::Sum => "sum",
+            Aggregate::Avg => "avg",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+            Aggregate::Count => "count",
+        }
+    }
+
+    /// The aggregate of a group's numbers (None if there are none to aggregate)
+    fn apply(self, rows: usize, values: &[f64]) -> Option<f64> {
+        match self {
+            Aggregate::Count => Some(rows as f64),
+            _ if values.is_empty() => None,
+            Aggregate::Sum => Some(values.iter().sum()),
+            Aggregate::Avg => Some(values.iter().sum::<f64>() / values.len() as f64),
+            Aggregate::Min => values.iter().copied().reduce(f64::min),
+            Aggregate::Max => values.iter().copied().reduce(f64::max),
+        }
+    }
+}
+
+/// What the formatted table is rendered as
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Space-aligned columns
+    #[default]
+    Plain,
+    /// GitHub-flavored Markdown table (first row is the header)
+    Markdown,
+    /// Comma-separated values (RFC 4180 quoting), without any alignment padding
+    Csv,
+    /// Aligned columns framed with box-drawing characters, with a rule under the header row
+    Box,
+    /// JSON Lines: an object per data row, keyed by the header's cells (numeric columns as numbers)
+    Json,
+}
+
+impl OutputFormat {
+    /// Whether cells get padded into columns at all
+    fn is_aligned(self) -> bool {
+        !matches!(self, OutputFormat::Csv | OutputFormat::Json)
+    }
+
+    /// Whether `--stream` windows can follow each other, as opposed to each repeating (or cutting off) a frame or header
+    fn is_streamable(self) -> bool {
+        matches!(self, OutputFormat::Plain | OutputFormat::Json)
+    }
+}
+
+/// Settings for parsing and formatting a table
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// Number of spaces between columns
+    pub separator: usize,
+    /// Columns to sort by, each breaking ties of the previous one (header row is kept on top)
+    pub sort: Vec<usize>,
+    /// Sort direction; unset means numeric columns descending and text ascending
+    pub sort_order: Option<SortOrder>,
+    /// Treat values with a leading `~`, `≈`, `<` or `>` as numbers
+    pub loose_numeric: bool,
+    /// Total line width to fit the table into, truncating the widest columns
+    pub width: Option<usize>,
+    /// When fitting into `width`, give the room left over by narrow columns back to the truncated ones
+    pub two_pass_widths: bool,
+    /// Cell placement within columns
+    pub align: Align,
+    /// Output rendering
+    pub format: OutputFormat,
+    /// Split cells on each occurrence of this character, instead of on whitespace runs
+    pub delimiter: Option<char>,
+    /// Line up the decimal points of numeric columns
+    pub decimal_align: bool,
+    /// Visible width beyond which a cell is cut short with '…'
+    pub max_col_width: Option<usize>,
+    /// Continue cells wider than `max_col_width` on extra lines instead of cutting them
+    pub wrap: bool,
+    /// Separator to group the thousands of numeric columns with
+    pub group_digits: Option<char>,
+    /// Indices of the columns to print, in this order (all of them if empty)
+    pub columns: Vec<usize>,
+    /// Input columns to move to the front, in this order (applied while splitting, so other indices see the new layout)
+    pub reorder: Vec<usize>,
+    /// Swap rows and columns before anything else looks at them
+    pub transpose: bool,
+    /// Lines starting with this (after indentation) are printed as they are, and don't count as rows
+    pub comment: Option<String>,
+    /// What separates cells, instead of `SPLIT_PATTERN` (ignored when there's a `delimiter`)
+    pub split_regex: Option<Regex>,
+    /// Underline the first row with dashes (plain format)
+    pub header_rule: bool,
+    /// Sort text by comparing digit runs as numbers (`file2` before `file10`)
+    pub natural_sort: bool,
+    /// Rows with equal sort keys keep their input order, in either direction
+    pub stable: bool,
+    /// The first row is data like the rest, rather than a header
+    pub no_header: bool,
+    /// Numbers are written like `1.234,56`: `,` is the decimal point, and `.` groups thousands
+    pub decimal_comma: bool,
+    /// Color the cells of this numeric column from green (smallest) to red (largest)
+    pub heatmap: Option<usize>,
+    /// Prepend a column of row numbers (the header gets `#`, blank rows get none)
+    pub number: bool,
+    /// Number the rows in their input order rather than in the (sorted) output order
+    pub number_before_sort: bool,
+    /// Character ranges (`start`, inclusive `end` or to the end of line) to cut each line into cells at, instead of splitting it
+    pub fixed_cols: Vec<(usize, Option<usize>)>,
+    /// Tab stops, for tabs within cells (0 keeps tabs as they are)
+    pub tab_width: usize,
+    /// Sort text without regard to case (`apple` before `Banana`)
+    pub sort_ignore_case: bool,
+    /// Print the data rows in reverse order (after sorting), keeping the header on top
+    pub reverse: bool,
+    /// Append a row with the sum of each numeric column (after sorting)
+    pub totals: bool,
+    /// What the totals row says in its leftmost non-numeric column
+    pub totals_label: String,
+    /// Append a column of bars charting this numeric column
+    pub bar: Option<usize>,
+    /// Width of the longest bar
+    pub bar_width: usize,
+    /// Keep only the first N data rows (after sorting)
+    pub head: Option<usize>,
+    /// Keep only the last N data rows (after sorting)
+    pub tail: Option<usize>,
+    /// Drop the escape codes (colors) from the cells as soon as they're split off
+    pub strip_colors: bool,
+    /// Spaces after each column, overriding `separator` for the first few
+    pub sep_spec: Vec<usize>,
+    /// Print the columns right-to-left, the last one first
+    pub rtl: bool,
+    /// Collapse rows sharing a value in this column into one row per value
+    pub group_by: Option<usize>,
+    /// What a group's row holds besides its key: aggregates of these columns (the row count if empty)
+    pub aggregates: Vec<(Aggregate, usize)>,
+    /// Never truncate or wrap the first column (the first printed one, `number`'s aside)
+    pub pin_first_col: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            separator: DEFAULT_SEPARATOR,
+            sort: vec![],
+            sort_order: None,
+            loose_numeric: false,
+            width: None,
+            two_pass_widths: false,
+            align: Align::Auto,
+            format: OutputFormat::Plain,
+            delimiter: None,
+            decimal_align: false,
+            max_col_width: None,
+            wrap: false,
+            group_digits: None,
+            columns: vec![],
+            reorder: vec![],
+            transpose: false,
+            comment: None,
+            split_regex: None,
+            header_rule: false,
+            natural_sort: false,
+            stable: false,
+            no_header: false,
+            decimal_comma: false,
+            heatmap: None,
+            number: false,
+            number_before_sort: false,
+            fixed_cols: vec![],
+            tab_width: DEFAULT_TAB_WIDTH,
+            sort_ignore_case: false,
+            reverse: false,
+            totals: false,
+            totals_label: DEFAULT_TOTALS_LABEL.to_string(),
+            bar: None,
+            bar_width: DEFAULT_BAR_WIDTH,
+            head: None,
+            tail: None,
+            strip_colors: false,
+            sep_spec: vec![],
+            rtl: false,
+            group_by: None,
+            aggregates: vec![],
+            pin_first_col: false,
+        }
+    }
+}
+
 // ——— Utilities ——————————————————————————————————————
 pub fn strip_ansi(text: &str) -> String {
     console::strip_ansi_codes(text).to_string()
 }
 
+/// Columns the text takes up in a terminal, escape sequences aside, measured grapheme by grapheme
 pub fn visible_len(text: &str) -> usize {
-    console::measure_text_width(&console::strip_ansi_codes(text))
+    console::strip_ansi_codes(text).graphemes(true).map(grapheme_width).sum()
+}
+
+/// Terminals draw an emoji sequence (ZWJ family, flag, `❤️`) as one double-width glyph,
+/// and combining marks don't take up room of their own
+fn grapheme_width(grapheme: &str) -> usize {
+    let mut chars = grapheme.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c.width().unwrap_or(0),
+        _ if grapheme.contains(['\u{200D}', '\u{FE0F}']) => 2,
+        _ => grapheme.width().min(2),
+    }
 }
 
 pub fn is_numeric_or_neutral(text: &str) -> bool {
     let clean = strip_ansi(text);
     let clean = clean.trim();
     matches!(clean, "" | "-" | "--" | "---" | "*" | "−" | "=" | "y" | "n" | "?")
-        || NUMERIC_PATTERN.is_match(clean)
+        || is_number(clean)
+}
+
+fn is_number(text: &str) -> bool {
+    NUMERIC_PATTERN.is_match(text)
+        || strip_currency(text).or_else(|| strip_parentheses(text)).is_some_and(|number| NUMERIC_PATTERN.is_match(&number))
+}
+
+/// The number in a currency value like `$1,234.50` or `-€99` (with its thousands commas dropped),
+/// or None if `text` isn't one
+fn strip_currency(text: &str) -> Option<String> {
+    let unsigned = text.trim_start_matches(['+', '-']);
+    let sign = &text[..text.len() - unsigned.len()];
+    let number = unsigned.strip_prefix(CURRENCY_SYMBOLS)?.trim_start();
+    without_thousands_commas(number).map(|number| format!("{sign}{number}"))
 }
 
+/// The negative number in an accounting-style value like `(1,234)` or `($99.50)`, or None if `text` isn't one
+fn strip_parentheses(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('(')?.strip_suffix(')')?.trim();
+    if inner.starts_with(['+', '-']) { return None; }
+    let number = strip_currency(inner).or_else(|| without_thousands_commas(inner))?;
+    Some(format!("-{number}"))
+}
+
+/// `number` without its commas, if they group the integer part by thousands
+fn without_thousands_commas(number: &str) -> Option<String> {
+    let integer = &number[..number.find(|c: char| !c.is_ascii_digit() && c != ',').unwrap_or(number.len())];
+    let mut groups = integer.split(',');
+    let well_grouped = groups.next().is_some_and(|first| !first.is_empty() && (!integer.contains(',') || first.len() <= 3))
+        && groups.all(|group| group.len() == 3);
+    well_grouped.then(|| number.replace(',', ""))
+}
+
+
+/// Drop a leading approximation/comparison marker, such as in `~5ms`, `>100` or `<=1%`
+fn strip_loose_markers(text: &str) -> &str {
+    let text = text.trim();
+    match text.strip_prefix(LOOSE_NUMERIC_MARKERS) {
+        Some(rest) => rest.strip_prefix('=').unwrap_or(rest).trim_start(),
+        None => text,
+    }
+}
+
+/// Undo `--group-digits` (so that `80,000` reads as 80000 again) and `--decimal-comma` (`1.234,5` → `1234.5`)
+fn ungrouped<'a>(cell: &'a str, options: &FormatOptions) -> Cow<'a, str> {
+    let cell = match options.group_digits {
+        Some(separator) if cell.contains(separator) => Cow::Owned(cell.replace(separator, "")),
+        _ => Cow::Borrowed(cell),
+    };
+    match options.decimal_comma.then(|| from_decimal_comma(&cell)).flatten() {
+        Some(converted) => Cow::Owned(converted),
+        None => cell,
+    }
+}
+
+/// `1.234.567,89` → `1234567.89`, if every `.` groups thousands and there's at most one `,`
+fn from_decimal_comma(cell: &str) -> Option<String> {
+    if !cell.contains([',', '.']) { return None; }
+    let bytes = cell.as_bytes();
+    let is_digit = |i: usize| bytes.get(i).is_some_and(u8::is_ascii_digit);
+    let grouping_dots = cell.match_indices('.')
+        .all(|(i, _)| i > 0 && is_digit(i - 1) && (i + 1..i + 4).all(is_digit) && !is_digit(i + 4));
+    (grouping_dots && cell.matches(',').count() <= 1).then(|| cell.replace('.', "").replacen(',', ".", 1))
+}
+
+/// Insert `separator` between each group of 3 digits of a cell's integer part (`288000M` → `288,000M`).
+/// Escape sequences, signs and suffixes are left alone, and so are hex numbers.
+fn group_integer_part(cell: &str, separator: char) -> String {
+    let mut out = String::with_capacity(cell.len() + cell.len() / 3);
+    let mut grouped = false;
+
+    for (part, is_ansi) in console::AnsiCodeIterator::new(cell) {
+        let Some(start) = part.find(|c: char| c.is_ascii_digit()).filter(|_| !is_ansi && !grouped) else {
+            out.push_str(part);
+            continue;
+        };
+        grouped = true;
+        let end = part[start..].find(|c: char| !c.is_ascii_digit()).map_or(part.len(), |len| start + len);
+        let (digits, rest) = (&part[start..end], &part[end..]);
+        if rest.starts_with(['x', 'X']) { out.push_str(part); continue; }
+
+        out.push_str(&part[..start]);
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 { out.push(separator); }
+            out.push(digit);
+        }
+        out.push_str(rest);
+    }
+    out
+}
+
+fn cell_is_numeric(cell: &str, options: &FormatOptions) -> bool {
+    let cell = &ungrouped(cell, options);
+    if options.loose_numeric { is_numeric_or_neutral(strip_loose_markers(&strip_ansi(cell))) }
+    else { is_numeric_or_neutral(cell) }
+}
+
+fn cell_value(cell: &str, options: &FormatOptions) -> f64 {
+    let cell = &ungrouped(cell, options);
+    if options.loose_numeric { evaluate_numeric_item(strip_loose_markers(&strip_ansi(cell))) }
+    else { evaluate_numeric_item(cell) }
+}
 
 fn evaluate_numeric_item(s: &str) -> f64 {
     let s = s.trim();
@@ -42,14 +400,34 @@ fn evaluate_numeric_item(s: &str) -> f64 {
     // first, try plain float
     if let Ok(val) = s.parse::<f64>() { return val; }
 
-    // Regex: optional sign, digits, optional fractional
-    let re = Regex::new(r"^[-+]?\d+(\.\d+)?").unwrap();
+    // currency, like `$1,234.50`: the amount, whichever the currency
+    if let Some(number) = strip_currency(s) { return evaluate_numeric_item(&number); }
+
+    // accounting-style negative, like `(1,234)`
+    if let Some(number) = strip_parentheses(s) { return evaluate_numeric_item(&number); }
+
+    // hex, like `0x1F` (addresses past i64::MAX are read unsigned)
+    let (sign, unsigned) = s.strip_prefix('-').map_or((1.0, s.strip_prefix('+').unwrap_or(s)), |rest| (-1.0, rest));
+    if let Some(digits) = unsigned.strip_prefix("0x").or_else(|| unsigned.strip_prefix("0X")) {
+        if let Ok(val) = i64::from_str_radix(digits, 16) { return sign * val as f64; }
+        if let Ok(val) = u64::from_str_radix(digits, 16) { return sign * val as f64; }
+    }
+
+    // Regex: optional sign, digits, optional fractional, optional exponent
+    let re = Regex::new(r"^[-+]?\d+(\.\d+)?([eE][-+]?\d+)?").unwrap();
     if let Some(mat) = re.find(s) {
         let num_str = mat.as_str();
         let mut value = num_str.parse::<f64>().unwrap_or(0.0);
 
         let rest = s[mat.end()..].trim().to_ascii_lowercase();
 
+        // durations, in seconds (so that `ms` isn't taken for mega-)
+        match rest.as_str() {
+            "s" => return value,
+            "ms" => return value * 1e-3,
+            _ => {},
+        }
+
         // Multipliers: binary first, then SI
         let multipliers: &[(&str, f64)] = &[
             ("ki", 1024.0), ("mi", 1024.0_f64.powi(2)), ("gi", 1024.0_f64.powi(3)),
@@ -74,11 +452,74 @@ fn evaluate_numeric_item(s: &str) -> f64 {
     0.0
 }
 
-fn split_row(line: &str) -> Vec<String> {
-    SPLIT_PATTERN.split(line.trim()).map(String::from).collect()
+fn split_row(line: &str, options: &FormatOptions) -> Vec<String> {
+    let mut cells: Vec<String> = match options.delimiter {
+        // positions are as seen on screen, so tabs are expanded beforehand
+        _ if !options.fixed_cols.is_empty() => slice_fixed_cols(&expand_tabs(line, options.tab_width), &options.fixed_cols),
+        // every delimiter separates two cells, so empty fields survive (`a,,b` is 3 cells)
+        Some(delimiter) if delimiter.is_whitespace() => line.split(delimiter).map(String::from).collect(),
+        Some(delimiter) => line.trim().split(delimiter).map(|cell| cell.trim().to_string()).collect(),
+        None => options.split_regex.as_ref().unwrap_or(&SPLIT_PATTERN).split(line.trim()).map(String::from).collect(),
+    };
+
+    if options.strip_colors {
+        for cell in cells.iter_mut().filter(|cell| cell.contains('\x1b')) { *cell = strip_ansi(cell); }
+    }
+
+    // tabs that didn't separate cells are part of them
+    for cell in cells.iter_mut().filter(|cell| cell.contains('\t')) {
+        *cell = expand_tabs(cell, options.tab_width).into_owned();
+    }
+
+    if !options.reorder.is_empty() { cells = reorder_cells(cells, &options.reorder); }
+
+    // cut runaway cells before they get measured, so they don't widen their column
+    if let Some(max) = options.max_col_width.filter(|_| options.format.is_aligned() && !options.wrap) {
+        let pinned = options.pin_first_col.then(|| options.columns.first().copied().unwrap_or(0));
+        for (_, cell) in cells.iter_mut().enumerate().filter(|(idx, cell)| Some(*idx) != pinned && visible_len(cell) > max) {
+            *cell = truncate_cell(cell, max);
+        }
+    }
+    cells
 }
 
-fn detect_column_properties(rows: &[Vec<String>]) -> (Vec<usize>, Vec<bool>) {
+/// Replace each tab with spaces up to the next multiple of `tab_width` columns (`tab_width` 0 leaves tabs alone)
+fn expand_tabs(text: &str, tab_width: usize) -> Cow<'_, str> {
+    if tab_width == 0 || !text.contains('\t') { return Cow::Borrowed(text); }
+    let mut out = String::with_capacity(text.len() + tab_width);
+    let mut column = 0;
+    for grapheme in text.graphemes(true) {
+        if grapheme == "\t" {
+            let spaces = tab_width - column % tab_width;
+            out.push_str(&" ".repeat(spaces));
+            column += spaces;
+        } else {
+            out.push_str(grapheme);
+            column += grapheme_width(grapheme);
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Cut `line` at character positions: each cell is the (trimmed) text from `start` to `end` inclusive,
+/// or to the end of the line when there's no `end`. Past the end of a short line, cells are empty.
+fn slice_fixed_cols(line: &str, ranges: &[(usize, Option<usize>)]) -> Vec<String> {
+    // byte offset of each char, plus the end of the line
+    let offsets: Vec<usize> = line.char_indices().map(|(i, _)| i).chain([line.len()]).collect();
+    let byte = |char_idx: usize| offsets[char_idx.min(offsets.len() - 1)];
+    ranges.iter()
+        .map(|&(start, end)| line[byte(start)..end.map_or(line.len(), |end| byte(end + 1).max(byte(start)))].trim().to_string())
+        .collect()
+}
+
+/// Put the cells at the `order` indices first (blank where the row is too short), followed by the rest as they were
+fn reorder_cells(cells: Vec<String>, order: &[usize]) -> Vec<String> {
+    let mut reordered: Vec<String> = order.iter().map(|&idx| cells.get(idx).cloned().unwrap_or_default()).collect();
+    reordered.extend(cells.into_iter().enumerate().filter(|(idx, _)| !order.contains(idx)).map(|(_, cell)| cell));
+    reordered
+}
+
+fn detect_column_properties(rows: &[Vec<String>], options: &FormatOptions) -> (Vec<usize>, Vec<bool>) {
     let num_cols = rows.iter().map(Vec::len).max().unwrap_or(0);
 
     // Transpose table: convert rows to columns
@@ -91,70 +532,790 @@ fn detect_column_properties(rows: &[Vec<String>]) -> (Vec<usize>, Vec<bool>) {
     (0..num_cols).into_par_iter()
         .map(|col_idx| {
             let col = &columns[col_idx];
-            let width = col.par_iter().map(|cell| visible_len(cell)).max().unwrap_or(0);
-            let is_numeric = col.par_iter().skip(1).all(|cell| is_numeric_or_neutral(cell));
+            let width = if !options.format.is_aligned() { 0 }  // no padding in CSV/JSON
+                else { col.par_iter().map(|cell| visible_len(cell)).max().unwrap_or(0) };
+            let is_numeric = col.par_iter().skip(header_rows(options)).all(|cell| cell_is_numeric(cell, options));
             (width, is_numeric)
         })
         .unzip()
 }
 
-fn format_row(cells: &[String], widths: &[usize], is_numeric: &[bool], sep_width: usize, ) -> String {
+/// Shrink column widths so that a full row (`separators` wide in total, besides the cells) is at most `target` wide.
+/// Each column gets at most an equal share of the room; with `redistribute`, the room that narrower
+/// columns don't use is handed back (round-robin) to the columns that were cut, up to their natural width.
+fn fit_widths(natural: &[usize], target: usize, separators: usize, redistribute: bool) -> Vec<usize> {
+    let budget = target.saturating_sub(separators);
+    if natural.iter().sum::<usize>() <= budget { return natural.to_vec(); }
+
+    let share = (budget / natural.len()).max(1);
+    let mut widths: Vec<usize> = natural.iter().map(|&w| w.min(share)).collect();
+
+    if redistribute {
+        let mut spare = budget.saturating_sub(widths.iter().sum());
+        while spare > 0 {
+            let spare_before = spare;
+            for (width, &natural_width) in widths.iter_mut().zip(natural) {
+                if spare > 0 && *width < natural_width { *width += 1; spare -= 1; }
+            }
+            if spare == spare_before { break; }  // every column is back to its natural width
+        }
+    }
+    widths
+}
+
+/// Pad the numbers of numeric columns so that their decimal points form a vertical line.
+/// Numbers without a fractional part get the room of the widest fraction as trailing spaces;
+/// cells that aren't numbers (e.g. the header) are left alone.
+fn align_decimals(rows: &mut [Vec<String>], widths: &mut [usize], is_numeric: &[bool], options: &FormatOptions) {
+    fn split_at_point(cell: &str, point: char) -> (&str, &str) { cell.split_at(cell.find(point).unwrap_or(cell.len())) }
+    let point = if options.decimal_comma { ',' } else { '.' };
+    let is_number = |cell: &str| !cell.is_empty() && cell_is_numeric(cell, options);
+
+    for (col, width) in widths.iter_mut().enumerate().filter(|(col, _)| is_numeric[*col]) {
+        let numbers = || rows.iter().filter_map(|row| row.get(col)).filter(|cell| is_number(cell));
+        let int_width = numbers().map(|cell| visible_len(split_at_point(cell, point).0)).max().unwrap_or(0);
+        let frac_width = numbers().map(|cell| visible_len(split_at_point(cell, point).1)).max().unwrap_or(0);
+
+        for cell in rows.iter_mut().filter_map(|row| row.get_mut(col)).filter(|cell| is_number(cell)) {
+            let (int, frac) = split_at_point(cell, point);
+            *cell = format!("{}{int}{frac}{}",
+                " ".repeat(int_width - visible_len(int)), " ".repeat(frac_width - visible_len(frac)));
+        }
+        *width = (*width).max(int_width + frac_width);
+    }
+}
+
+/// Ascending comparison of two cells of a sort column (missing cells count as empty / 0)
+fn compare_cells(a: Option<&String>, b: Option<&String>, numeric: bool, options: &FormatOptions) -> Ordering {
+    if numeric {
+        let value = |cell: Option<&String>| OrderedFloat(cell.map(|s| cell_value(s, options)).unwrap_or(0.0));
+        value(a).cmp(&value(b))
+    } else {
+        fn key(cell: Option<&String>, ignore_case: bool) -> Cow<'_, str> {
+            let text = cell.map(String::as_str).unwrap_or_default();
+            if ignore_case { Cow::Owned(text.to_lowercase()) } else { Cow::Borrowed(text) }
+        }
+        let (a, b) = (key(a, options.sort_ignore_case), key(b, options.sort_ignore_case));
+        if options.natural_sort { natural_cmp(&a, &b) } else { a.cmp(&b) }
+    }
+}
+
+/// Compare text with runs of digits compared by their value, so that `item2` comes before `item10`
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    fn chunks(text: &str) -> impl Iterator<Item = &str> {  // alternating runs of digits and non-digits
+        let mut rest = text;
+        std::iter::from_fn(move || {
+            let digits = rest.chars().next()?.is_ascii_digit();
+            let (chunk, tail) = rest.split_at(rest.find(|c: char| c.is_ascii_digit() != digits).unwrap_or(rest.len()));
+            rest = tail;
+            Some(chunk)
+        })
+    }
+    // equal-valued numbers with different leading zeros (`7`, `007`) fall back to plain text order
+    chunks(a).zip(chunks(b))
+        .map(|(x, y)| match (x.starts_with(|c: char| c.is_ascii_digit()), y.starts_with(|c: char| c.is_ascii_digit())) {
+            (true, true) => {
+                let (x_digits, y_digits) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                x_digits.len().cmp(&y_digits.len()).then_with(|| x_digits.cmp(y_digits))
+            },
+            _ => x.cmp(y),
+        })
+        .find(|ord| ord.is_ne())
+        .unwrap_or_else(|| chunks(a).count().cmp(&chunks(b).count()))
+        .then_with(|| a.cmp(b))
+}
+
+/// Cut a cell down to `width` visible columns, ending it with '…'. ANSI sequences are kept intact.
+fn truncate_cell(cell: &str, width: usize) -> String {
+    console::truncate_str(cell, width, "…").into_owned()
+}
+
+/// Split a cell into lines of at most `width` visible columns, breaking between graphemes.
+/// ANSI sequences are never cut; colors still open at a line break are reset there and reopened on the next line.
+fn wrap_cell(cell: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    let mut line_width = 0;
+    let mut active = String::new();  // escape sequences in effect since the last reset
+
+    for (part, is_ansi) in console::AnsiCodeIterator::new(cell) {
+        let line = lines.last_mut().unwrap();
+        if is_ansi {
+            if matches!(part, "\x1b[0m" | "\x1b[m") { active.clear(); } else { active.push_str(part); }
+            line.push_str(part);
+            continue;
+        }
+        for grapheme in part.graphemes(true) {
+            let grapheme_width = grapheme_width(grapheme);
+            if line_width > 0 && line_width + grapheme_width > width {
+                if !active.is_empty() { lines.last_mut().unwrap().push_str("\x1b[0m"); }
+                lines.push(active.clone());
+                line_width = 0;
+            }
+            lines.last_mut().unwrap().push_str(grapheme);
+            line_width += grapheme_width;
+        }
+    }
+    lines
+}
+
+/// Render a row; cells wider than their column (see `--wrap`) continue on extra lines, with blank cells beside them.
+/// `spacers` go between the columns: the first one after column 0, and so on.
+fn format_row(cells: &[String], widths: &[usize], is_numeric: &[bool], spacers: &[String], align: Align) -> Vec<String> {
+    if cells.iter().zip(widths).all(|(cell, &width)| visible_len(cell) <= width) {
+        return vec![format_line(cells, widths, is_numeric, spacers, align)];
+    }
+
+    // pad by visible width here, so colored or double-width pieces keep their column straight
+    let empty = String::new();
+    let pieces: Vec<Vec<String>> = izip!(cells.iter().chain(repeat(&empty)), widths, is_numeric)
+        .map(|(cell, &width, &numeric)| wrap_cell(cell, width).into_iter()
+            .map(|piece| {
+                let mut padded = String::with_capacity(piece.len().max(width));
+                pad_cell(&mut padded, &piece, width, numeric, align);
+                padded
+            })
+            .collect())
+        .collect();
+    let height = pieces.iter().map(Vec::len).max().unwrap_or(1);
+    let already_padded = vec![0; widths.len()];
+    (0..height)
+        .map(|i| {
+            let line: Vec<String> = izip!(&pieces, widths)
+                .map(|(p, &width)| p.get(i).cloned().unwrap_or_else(|| " ".repeat(width)))
+                .collect();
+            format_line(&line, &already_padded, is_numeric, spacers, align)
+        })
+        .collect()
+}
+
+fn format_line(cells: &[String], widths: &[usize], is_numeric: &[bool], spacers: &[String], align: Align) -> String {
     // Pre-compute total capacity
     let total = widths.iter().sum::<usize>()
-        + sep_width * widths.len().saturating_sub(1);
+        + spacers.iter().map(String::len).sum::<usize>();
     let mut out = String::with_capacity(total);
-    let spacer = " ".repeat(sep_width);
 
     // Bind a single empty String for all "missing" cells
     let empty = String::new();
 
     // Zip widths, flags, and cells (falling back to &empty)
-    for (&width, &numeric, cell) in izip!(
+    for (col_idx, (&width, &numeric, cell)) in izip!(
         widths.iter(),
         is_numeric.iter(),
         cells.iter().chain(repeat(&empty))
-    ) {
-        if numeric { write!(out, "{:>width$}", cell, width = width).unwrap(); }
-        else { write!(out, "{:<width$}", cell, width = width).unwrap(); }
-        out.push_str(&spacer);
+    ).enumerate() {
+        if col_idx > 0 { out.push_str(spacers.get(col_idx - 1).unwrap_or(&empty)); }
+        pad_cell(&mut out, cell, width, numeric, align);
     }
+    out
+}
+
+/// Write `cell` into `out`, aligned within `width`
+fn pad_cell(out: &mut String, cell: &str, width: usize, numeric: bool, align: Align) {
+    // padded by the room it takes on screen: escape sequences take none, wide characters two columns
+    let padding = width.saturating_sub(visible_len(cell));
+    match (align, numeric) {
+        (Align::Right, _) | (Align::Auto, true) => write!(out, "{}{cell}", " ".repeat(padding)).unwrap(),
+        (Align::Left, _) | (Align::Auto, false) => write!(out, "{cell}{}", " ".repeat(padding)).unwrap(),
+        (Align::Center, _) => {  // odd leftover space goes to the right
+            write!(out, "{}{cell}{}", " ".repeat(padding / 2), " ".repeat(padding - padding / 2)).unwrap();
+        },
+    }
+}
+
+/// Render rows inside box-drawing borders, with a rule under the first (header) row.
+/// Cells are padded just like in the plain format, and framed by one space on either side.
+/// Returns the lines of each row; the borders go with the first and last rows.
+fn format_box(rows: &[Vec<String>], widths: &[usize], is_numeric: &[bool], align: Align) -> Vec<Vec<String>> {
+    let rule = |left: &str, middle: &str, right: &str| {
+        format!("{left}{}{right}", widths.iter().map(|&w| "─".repeat(w + 2)).collect::<Vec<_>>().join(middle))
+    };
+    let spacers = vec![BOX_SPACER.to_string(); widths.len().saturating_sub(1)];
+    let framed = |row: &Vec<String>| {
+        format_row(row, widths, is_numeric, &spacers, align).into_iter().map(|line| format!("│ {line} │"))
+    };
 
-    // Trim off the trailing separator
-    out.truncate(out.len().saturating_sub(sep_width));
+    let Some((header, body)) = rows.split_first() else { return vec![] };
+    let mut out = vec![std::iter::once(rule("┌", "┬", "┐")).chain(framed(header)).collect::<Vec<_>>()];
+    if !body.is_empty() { out[0].push(rule("├", "┼", "┤")); }
+    out.par_extend(body.par_iter().map(|row| framed(row).collect()));
+    out.last_mut().unwrap().push(rule("└", "┴", "┘"));
     out
 }
 
+/// Render each data row as a JSON object on its own line, keyed by the header row's cells (`col0`, `col1`... with
+/// `no_header`, or past the header's end). Cells of numeric columns become numbers, or null for placeholders like `-`.
+/// Short rows just have fewer keys. The header row itself gets no line.
+fn format_json(rows: &[Vec<String>], is_numeric: &[bool], options: &FormatOptions) -> Vec<Vec<String>> {
+    let (header, body) = match rows.split_first() {
+        Some((header, body)) if !options.no_header => (header.as_slice(), body),
+        _ => (&[][..], rows),
+    };
+    let keys: Vec<String> = (0..is_numeric.len())
+        .map(|idx| header.get(idx).map_or_else(|| format!("col{idx}"), |cell| strip_ansi(cell)))
+        .collect();
+
+    let value = |cell: &str, numeric: bool| -> serde_json::Value {
+        let clean = strip_ansi(cell);
+        let is_number = numeric && is_number(ungrouped(strip_loose_markers(&clean), options).trim());
+        if !is_number {
+            return if numeric && cell_is_numeric(&clean, options) { serde_json::Value::Null } else { clean.into() };
+        }
+        let number = cell_value(&clean, options);
+        if number.fract() == 0.0 && number.abs() < i64::MAX as f64 { (number as i64).into() } else { number.into() }
+    };
+
+    let mut lines: Vec<Vec<String>> = if header.is_empty() { vec![] } else { vec![vec![]] };
+    lines.par_extend(body.par_iter().map(|row| {
+        let object: serde_json::Map<String, serde_json::Value> = izip!(&keys, row, is_numeric)
+            .map(|(key, cell, &numeric)| (key.clone(), value(cell, numeric)))
+            .collect();
+        vec![serde_json::Value::Object(object).to_string()]
+    }));
+    lines
+}
+
+/// Render rows as a Markdown table: the first row is the header, followed by the `|---|--:|` rule.
+/// ANSI codes are dropped (Markdown can't show them) and `|` inside cells is escaped.
+fn format_markdown(rows: &[Vec<String>], is_numeric: &[bool], align: Align) -> Vec<String> {
+    let cells: Vec<Vec<String>> = rows.iter()
+        .map(|row| (0..is_numeric.len())
+            .map(|i| row.get(i).map(|c| strip_ansi(c).replace('|', "\\|")).unwrap_or_default())
+            .collect())
+        .collect();
+    let widths: Vec<usize> = (0..is_numeric.len())
+        .map(|i| cells.iter().map(|row| visible_len(&row[i])).max().unwrap_or(0).max(3))  // at least "---"
+        .collect();
+
+    let line = |row: &[String]| -> String {
+        let padded = izip!(row, &widths, is_numeric).map(|(cell, &width, &numeric)| {
+            let padding = " ".repeat(width.saturating_sub(visible_len(cell)));
+            if matches!((align, numeric), (Align::Right, _) | (Align::Auto, true)) { format!("{padding}{cell}") } else { format!("{cell}{padding}") }
+        });
+        format!("| {} |", padded.collect::<Vec<_>>().join(" | "))
+    };
+    let rule = izip!(&widths, is_numeric).map(|(&width, &numeric)| match (align, numeric) {
+        (Align::Left, _) => format!(":{}", "-".repeat(width - 1)),
+        (Align::Right, _) | (Align::Auto, true) => format!("{}:", "-".repeat(width - 1)),
+        (Align::Center, _) => format!(":{}:", "-".repeat(width - 2)),
+        (Align::Auto, false) => "-".repeat(width),
+    });
+
+    let mut out: Vec<String> = cells.iter().map(|row| line(row)).collect();
+    if !out.is_empty() { out.insert(1, format!("| {} |", rule.collect::<Vec<_>>().join(" | "))); }
+    out
+}
+
+/// Render rows as CSV. Fields containing a comma, quote or line break are quoted, with inner quotes doubled.
+fn format_csv(rows: &[Vec<String>]) -> Vec<String> {
+    fn quote(cell: &str) -> String {
+        if cell.contains([',', '"', '\n', '\r']) { format!("\"{}\"", cell.replace('"', "\"\"")) }
+        else { cell.to_string() }
+    }
+
+    rows.par_iter()
+        .map(|row| row.iter().map(|cell| quote(cell)).collect::<Vec<_>>().join(","))
+        .collect()
+}
+
+/// Swap rows and columns; short rows are padded with empty cells first
+fn transpose(rows: Vec<Vec<String>>) -> Vec<Vec<String>> {
+    let num_cols = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let mut columns = vec![Vec::with_capacity(rows.len()); num_cols];
+    for mut row in rows {
+        row.resize(num_cols, String::new());
+        for (column, cell) in columns.iter_mut().zip(row) { column.push(cell); }
+    }
+    columns
+}
+
 // ——— Core formatting functions ——————————————————————————————————
-pub fn format_table(lines: &[String], separator: usize, col_idx: Option<usize>) -> Vec<String> {
-    // Split rows - always use par_iter, rayon will handle the parallelization decision
-    let mut rows: Vec<Vec<String>> = lines.par_iter().map(|line| split_row(line)).collect();
-    let (widths, is_numeric) = detect_column_properties(&rows);
-
-    // sort, if asked to
-    if let Some(idx) = col_idx {
-        // if the first row has an actual number in that index, include it in the sort
-        let sorting_first_row_too = !rows.is_empty() && evaluate_numeric_item(&rows[0][idx]) != 0.0;
-        let header = if !sorting_first_row_too { rows.remove(0) } else { vec![] };
-
-        if is_numeric[idx] {
-            rows.sort_by_key(|row| {
-                OrderedFloat(row.get(idx).map(|s| evaluate_numeric_item(s)).unwrap_or(0.0))
+/// Split rows, along with each column's display width and numeric-flag
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Table {
+    pub rows: Vec<Vec<String>>,
+    pub widths: Vec<usize>,
+    pub is_numeric: Vec<bool>,
+    /// Comment lines (see `--comment`), each with the number of rows that came before it
+    pub comments: Vec<(usize, String)>,
+}
+
+fn is_comment(line: &str, options: &FormatOptions) -> bool {
+    options.comment.as_deref().is_some_and(|prefix| line.trim_start().starts_with(prefix))
+}
+
+impl Table {
+    pub fn from_lines(lines: &[String], options: &FormatOptions) -> Self {
+        let mut comments = vec![];
+        let mut data = Vec::with_capacity(lines.len());
+        for line in lines {
+            let line = line.strip_suffix('\r').unwrap_or(line);  // CRLF line endings (only the one at the end; inner '\r's are content)
+            if is_comment(line, options) { comments.push((data.len(), line.to_string())); } else { data.push(line); }
+        }
+
+        // Split rows - always use par_iter, rayon will handle the parallelization decision
+        let rows: Vec<Vec<String>> = data.par_iter().map(|line| split_row(line, options)).collect();
+        Table::from_rows(rows, comments, options)
+    }
+
+    fn from_rows(rows: Vec<Vec<String>>, comments: Vec<(usize, String)>, options: &FormatOptions) -> Self {
+        let rows = if options.transpose { transpose(rows) } else { rows };
+        let rows = match options.group_by {
+            Some(key) => group_rows(rows, key, &options.aggregates, options),
+            None => rows,
+        };
+        let (widths, is_numeric) = detect_column_properties(&rows, options);
+        let mut table = Table { rows, widths, is_numeric, comments };
+        if let Some(separator) = options.group_digits { table.group_digits(separator, options); }
+        table
+    }
+
+    /// Group the digits of every numeric column (below the header row), widening the columns to match
+    fn group_digits(&mut self, separator: char, options: &FormatOptions) {
+        let Table { rows, widths, is_numeric, .. } = self;
+        rows.par_iter_mut().skip(header_rows(options)).for_each(|row| {
+            for (cell, _) in row.iter_mut().zip(is_numeric.iter()).filter(|(_, &numeric)| numeric) {
+                *cell = group_integer_part(cell, separator);
+            }
+        });
+        if !options.format.is_aligned() { return; }  // no padding in CSV/JSON
+        for row in rows.iter().skip(header_rows(options)) {
+            for (cell, width) in row.iter().zip(widths.iter_mut()) { *width = (*width).max(visible_len(cell)); }
+        }
+    }
+
+    /// Parse while reading: each line is split as soon as it arrives, and the column properties
+    /// are accumulated along the way, so the raw lines are never held in memory.
+    /// Invalid UTF-8 is replaced with '�'.
+    pub fn from_reader(mut reader: impl BufRead, options: &FormatOptions) -> io::Result<Self> {
+        let mut table = Table::default();
+        let mut buf = Vec::new();
+
+        while reader.read_until(b'\n', &mut buf)? > 0 {
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if is_comment(line, options) {
+                table.comments.push((table.rows.len(), line.to_string()));
+                buf.clear();
+                continue;
+            }
+            let row = split_row(line, options);
+
+            for (col_idx, cell) in row.iter().enumerate() {
+                let width = if options.format.is_aligned() { visible_len(cell) } else { 0 };
+                if col_idx == table.widths.len() {  // a column's first cell is its header; it doesn't affect numeric-ness
+                    table.widths.push(width);
+                    table.is_numeric.push(!options.no_header || cell_is_numeric(cell, options));
+                } else {
+                    table.widths[col_idx] = table.widths[col_idx].max(width);
+                    table.is_numeric[col_idx] &= cell_is_numeric(cell, options);
+                }
+            }
+
+            table.rows.push(row);
+            buf.clear();
+        }
+
+        // the columns of a transposed table (or its groups) are only known once every row is in
+        if options.transpose || options.group_by.is_some() { return Ok(Table::from_rows(table.rows, table.comments, options)); }
+        if let Some(separator) = options.group_digits { table.group_digits(separator, options); }
+        Ok(table)
+    }
+
+    pub fn format(mut self, options: &FormatOptions) -> Vec<String> {
+        let comments = std::mem::take(&mut self.comments);
+        splice_comments(self.format_rows(options).1, &comments)
+    }
+
+    /// Format every row into its output line(s), leaving out the comments, along with where each row was in the input
+    /// (past the last one for the footer)
+    fn format_rows(self, options: &FormatOptions) -> (Vec<usize>, Vec<Vec<String>>) {
+        let Table { mut rows, mut widths, mut is_numeric, .. } = self;
+        let mut has_header = !options.no_header && !rows.is_empty();
+        // where each row was in the input
+        let mut positions: Vec<usize> = (0..rows.len()).collect();
+        // for --number, what each row counts as in that input order: blank (separator) rows count as nothing
+        let input_numbers: Vec<Option<usize>> = rows.iter()
+            .scan(0, |count, row| Some((!row.iter().all(String::is_empty)).then(|| { *count += 1; *count })))
+            .collect();
+
+        // sort, if asked to
+        if let Some(&primary) = options.sort.first() {
+            // if the first row has an actual number in that index, include it in the sort
+            let sorting_first_row_too = !rows.is_empty() && (options.no_header || rows[0].get(primary).is_some_and(|cell| cell_value(cell, options) != 0.0));
+            has_header = !sorting_first_row_too;
+            let mut numbered: Vec<(usize, Vec<String>)> = positions.into_iter().zip(rows).collect();
+            let header = if !sorting_first_row_too { Some(numbered.remove(0)) } else { None };
+
+            // unless told otherwise, biggest numbers appear at the top
+            // a column past the last one sorts as empty text
+            let numeric = |idx: usize| is_numeric.get(idx).copied().unwrap_or(false);
+            let descending = |idx: usize| options.sort_order.map_or(numeric(idx), |order| order == SortOrder::Desc);
+            // compare by each key in turn, flipping the keys `reversed` says so
+            let compare = |a: &Vec<String>, b: &Vec<String>, reversed: &dyn Fn(usize) -> bool| {
+                options.sort.iter().fold(Ordering::Equal, |ord, &idx| {
+                    ord.then_with(|| {
+                        let ord = compare_cells(a.get(idx), b.get(idx), numeric(idx), options);
+                        if reversed(idx) { ord.reverse() } else { ord }
+                    })
+                })
+            };
+            // blank lines separate sections, each sorted on its own
+            for section in numbered.split_mut(|(_, row)| row.iter().all(String::is_empty)) {
+                if options.stable {
+                    // each key in its own direction; `sort_by` is stable, so full ties keep their input order
+                    section.sort_by(|(_, a), (_, b)| compare(a, b, &descending));
+                } else {
+                    // sort in the primary key's direction and flip the whole thing afterwards if that's descending;
+                    // keys with the opposite direction are compared in reverse so they come out right after the flip
+                    // (full ties of a descending sort thereby end up in reverse input order)
+                    section.sort_by(|(_, a), (_, b)| compare(a, b, &|idx| descending(idx) != descending(primary)));
+                    if descending(primary) { section.reverse(); }
+                }
+            }
+            numbered.splice(0..0, header);  // restore header post-sort
+            (positions, rows) = numbered.into_iter().unzip();
+        }
+
+        // flip the data rows (blank ones included) under the header, whatever order they're in by now
+        if options.reverse {
+            let header_offset = usize::from(has_header);
+            rows[header_offset..].reverse();
+            positions[header_offset..].reverse();
+        }
+
+        // only the first/last data rows, with the columns only as wide as those need
+        if options.head.is_some() || options.tail.is_some() {
+            let header_offset = usize::from(has_header);
+            if let Some(n) = options.head {
+                rows.truncate(header_offset + n);
+                positions.truncate(header_offset + n);
+            }
+            if let Some(n) = options.tail {
+                let dropped = header_offset..rows.len().saturating_sub(n).max(header_offset);
+                rows.drain(dropped.clone());
+                positions.drain(dropped);
+            }
+            if options.format.is_aligned() {
+                let (tight, _) = detect_column_properties(&rows, options);
+                widths.iter_mut().enumerate().for_each(|(idx, width)| *width = tight.get(idx).copied().unwrap_or(0));
+            }
+        }
+
+        // a footer with the sum of each numeric column
+        let footer = options.totals && rows.len() > usize::from(has_header);
+        if footer {
+            let totals = totals_row(&rows[usize::from(has_header)..], &is_numeric, options);
+            if options.format.is_aligned() {
+                for (width, cell) in widths.iter_mut().zip(&totals) { *width = (*width).max(visible_len(cell)); }
+            }
+            positions.push(rows.len());
+            rows.push(totals);
+        }
+
+        // bars of the --bar column, to go after all the other columns
+        let bars = options.bar.filter(|_| options.format.is_aligned())
+            .map(|col| bar_cells(&rows, col, usize::from(has_header), usize::from(footer), options));
+
+        // where the heatmap column ends up
+        let heat_col = options.heatmap.and_then(|idx| if options.columns.is_empty() { Some(idx) }
+            else { options.columns.iter().filter(|&&col| col < widths.len()).position(|&col| col == idx) });
+
+        // keep only the requested columns (sort keys still refer to the original ones)
+        if !options.columns.is_empty() {
+            let kept: Vec<usize> = options.columns.iter().copied().filter(|&idx| idx < widths.len()).collect();
+            rows.par_iter_mut().for_each(|row| {
+                // past its last existing cell, a short row just ends (like it did before)
+                let present = kept.iter().rposition(|&idx| idx < row.len()).map_or(0, |last| last + 1);
+                *row = kept[..present].iter().map(|&idx| row.get(idx).cloned().unwrap_or_default()).collect();
+            });
+            widths = kept.iter().map(|&idx| widths[idx]).collect();
+            is_numeric = kept.iter().map(|&idx| is_numeric[idx]).collect();
+        }
+
+        // a leading column counting the data rows, in output order or (--number-before-sort) input order
+        if options.number {
+            let header_offset = usize::from(has_header);
+            let mut counted = 0;
+            let numbers: Vec<Vec<String>> = positions.iter().enumerate()
+                .map(|(row_idx, &position)| match (row_idx, input_numbers.get(position).copied().flatten()) {
+                    (0, _) if has_header => "#".to_string(),
+                    _ if footer && row_idx == positions.len() - 1 => String::new(),
+                    (_, None) => String::new(),
+                    (_, Some(number)) if options.number_before_sort => (number - header_offset).to_string(),
+                    _ => { counted += 1; counted.to_string() },
+                })
+                .map(|number| vec![number])
+                .collect();
+            let (width, numeric) = detect_column_properties(&numbers, options);
+            for (row, number) in rows.iter_mut().zip(numbers) { row.splice(0..0, number); }
+            widths.splice(0..0, width);
+            is_numeric.splice(0..0, numeric);
+        }
+        let heat_col = heat_col.map(|col| col + usize::from(options.number));
+
+        if let Some(bars) = bars {
+            for (row, bar) in rows.iter_mut().zip(bars) {
+                row.resize(widths.len(), String::new());
+                row.push(bar);
+            }
+            widths.push(options.bar_width);
+            is_numeric.push(false);
+        }
+
+        // the first column (after --number's) that's never cut short
+        let pinned = options.pin_first_col.then_some(usize::from(options.number)).filter(|&idx| idx < widths.len());
+
+        // wrapped cells take up no more than the max width; the rest goes to continuation lines
+        if let Some(max) = options.max_col_width.filter(|_| options.wrap && matches!(options.format, OutputFormat::Plain | OutputFormat::Box)) {
+            widths.iter_mut().enumerate().filter(|(idx, _)| Some(*idx) != pinned).for_each(|(_, width)| *width = (*width).min(max));
+        }
+
+        // the gap after each column but the last
+        let spacers: Vec<String> = (0..widths.len().saturating_sub(1))
+            .map(|idx| " ".repeat(options.sep_spec.get(idx).copied().unwrap_or(options.separator)))
+            .collect();
+
+        // fit into the requested total width, truncating cells of narrowed columns
+        if let Some(target) = options.width.filter(|_| options.format.is_aligned()) {
+            let boundaries = widths.len().saturating_sub(1);
+            let reserved = pinned.map_or(0, |idx| widths.remove(idx));  // the pinned column keeps all it needs
+            widths = match options.format {
+                // "│ " + cells joined by " │ " + " │"
+                OutputFormat::Box => fit_widths(&widths, target.saturating_sub(4 + reserved), BOX_SPACER.chars().count() * boundaries, options.two_pass_widths),
+                _ => fit_widths(&widths, target.saturating_sub(reserved), spacers.iter().map(String::len).sum(), options.two_pass_widths),
+            };
+            if let Some(idx) = pinned { widths.insert(idx, reserved); }
+            rows.par_iter_mut().for_each(|row| {
+                for (cell, &width) in row.iter_mut().zip(&widths) {
+                    if visible_len(cell) > width { *cell = truncate_cell(cell, width); }
+                }
             });
-            rows.reverse();  // make biggest numbers appear at the top
-        } else {rows.sort_by_key(|row| { row.get(idx).cloned().unwrap_or_default() }); }
-        if !sorting_first_row_too { rows.insert(0, header); }  // restore header post-sort
+        }
+
+        if matches!(options.format, OutputFormat::Plain | OutputFormat::Box) && options.decimal_align {
+            align_decimals(&mut rows, &mut widths, &is_numeric, options);
+        }
+
+        // colored last, so that nothing above has to look past the escape codes
+        if let Some(col) = heat_col.filter(|&col| options.format.is_aligned() && is_numeric.get(col) == Some(&true)) {
+            let data_end = rows.len() - usize::from(footer);
+            heat_colorize(&mut rows[..data_end], col, options);
+        }
+
+        // last column first; each column keeps its width and alignment
+        if options.rtl {
+            rows.par_iter_mut().for_each(|row| {
+                row.resize(widths.len(), String::new());
+                row.reverse();
+            });
+            widths.reverse();
+            is_numeric.reverse();
+        }
+
+        // the lines of each row
+        let lines = match options.format {
+            OutputFormat::Markdown => {
+                let mut lines: Vec<Vec<String>> = format_markdown(&rows, &is_numeric, options.align).into_iter().map(|line| vec![line]).collect();
+                if lines.len() > 1 { let rule = lines.remove(1); lines[0].extend(rule); }  // the rule goes with the header
+                lines
+            },
+            OutputFormat::Csv => format_csv(&rows).into_iter().map(|line| vec![line]).collect(),
+            OutputFormat::Json => format_json(&rows, &is_numeric, options),
+            OutputFormat::Box => format_box(&rows, &widths, &is_numeric, options.align),
+            // Format rows (the main feature; handle the spacing)
+            OutputFormat::Plain => {
+                let mut lines: Vec<Vec<String>> = rows.par_iter()
+                    .map(|row| format_row(row, &widths, &is_numeric, &spacers, options.align))
+                    .collect();
+                if let Some(header) = lines.first_mut().filter(|_| options.header_rule) {
+                    let dashes: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+                    header.push(format_line(&dashes, &widths, &vec![false; widths.len()], &spacers, Align::Left));
+                }
+                lines
+            },
+        };
+        (positions, lines)
     }
+}
 
-    // Format rows (the main feature; handle the spacing)
-    rows.par_iter()
-        .map(|row| format_row(row, &widths, &is_numeric, separator))
+/// Collapse the rows sharing a value in column `key` into one: the key, followed by each of `aggregates`
+/// (just the row count if there are none). Groups are in order of first appearance; rows too short to have
+/// the key column form a group with an empty key, and blank lines are dropped.
+fn group_rows(rows: Vec<Vec<String>>, key: usize, aggregates: &[(Aggregate, usize)], options: &FormatOptions) -> Vec<Vec<String>> {
+    let aggregates = if aggregates.is_empty() { &[(Aggregate::Count, key)][..] } else { aggregates };
+    let (header, data) = rows.split_at(header_rows(options).min(rows.len()));
+
+    let mut groups: Vec<(&str, Vec<&Vec<String>>)> = vec![];
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for row in data.iter().filter(|row| row.iter().any(|cell| !cell.is_empty())) {
+        let value = row.get(key).map_or("", String::as_str);
+        let group = *index.entry(value).or_insert_with(|| { groups.push((value, vec![])); groups.len() - 1 });
+        groups[group].1.push(row);
+    }
+
+    let column_name = |row: &Vec<String>, col: usize| row.get(col).cloned().unwrap_or_else(|| format!("col{col}"));
+    let header = header.first().map(|header| std::iter::once(column_name(header, key))
+        .chain(aggregates.iter().map(|&(aggregate, col)| match aggregate {
+            Aggregate::Count => aggregate.name().to_string(),
+            _ => format!("{}({})", aggregate.name(), column_name(header, col)),
+        }))
+        .collect());
+    let groups = groups.into_iter().map(|(value, rows)| {
+        std::iter::once(value.to_string())
+            .chain(aggregates.iter().map(|&(aggregate, col)| {
+                let values: Vec<f64> = rows.iter().filter_map(|row| row.get(col)).filter_map(|cell| measured_value(cell, options)).collect();
+                aggregate.apply(rows.len(), &values).map(|value| format_number(value, options)).unwrap_or_default()
+            }))
+            .collect()
+    });
+    header.into_iter().chain(groups).collect()
+}
+
+/// A computed number as a cell, e.g. `0.3` rather than `0.30000000000000004` (in the `--decimal-comma` style if need be)
+fn format_number(value: f64, options: &FormatOptions) -> String {
+    let number = ((value * 1e10).round() / 1e10).to_string();
+    if options.decimal_comma { number.replace('.', ",") } else { number }
+}
+
+/// The sums of the numeric columns over `data`, labelled in the leftmost other column
+fn totals_row(data: &[Vec<String>], is_numeric: &[bool], options: &FormatOptions) -> Vec<String> {
+    let mut totals: Vec<String> = is_numeric.iter().enumerate()
+        .map(|(idx, &numeric)| {
+            if !numeric { return String::new(); }
+            let sum: f64 = data.iter().filter_map(|row| row.get(idx)).map(|cell| cell_value(cell, options)).sum();
+            let total = format_number(sum, options);
+            match options.group_digits {
+                Some(separator) => group_integer_part(&total, separator),
+                None => total,
+            }
+        })
+        .collect();
+    if let Some(label) = is_numeric.iter().position(|&numeric| !numeric) { totals[label] = options.totals_label.clone(); }
+    totals
+}
+
+/// A bar per row, as long (in eighths of a cell) as the number in column `col` is relative to the column's biggest,
+/// with the biggest spanning `bar_width`. The header and footer rows (and rows without a number there) get none.
+fn bar_cells(rows: &[Vec<String>], col: usize, header: usize, footer: usize, options: &FormatOptions) -> Vec<String> {
+    let values: Vec<Option<f64>> = rows.iter().enumerate()
+        .map(|(row_idx, row)| row.get(col).filter(|_| (header..rows.len() - footer).contains(&row_idx)).and_then(|cell| measured_value(cell, options)))
+        .collect();
+    let max = values.iter().flatten().fold(0.0_f64, |max, &v| max.max(v));
+
+    values.into_iter()
+        .map(|value| {
+            let Some(value) = value.filter(|&v| v > 0.0 && max > 0.0) else { return String::new() };
+            let eighths = (value / max * options.bar_width as f64 * 8.0).round() as usize;
+            format!("{}{}", "█".repeat(eighths / 8), BAR_EIGHTHS[eighths % 8])
+        })
         .collect()
 }
 
-fn print_table(lines: &[String], separator: usize, col_idx: Option<usize>) {
-    format_table(lines, separator, col_idx)
-        .iter()
-        .for_each(|line| println!("{line}"));
+/// Color the numbers of column `col` by their value, from green (smallest) to red (largest), leaving the header alone
+fn heat_colorize(rows: &mut [Vec<String>], col: usize, options: &FormatOptions) {
+    let values: Vec<Option<f64>> = rows.iter().enumerate()
+        .map(|(row_idx, row)| row.get(col).filter(|_| row_idx >= header_rows(options)).and_then(|cell| measured_value(cell, options)))
+        .collect();
+    let (min, max) = values.iter().flatten().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &v| (min.min(v), max.max(v)));
+
+    for (row, value) in rows.iter_mut().zip(values) {
+        let Some(value) = value else { continue };
+        let heat = if max > min { (value - min) / (max - min) } else { 0.0 };
+        row[col] = format!("\x1b[38;5;{}m{}\x1b[0m", heat_color(heat), row[col]);
+    }
+}
+
+/// The value of a cell that holds an actual number, rather than a neutral placeholder like `-`
+fn measured_value(cell: &str, options: &FormatOptions) -> Option<f64> {
+    let clean = strip_ansi(&ungrouped(cell, options));
+    let clean = if options.loose_numeric { strip_loose_markers(&clean) } else { clean.trim() };
+    is_number(clean).then(|| evaluate_numeric_item(clean))
+}
+
+/// 256-color code along green → yellow → red, for `heat` from 0 to 1
+fn heat_color(heat: f64) -> u8 {
+    let red = (heat * 10.0).round().min(5.0) as u8;
+    let green = ((1.0 - heat) * 10.0).round().min(5.0) as u8;
+    16 + 36 * red + 6 * green
+}
+
+/// Number of leading rows that are header rather than data
+fn header_rows(options: &FormatOptions) -> usize {
+    usize::from(!options.no_header)
+}
+
+/// Flatten the lines of each row, putting every comment back after the number of rows it came after
+fn splice_comments(lines: Vec<Vec<String>>, comments: &[(usize, String)]) -> Vec<String> {
+    if comments.is_empty() { return lines.into_iter().flatten().collect(); }
+
+    let mut out = Vec::with_capacity(lines.len() + comments.len());
+    let mut comments = comments.iter().peekable();
+    for (row_idx, row_lines) in lines.into_iter().enumerate() {
+        while let Some((_, comment)) = comments.next_if(|(before, _)| *before <= row_idx) { out.push(comment.clone()); }
+        out.extend(row_lines);
+    }
+    out.extend(comments.map(|(_, comment)| comment.clone()));
+    out
+}
+
+pub fn format_table(lines: &[String], options: &FormatOptions) -> Vec<String> {
+    Table::from_lines(lines, options).format(options)
+}
+
+/// Write every formatted line (newline-terminated) through a single writer, flushing once at the end
+fn write_table(formatted: &[String], out: &mut impl IoWrite) -> io::Result<()> {
+    for line in formatted { writeln!(out, "{line}")?; }
+    out.flush()
+}
+
+/// `--stream`: format and write every `window` input lines as a table of their own, so memory use stays bounded
+/// no matter how long the input is. Unless `no_header`, the first line is taken as the header: it's measured (and kept on top) along
+/// with every window, but only printed with the first. Column widths can differ from one window to the next.
+fn stream_table(mut reader: impl BufRead, options: &FormatOptions, window: usize, out: &mut impl IoWrite) -> io::Result<()> {
+    let mut header: Option<String> = None;
+    let mut buf = Vec::new();
+    let mut eof = false;
+
+    while !eof {
+        let mut lines: Vec<String> = header.iter().cloned().collect();
+        let carried = lines.len();
+        while lines.len() - carried < window.max(1) {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 { eof = true; break; }
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            lines.push(line.strip_suffix('\r').unwrap_or(line).to_string());
+        }
+        if lines.len() == carried { break; }
+
+        if header.is_none() && !options.no_header {
+            // a first row of just numbers is data rather than a header
+            header = lines.iter().find(|line| !is_comment(line, options))
+                .filter(|line| !split_row(line, options).iter().all(|cell| cell_is_numeric(cell, options)))
+                .cloned();
+        }
+
+        let mut table = Table::from_lines(&lines, options);
+        let mut comments = std::mem::take(&mut table.comments);
+        let (positions, mut rows) = table.format_rows(options);
+        // the header was printed with the first window (and may have been sorted in with the data since)
+        if let Some(at) = positions.iter().position(|&position| position == 0).filter(|_| carried > 0) {
+            rows.remove(at);
+            comments.iter_mut().filter(|(before, _)| *before > at).for_each(|(before, _)| *before -= 1);
+        }
+        write_table(&splice_comments(rows, &comments), out)?;
+    }
+    Ok(())
+}
+
+/// Print to stdout, or into the `output` file (created/truncated only once the table is formatted)
+fn print_table(table: Table, options: &FormatOptions, output: Option<&Path>) -> io::Result<()> {
+    let formatted = table.format(options);
+    match output {
+        Some(path) => write_table(&formatted, &mut BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, create_output(path)?)),
+        None => write_table(&formatted, &mut BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, io::stdout().lock())),
+    }
 }
 
 // ——— CLI Options ——————————————————————————————————————
@@ -169,33 +1330,359 @@ struct Args {
     #[arg(short, long, default_value_t = DEFAULT_SEPARATOR)]
     separator: usize,
 
+    /// Number of spaces after each printed column, e.g. '2,2,4' puts 4 before the fourth column.
+    /// Boundaries past the list get --separator.
+    #[arg(long, value_delimiter = ',', value_name = "N[,N...]")]
+    sep_spec: Vec<usize>,
+
     /// Sort by column index (0-based), Header row is kept on top.
+    /// Give several comma-separated indices (e.g. `6,3`) to break ties by the following columns.
+    /// Blank lines stay where they are, splitting the rows into sections that are sorted separately.
+    #[arg(long, value_delimiter = ',', value_name = "IDX[,IDX...]")]
+    sort: Vec<usize>,
+
+    /// Direction of --sort, for numeric and text columns alike (default: numbers descending, text ascending)
+    #[arg(long, value_enum, requires = "sort")]
+    sort_order: Option<SortOrder>,
+
+    /// Also treat values like `~5ms`, `>100` or `<1%` as numbers (for alignment and sorting)
+    #[arg(long)]
+    loose_numeric: bool,
+
+    /// Fit lines into this many columns by truncating the widest cells with '…'
+    #[arg(long, visible_alias = "max-width", value_name = "N")]
+    width: Option<usize>,
+
+    /// With --width, re-expand truncated columns into the room left by narrower ones
+    #[arg(long, visible_alias = "redistribute", requires = "width")]
+    two_pass_widths: bool,
+
+    /// Cell alignment within columns ("auto" right-aligns numeric columns and left-aligns the rest)
+    #[arg(long, value_enum, default_value_t = Align::Auto)]
+    align: Align,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Plain)]
+    format: OutputFormat,
+
+    /// Split cells on every occurrence of this character (e.g. ',' or ';') instead of on runs of whitespace.
+    /// Empty fields between consecutive delimiters are kept as empty cells.
+    #[arg(short, long, value_name = "CHAR")]
+    delimiter: Option<char>,
+
+    /// Pad numeric columns so their decimal points line up (instead of right-aligning whole values)
+    #[arg(long)]
+    decimal_align: bool,
+
+    /// Truncate any cell wider than N visible columns to N-1 of them plus '…' (colors are kept intact)
+    #[arg(long, value_name = "N", value_parser = parse_col_width)]
+    max_col_width: Option<usize>,
+
+    /// With --max-col-width, wrap wide cells onto continuation lines instead of truncating them
+    #[arg(long, requires = "max_col_width")]
+    wrap: bool,
+
+    /// Exempt the first column (e.g. the keys of a key/value dump) from --max-col-width and --width:
+    /// it's never truncated or wrapped, but still padded like the others
+    #[arg(long)]
+    pin_first_col: bool,
+
+    /// Group the digits of numbers in thousands, like `80,000` (use `--group-digits=SEP` for another separator than ',',
+    /// or '.' with --decimal-comma)
+    #[arg(long, num_args = 0..=1, require_equals = true, value_name = "SEP", value_parser = parse_group_separator)]
+    group_digits: Option<Option<char>>,
+
+    /// Only print these columns (0-based indices), in the given order. --sort indices still refer to the input columns.
+    #[arg(long, value_delimiter = ',', value_name = "IDX[,IDX...]")]
+    columns: Vec<usize>,
+
+    /// Rearrange the columns: the listed ones (0-based) come first, in this order, then the rest as they were.
+    /// Applied right after splitting; --sort and --columns indices refer to the rearranged table.
+    #[arg(long, value_delimiter = ',', value_name = "IDX[,IDX...]")]
+    reorder: Vec<usize>,
+
+    /// Swap rows and columns (the first input column becomes the header row). Reads all input before printing.
     #[arg(long)]
-    sort: Option<usize>,
+    transpose: bool,
+
+    /// Pass lines starting with PREFIX (e.g. '#') through untouched, in place, without them affecting the columns.
+    /// Under --sort, comments keep their position among the rows.
+    #[arg(long, value_name = "PREFIX")]
+    comment: Option<String>,
+
+    /// Split cells on matches of this regex instead of on 2+ spaces / tabs (e.g. '\s*\|\s*').
+    /// Like the default, it's applied to the line with its outer whitespace trimmed.
+    #[arg(long, value_name = "PATTERN", value_parser = Regex::new, conflicts_with = "delimiter")]
+    split_regex: Option<Regex>,
+
+    /// Collapse the rows sharing a value in column IDX into one row per value (in order of first appearance),
+    /// holding that value and the --agg columns. Rows too short to have column IDX are grouped under an empty value.
+    #[arg(long, value_name = "IDX")]
+    group_by: Option<usize>,
+
+    /// With --group-by, aggregate column IDX per group with FUNC: sum, avg, min, max or count (rows per group; IDX is optional).
+    /// Only numbers are aggregated, placeholders like '-' are skipped. Without --agg, groups get their row count.
+    #[arg(long, value_delimiter = ',', value_name = "FUNC:IDX[,...]", value_parser = parse_aggregate, requires = "group_by")]
+    agg: Vec<(Aggregate, usize)>,
+
+    /// Print the columns right-to-left (the last one first), after sorting. Cell contents are left as they are.
+    #[arg(long)]
+    rtl: bool,
+
+    /// Put a line of dashes under the first (header) row, spanning each column
+    #[arg(long)]
+    header_rule: bool,
+
+    /// With --sort, order text columns "naturally": digit runs compare by value, so `item2` comes before `item10`
+    #[arg(long, requires = "sort")]
+    natural_sort: bool,
+
+    /// Print the data rows bottom-up (after any sorting); the header stays on top
+    #[arg(long)]
+    reverse: bool,
+
+    /// Append a row with the sum of each numeric column (the header isn't counted), computed after sorting
+    #[arg(long, conflicts_with = "stream")]
+    totals: bool,
+
+    /// With --totals, what to put in the footer's leftmost non-numeric column
+    #[arg(long, value_name = "TEXT", default_value = DEFAULT_TOTALS_LABEL, requires = "totals")]
+    totals_label: String,
+
+    /// Append a column of bars charting the numbers of column IDX, scaled so that the biggest spans --bar-width
+    #[arg(long, value_name = "IDX")]
+    bar: Option<usize>,
+
+    /// With --bar, how many columns the longest bar takes up
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_BAR_WIDTH, requires = "bar")]
+    bar_width: usize,
+
+    /// Only print the first N data rows (after sorting; the header is kept). Columns are sized for those rows.
+    #[arg(long, value_name = "N", conflicts_with_all = ["tail", "stream"])]
+    head: Option<usize>,
+
+    /// Only print the last N data rows (after sorting; the header is kept). Columns are sized for those rows.
+    #[arg(long, value_name = "N", conflicts_with = "stream")]
+    tail: Option<usize>,
+
+    /// With --sort, compare text regardless of case (cells are still printed as they are)
+    #[arg(long, requires = "sort")]
+    sort_ignore_case: bool,
+
+    /// With --sort, keep rows with equal keys in their input order (descending sorts otherwise reverse them)
+    #[arg(long, requires = "sort")]
+    stable: bool,
+
+    /// The first row is data too: it's sorted and counts towards numeric detection (and JSON keys become col0, col1...)
+    #[arg(long)]
+    no_header: bool,
+
+    /// Read numbers the European way, like `1.234,56` (`,` as the decimal point, `.` between thousands)
+    #[arg(long)]
+    decimal_comma: bool,
+
+    /// Write the table into this file instead of stdout (the input has been read completely by then, so it may be the same file)
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Format and print every N lines on their own so memory stays bounded on huge inputs.
+    /// Column widths (and sorting) are then per window instead of over the whole table; the header is only printed once.
+    /// Only for the plain and json formats.
+    #[arg(long, value_name = "N", conflicts_with = "transpose")]
+    stream: Option<usize>,
+
+    /// Color the numbers of column IDX from green (smallest) to red (largest); the header stays uncolored
+    #[arg(long, value_name = "IDX")]
+    heatmap: Option<usize>,
+
+    /// When to print colors: escape codes in the input cells are kept, and --heatmap colors added, only with "always",
+    /// or with "auto" when printing to a terminal and NO_COLOR isn't set. Otherwise cells are stripped of their codes
+    /// before being measured, and --heatmap does nothing.
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+
+    /// Prepend a column numbering the rows from 1, in printed order (the header gets '#')
+    #[arg(long)]
+    number: bool,
+
+    /// With --number, number the rows in their input order, so sorting carries the numbers along
+    #[arg(long, requires = "number")]
+    number_before_sort: bool,
+
+    /// Cut lines into cells at fixed character positions instead of splitting them, e.g. '0-8,9-20,21-'
+    /// (ranges are inclusive; a trailing '-' runs to the end of the line). Cells are trimmed.
+    #[arg(long, value_delimiter = ',', value_name = "START-[END][,...]", value_parser = parse_char_range, conflicts_with_all = ["delimiter", "split_regex"])]
+    fixed_cols: Vec<(usize, Option<usize>)>,
+
+    /// Expand tabs within cells to the next multiple of N columns (0 keeps them as they are).
+    /// By default tabs separate cells, but with --delimiter (other than a tab) or --split-regex
+    /// they're content and get expanded; with --fixed-cols they're expanded first, so positions are on-screen columns.
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_TAB_WIDTH)]
+    tab_width: usize,
+}
+
+fn parse_group_separator(arg: &str) -> Result<char, String> {
+    match arg.parse::<char>() {
+        Ok(c) if c.is_ascii_digit() => Err("a digit can't separate digits".to_string()),
+        Ok(c) => Ok(c),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_col_width(arg: &str) -> Result<usize, String> {
+    match arg.parse::<usize>() {
+        Ok(0) => Err("a column needs to be at least 1 wide".to_string()),
+        Ok(width) => Ok(width),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_aggregate(arg: &str) -> Result<(Aggregate, usize), String> {
+    let (name, col) = arg.split_once(':').map_or((arg, None), |(name, col)| (name, Some(col)));
+    let aggregate = match name.trim() {
+        "sum" => Aggregate::Sum,
+        "avg" => Aggregate::Avg,
+        "min" => Aggregate::Min,
+        "max" => Aggregate::Max,
+        "count" => Aggregate::Count,
+        other => return Err(format!("unknown aggregate '{other}' (expected sum, avg, min, max or count)")),
+    };
+    match col {
+        Some(col) => col.trim().parse().map(|col| (aggregate, col)).map_err(|e| format!("bad column in '{arg}': {e}")),
+        None if aggregate == Aggregate::Count => Ok((aggregate, 0)),
+        None => Err(format!("'{arg}' needs a column, like '{name}:3'")),
+    }
+}
+
+fn parse_char_range(arg: &str) -> Result<(usize, Option<usize>), String> {
+    let (start, end) = arg.split_once('-').ok_or_else(|| format!("'{arg}' isn't a START-END or START- range"))?;
+    let start: usize = start.trim().parse().map_err(|e| format!("bad start in '{arg}': {e}"))?;
+    let end: Option<usize> = match end.trim() {
+        "" => None,
+        end => Some(end.parse().map_err(|e| format!("bad end in '{arg}': {e}"))?),
+    };
+    match end {
+        Some(end) if end < start => Err(format!("'{arg}' ends before it starts")),
+        _ => Ok((start, end)),
+    }
 }
 
 // ——— Main Function ——————————————————————————————————————
-fn main() -> io::Result<()> {
+fn main() -> ExitCode {
     let args = Args::parse();
+    if let Err(err) = check_combinations(&args) { err.exit(); }
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Conflicts between argument values, which clap can't declare by itself
+fn check_combinations(args: &Args) -> Result<(), clap::Error> {
+    if args.stream.is_some() && !args.format.is_streamable() {
+        let format = args.format.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+        return Err(Args::command().error(clap::error::ErrorKind::ArgumentConflict, format!("--stream can't be used with --format {format}")));
+    }
+    let point = if args.decimal_comma { ',' } else { '.' };
+    if args.group_digits == Some(Some(point)) {
+        return Err(Args::command().error(clap::error::ErrorKind::ValueValidation, format!("--group-digits can't use '{point}', which is the decimal point")));
+    }
+    Ok(())
+}
+
+fn run(args: Args) -> io::Result<()> {
+    let colorize = match args.color {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => args.output.is_none() && io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+    };
+    let options = FormatOptions {
+        separator: args.separator,
+        sep_spec: args.sep_spec,
+        rtl: args.rtl,
+        group_by: args.group_by,
+        aggregates: args.agg,
+        pin_first_col: args.pin_first_col,
+        sort: args.sort,
+        sort_order: args.sort_order,
+        loose_numeric: args.loose_numeric,
+        width: args.width,
+        two_pass_widths: args.two_pass_widths,
+        align: args.align,
+        format: args.format,
+        delimiter: args.delimiter,
+        decimal_align: args.decimal_align,
+        max_col_width: args.max_col_width,
+        wrap: args.wrap,
+        group_digits: args.group_digits.map(|separator| separator.unwrap_or(if args.decimal_comma { '.' } else { ',' })),
+        columns: args.columns,
+        reorder: args.reorder,
+        transpose: args.transpose,
+        comment: args.comment,
+        split_regex: args.split_regex,
+        header_rule: args.header_rule,
+        natural_sort: args.natural_sort,
+        stable: args.stable,
+        no_header: args.no_header,
+        decimal_comma: args.decimal_comma,
+        heatmap: args.heatmap.filter(|_| colorize),
+        strip_colors: !colorize,
+        number: args.number,
+        number_before_sort: args.number_before_sort,
+        fixed_cols: args.fixed_cols,
+        tab_width: args.tab_width,
+        sort_ignore_case: args.sort_ignore_case,
+        reverse: args.reverse,
+        totals: args.totals,
+        totals_label: args.totals_label,
+        bar: args.bar,
+        bar_width: args.bar_width,
+        head: args.head,
+        tail: args.tail,
+    };
+
+    if let Some(window) = args.stream.filter(|_| !args.input.contains('\n')) {
+        let reader: Box<dyn BufRead> = if args.input == "-" { Box::new(io::stdin().lock()) }
+            else { Box::new(open_input(&args.input)?) };
+        return match args.output.as_deref() {
+            Some(path) => stream_table(reader, &options, window, &mut BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, create_output(path)?)),
+            None => stream_table(reader, &options, window, &mut BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, io::stdout().lock())),
+        };
+    }
 
     // get the data from input (file / arg-str / stdin)
-    let lines: Vec<String> = if args.input == "-" {
-        io::stdin().lock().lines().collect::<Result<_, _>>()?
+    let table = if args.input == "-" {
+        Table::from_reader(io::stdin().lock(), &options)?
     } else if args.input.contains('\n') {
         // multiline string provided directly → treat as raw data rather than filepath
-        args.input.lines().map(|s| s.to_string()).collect()
+        Table::from_lines(&args.input.lines().map(|s| s.to_string()).collect::<Vec<_>>(), &options)
     } else {
-        // BufReader::new(File::open(&args.input)?).lines().collect::<Result<_, _>>()?  // assumes utf8 input
-        let mut buf = Vec::new();
-        BufReader::new(File::open(args.input).unwrap()).read_to_end(&mut buf)?;
-        String::from_utf8_lossy(&buf)  // replaces invalid utf8 with '�'
-            .lines()
-            .map(|s| s.to_string())
-            .collect()
+        Table::from_reader(open_input(&args.input)?, &options)?  // replaces invalid utf8 with '�'
     };
 
-    print_table(&lines, args.separator, args.sort);
-    Ok(())
+    print_table(table, &options, args.output.as_deref())
+}
+
+/// Open the input file, with the path (and what else the argument might have been meant as) in the error
+fn open_input(path: &str) -> io::Result<BufReader<File>> {
+    let opened = File::open(path).and_then(|file| match file.metadata()?.is_dir() {
+        true => Err(io::Error::new(io::ErrorKind::InvalidInput, "it's a directory")),
+        false => Ok(file),
+    });
+    opened.map(BufReader::new).map_err(|e| {
+        // a single line of data looks like a path too
+        let hint = if e.kind() == io::ErrorKind::NotFound && path.contains(char::is_whitespace) {
+            " (data given as an argument needs more than one line; pipe a single line in instead)"
+        } else { "" };
+        io::Error::new(e.kind(), format!("cannot open '{path}': {e}{hint}"))
+    })
+}
+
+fn create_output(path: &Path) -> io::Result<File> {
+    File::create(path).map_err(|e| io::Error::new(e.kind(), format!("cannot create '{}': {e}", path.display())))
 }
 
 // Include tests
@@ -1,7 +1,8 @@
 use std::fs::File;
 use assert_cmd::Command;
-use crate::{format_table, strip_ansi, is_numeric_or_neutral, DEFAULT_SEPARATOR};
+use crate::{format_table, write_table, stream_table, strip_ansi, visible_len, is_numeric_or_neutral, FormatOptions, OutputFormat, SortOrder, Table};
 use test_case::test_case;
+use regex::Regex;
 
 // numerical column needs to align right
 // extra excessive spaces need to be trimmed off
@@ -146,7 +147,7 @@ const SPECIAL_CHARS: &[&str] = &[
 const SPECIAL_CHARS_ORGANIZED: &[&str] = &[
     "A   B",
     "1   x",
-    "🌎   X",
+    "🌎  X",
     "🇺🇸  X",
     "3   X",
 ];
@@ -155,6 +156,14 @@ fn to_strings(arr: &[&str]) -> Vec<String> {
     arr.iter().map(|s| s.to_string()).collect()
 }
 
+fn sorted_by(col_idx: usize) -> FormatOptions {
+    FormatOptions { sort: vec![col_idx], ..Default::default() }
+}
+
+fn sorted_by_in(col_idx: usize, order: SortOrder) -> FormatOptions {
+    FormatOptions { sort: vec![col_idx], sort_order: Some(order), ..Default::default() }
+}
+
 fn assert_cmd_and_print(command: &mut Command) -> Vec<String> {
     let output = command.output()
         .expect("failed to execute process");
@@ -172,21 +181,32 @@ fn assert_cmd_and_print(command: &mut Command) -> Vec<String> {
         .collect::<Vec<_>>()
 }
 
+// stdout is a pipe in these, which would strip the cells' colors
 fn run_with_cmdline_arg(arg: &str) -> Vec<String> {
     assert_cmd_and_print(
         Command::cargo_bin("table_formatter").unwrap()
-            .arg(arg)
+            .arg(arg).args(["--color", "always"])
     )
 }
 
 fn run_with_piped_data(piped: &str) -> Vec<String> {
     assert_cmd_and_print(
         Command::cargo_bin("table_formatter").unwrap()
+            .args(["--color", "always"])
             .write_stdin(piped)
     )
 }
 fn direct_test(input: &[&str], expected: &[&str]) {  // call the actual function directly
-    assert_eq!(format_table(&to_strings(input), DEFAULT_SEPARATOR, None), to_strings(expected));
+    assert_eq!(format_table(&to_strings(input), &FormatOptions::default()), to_strings(expected));
+}
+
+fn reader_input_test(input: &[&str], expected: &[&str]) {  // parse straight from a reader instead of collected lines
+    use std::io::Cursor;
+
+    let options = FormatOptions::default();
+    let from_reader = Table::from_reader(Cursor::new(input.join("\n")), &options).unwrap();
+    assert_eq!(from_reader, Table::from_lines(&to_strings(input), &options));
+    assert_eq!(from_reader.format(&options), to_strings(expected));
 }
 
 fn file_input_test(input: &[&str], expected: &[&str]) {  // run the program through its bin-file and provide a temp-file
@@ -212,7 +232,7 @@ fn piped_input_test(input: &[&str], expected: &[&str]) {
 }
 
 fn check_immutability_on_2nd_run(input: &[&str]) {  // input is a pre-organized table. There's nothing to further organize.
-    assert_eq!(format_table(&to_strings(input), DEFAULT_SEPARATOR, None), to_strings(input));
+    assert_eq!(format_table(&to_strings(input), &FormatOptions::default()), to_strings(input));
 }
 
 #[test_case(SAMPLE_INPUT, SAMPLE_OUTPUT)]
@@ -224,6 +244,7 @@ fn check_immutability_on_2nd_run(input: &[&str]) {  // input is a pre-organized
 #[test_case(SPECIAL_CHARS, SPECIAL_CHARS_ORGANIZED)]
 fn test_sets(input: &[&str], expected: &[&str]) {
     direct_test(input, expected);
+    reader_input_test(input, expected);
     file_input_test(input, expected);
     string_input_test(input, expected);
     piped_input_test(input, expected);
@@ -292,8 +313,8 @@ fn test_sorting() {
         "A  1  c  d  e  f  g",
     ];
 
-    assert_eq!(format_table(&to_strings(VARYING_LENGTH_TABLE), DEFAULT_SEPARATOR, Some(0)), to_strings(VARYING_LENGTH_TABLE_SORT0_ORGANIZED));
-    assert_eq!(format_table(&to_strings(VARYING_LENGTH_TABLE), DEFAULT_SEPARATOR, Some(1)), to_strings(VARYING_LENGTH_TABLE_SORT1_ORGANIZED));
+    assert_eq!(format_table(&to_strings(VARYING_LENGTH_TABLE), &sorted_by(0)), to_strings(VARYING_LENGTH_TABLE_SORT0_ORGANIZED));
+    assert_eq!(format_table(&to_strings(VARYING_LENGTH_TABLE), &sorted_by(1)), to_strings(VARYING_LENGTH_TABLE_SORT1_ORGANIZED));
 
 
     const SORT_TESTER: &[&str] = &[
@@ -324,9 +345,1362 @@ fn test_sorting() {
         "3     9  3.5K",
     ];
 
-    assert_eq!(format_table(&to_strings(SORT_TESTER), DEFAULT_SEPARATOR, Some(1)), to_strings(SORT_TESTER_SORT1));
-    assert_eq!(format_table(&to_strings(SORT_TESTER), DEFAULT_SEPARATOR, Some(2)), to_strings(SORT_TESTER_SORT2));
+    assert_eq!(format_table(&to_strings(SORT_TESTER), &sorted_by(1)), to_strings(SORT_TESTER_SORT1));
+    assert_eq!(format_table(&to_strings(SORT_TESTER), &sorted_by(2)), to_strings(SORT_TESTER_SORT2));
+
+}
+
+#[test]
+fn test_sort_order() {
+    const VARYING_LENGTH_TABLE_SORT0_DESC: &[&str] = &[
+        "A  1  c  d  e  f  g",
+        "H  6               ",
+        "G  7  c  d  e  f  g",
+        "E  5  c  d  e  f  g",
+        "D  3  c            ",
+        "C  4  c  d  e  f  g",
+        "B  8               ",
+    ];
+
+    const VARYING_LENGTH_TABLE_SORT1_ASC: &[&str] = &[
+        "A  1  c  d  e  f  g",
+        "D  3  c            ",
+        "C  4  c  d  e  f  g",
+        "E  5  c  d  e  f  g",
+        "H  6               ",
+        "G  7  c  d  e  f  g",
+        "B  8               ",
+    ];
+
+    const VARYING_LENGTH_TABLE_SORT0_ASC: &[&str] = &[
+        "A  1  c  d  e  f  g",
+        "B  8               ",
+        "C  4  c  d  e  f  g",
+        "D  3  c            ",
+        "E  5  c  d  e  f  g",
+        "G  7  c  d  e  f  g",
+        "H  6               ",
+    ];
+
+    let table = to_strings(VARYING_LENGTH_TABLE);
+    assert_eq!(format_table(&table, &sorted_by_in(0, SortOrder::Asc)), to_strings(VARYING_LENGTH_TABLE_SORT0_ASC));
+    assert_eq!(format_table(&table, &sorted_by_in(0, SortOrder::Desc)), to_strings(VARYING_LENGTH_TABLE_SORT0_DESC));
+    assert_eq!(format_table(&table, &sorted_by_in(1, SortOrder::Asc)), to_strings(VARYING_LENGTH_TABLE_SORT1_ASC));
+    assert_eq!(format_table(&table, &sorted_by_in(1, SortOrder::Desc)), format_table(&table, &sorted_by(1)));
+
+
+    const SORT_TESTER: &[&str] = &[
+        "X     X     X",
+        "2  1000    2M",
+        "3     9  3.5K",
+        "4     5    9G",
+        "5     6    3G",
+        "6     8   10T",
+        "7     9  288M",
+    ];
+    const SORT_TESTER_SORT1_ASC: &[&str] = &[
+        "X     X     X",
+        "4     5    9G",
+        "5     6    3G",
+        "6     8   10T",
+        "3     9  3.5K",
+        "7     9  288M",
+        "2  1000    2M",
+    ];
+    const SORT_TESTER_SORT2_ASC: &[&str] = &[
+        "X     X     X",
+        "3     9  3.5K",
+        "2  1000    2M",
+        "7     9  288M",
+        "5     6    3G",
+        "4     5    9G",
+        "6     8   10T",
+    ];
+
+    // header row stays on top in both directions
+    let table = to_strings(SORT_TESTER);
+    assert_eq!(format_table(&table, &sorted_by_in(1, SortOrder::Asc)), to_strings(SORT_TESTER_SORT1_ASC));
+    assert_eq!(format_table(&table, &sorted_by_in(2, SortOrder::Asc)), to_strings(SORT_TESTER_SORT2_ASC));
+    assert_eq!(format_table(&table, &sorted_by_in(1, SortOrder::Desc)), format_table(&table, &sorted_by(1)));
+    assert_eq!(format_table(&table, &sorted_by_in(2, SortOrder::Desc)), format_table(&table, &sorted_by(2)));
+}
+
+#[test]
+fn test_multi_column_sort() {
+    const SCORES: &[&str] = &[
+        "team  score  name",
+        "b  10  zed",
+        "a  5  amy",
+        "b  20  bob",
+        "a  5  cat",
+        "a  7  dan",
+    ];
+    const SCORES_SORT0_1: &[&str] = &[
+        "team  score  name",
+        "a         7  dan ",
+        "a         5  amy ",
+        "a         5  cat ",
+        "b        20  bob ",
+        "b        10  zed ",
+    ];
+    const SCORES_SORT1_0_2: &[&str] = &[
+        "team  score  name",
+        "b        20  bob ",
+        "b        10  zed ",
+        "a         7  dan ",
+        "a         5  amy ",
+        "a         5  cat ",
+    ];
+    const SCORES_SORT1_2_ASC: &[&str] = &[
+        "team  score  name",
+        "a         5  amy ",
+        "a         5  cat ",
+        "a         7  dan ",
+        "b        10  zed ",
+        "b        20  bob ",
+    ];
+
+    let table = to_strings(SCORES);
+    let sorted_by_keys = |keys: &[usize]| FormatOptions { sort: keys.to_vec(), ..Default::default() };
+    assert_eq!(format_table(&table, &sorted_by_keys(&[0, 1])), to_strings(SCORES_SORT0_1));
+    assert_eq!(format_table(&table, &sorted_by_keys(&[1, 0, 2])), to_strings(SCORES_SORT1_0_2));
+    assert_eq!(format_table(&table, &FormatOptions { sort_order: Some(SortOrder::Asc), ..sorted_by_keys(&[1, 2]) }), to_strings(SCORES_SORT1_2_ASC));
+    // a single key behaves as before
+    assert_eq!(format_table(&table, &sorted_by_keys(&[1])), format_table(&table, &sorted_by(1)));
+    // keys past the last column sort as empty text, instead of panicking
+    assert_eq!(format_table(&table, &sorted_by_keys(&[0, 5])), format_table(&table, &sorted_by(0)));
+    let mut past_the_end = format_table(&table, &sorted_by(5));
+    past_the_end.sort();
+    let mut unsorted = format_table(&table, &FormatOptions::default());
+    unsorted.sort();
+    assert_eq!(past_the_end, unsorted);
+
+    let output = assert_cmd_and_print(Command::cargo_bin("table_formatter").unwrap().args(["--sort", "0,1", &SCORES.join("\n")]));
+    assert_eq!(output, to_strings(SCORES_SORT0_1));
+}
+
+#[test]
+fn test_decimal_align() {
+    const MEASUREMENTS: &[&str] = &[
+        "name  val  size",
+        "a  3.5  2M",
+        "b  288  3.5K",
+        "c  12.25  -",
+        "d  0.125  10.5G",
+    ];
+    const MEASUREMENTS_DECIMAL_ALIGNED: &[&str] = &[
+        "name      val   size",
+        "a       3.5    2M   ",
+        "b     288       3.5K",
+        "c      12.25    -   ",
+        "d       0.125  10.5G",
+    ];
+    const MEASUREMENTS_RIGHT_ALIGNED: &[&str] = &[
+        "name    val   size",
+        "a       3.5     2M",
+        "b       288   3.5K",
+        "c     12.25      -",
+        "d     0.125  10.5G",
+    ];
+
+    let decimal = FormatOptions { decimal_align: true, ..Default::default() };
+    assert_eq!(format_table(&to_strings(MEASUREMENTS), &decimal), to_strings(MEASUREMENTS_DECIMAL_ALIGNED));
+    assert_eq!(format_table(&to_strings(MEASUREMENTS), &FormatOptions::default()), to_strings(MEASUREMENTS_RIGHT_ALIGNED));
+}
+
+#[test]
+fn test_max_col_width() {
+    const PATHS: &[&str] = &[
+        "path  size",
+        "/usr/share/doc/very-long-package-name/README.md  12K",
+        "/etc/\u{1b}[31mhosts.allow.d\u{1b}[0m  1K",
+        "/bin  -",
+    ];
+    const PATHS_TRUNCATED: &[&str] = &[
+        "path          size",
+        "/usr/share/…   12K",
+        "/etc/\u{1b}[31mhosts.…\u{1b}[0m    1K",
+        "/bin             -",
+    ];
+
+    let capped = FormatOptions { max_col_width: Some(12), ..Default::default() };
+    let output = format_table(&to_strings(PATHS), &capped);
+    assert_eq!(output, to_strings(PATHS_TRUNCATED));
+    assert!(output.iter().all(|line| visible_len(line) == 18));
+}
+
+#[test]
+fn test_pin_first_col() {
+    const SETTINGS: &[&str] = &[
+        "org.gnome.desktop.interface  cursor-theme-name-that-is-long",
+        "ui.scale  1.25",
+    ];
+
+    let pinned = FormatOptions { max_col_width: Some(10), pin_first_col: true, no_header: true, ..Default::default() };
+    assert_eq!(format_table(&to_strings(SETTINGS), &pinned), to_strings(&[
+        "org.gnome.desktop.interface  cursor-th…",
+        "ui.scale                     1.25      ",
+    ]));
+    assert_eq!(format_table(&to_strings(SETTINGS), &FormatOptions { wrap: true, ..pinned.clone() }), to_strings(&[
+        "org.gnome.desktop.interface  cursor-the",
+        "                             me-name-th",
+        "                             at-is-long",
+        "ui.scale                     1.25      ",
+    ]));
+    let fitted = FormatOptions { width: Some(34), pin_first_col: true, no_header: true, ..Default::default() };
+    assert_eq!(format_table(&to_strings(SETTINGS), &fitted), to_strings(&[
+        "org.gnome.desktop.interface  curs…",
+        "ui.scale                     1.25 ",
+    ]));
+}
+
+#[test]
+fn test_wrap_cells() {
+    const PATHS: &[&str] = &[
+        "path  size",
+        "/usr/share/doc/readme  12K",
+        "/etc/\u{1b}[31mhosts.allow\u{1b}[0m  1K",
+        "/bin  -",
+    ];
+    const PATHS_WRAPPED: &[&str] = &[
+        "path      size",
+        "/usr/sha   12K",
+        "re/doc/r      ",
+        "eadme         ",
+        "/etc/\u{1b}[31mhos\u{1b}[0m    1K",
+        "\u{1b}[31mts.allow\u{1b}[0m      ",
+        "/bin         -",
+    ];
+    const WIDE_WRAPPED: &[&str] = &[
+        "ハハハ   1",
+        "ハハハ    ",
+        "ハ        ",
+        "ab       2",
+    ];
+
+    let wrapped = FormatOptions { max_col_width: Some(8), wrap: true, ..Default::default() };
+    let output = format_table(&to_strings(PATHS), &wrapped);
+    assert_eq!(output, to_strings(PATHS_WRAPPED));
+    assert!(output.iter().all(|line| visible_len(line) == 14));
+
+    // double-width characters aren't split in half; a line they leave short gets padded instead
+    let output = format_table(&to_strings(&["ハハハハハハハ  1", "ab  2"]), &FormatOptions { max_col_width: Some(7), ..wrapped.clone() });
+    assert_eq!(output, to_strings(WIDE_WRAPPED));
+
+    // the pieces of a wrapped cell are aligned like any unwrapped one
+    let centered = FormatOptions { max_col_width: Some(4), align: crate::Align::Center, ..wrapped };
+    let output = format_table(&to_strings(&["name  v", "abcdefghij  1", "x  2"]), &centered);
+    assert_eq!(output, to_strings(&["name  v", "abcd  1", "efgh   ", " ij    ", " x    2"]));
+
+    Command::cargo_bin("table_formatter").unwrap().args(["--max-col-width", "0"]).write_stdin("a\n").assert().failure().code(2);
+}
+
+#[test]
+fn test_box_output() {
+    const SMTOUHOU_DATA_BOX: &[&str] = &[
+        "┌───┬────────────┬─────┬─────┬─────┬─────┬─────┐",
+        "│ # │ Name       │ Lv. │  HP │  MP │ ATK │ DEF │",
+        "├───┼────────────┼─────┼─────┼─────┼─────┼─────┤",
+        "│ 1 │ Reimu      │  40 │ 193 │ 211 │  63 │  82 │",
+        "│ 2 │ Marisa     │  28 │ 125 │ 166 │  46 │  57 │",
+        "│ 3 │ Shingyoku  │  89 │ 620 │ 505 │ 202 │ 182 │",
+        "│ 4 │ Yugenmagan │  87 │ 628 │ 576 │ 176 │ 189 │",
+        "│ 5 │ Elis       │  78 │ 495 │ 448 │ 215 │ 145 │",
+        "│ 6 │ Sariel     │  90 │ 690 │ 630 │ 164 │ 217 │",
+        "│ 7 │ Mima       │  74 │ 494 │ 472 │ 146 │ 166 │",
+        "└───┴────────────┴─────┴─────┴─────┴─────┴─────┘",
+    ];
+    const WRAPPED_BOX: &[&str] = &[
+        "┌──────┬──────┐",
+        "│ abc… │ bcd… │",
+        "└──────┴──────┘",
+    ];
+
+    let boxed = FormatOptions { format: OutputFormat::Box, ..Default::default() };
+    let output = format_table(&to_strings(SMTOUHOU_DATA), &boxed);
+    assert_eq!(output, to_strings(SMTOUHOU_DATA_BOX));
+    assert!(output.iter().all(|line| visible_len(line) == visible_len(SMTOUHOU_DATA_BOX[0])));
+
+    // --width accounts for the borders
+    let narrow = FormatOptions { width: Some(15), ..boxed };
+    assert_eq!(format_table(&to_strings(&["abcdefghij  bcdefgh"]), &narrow), to_strings(WRAPPED_BOX));
+}
+
+#[test]
+fn test_group_digits() {
+    const TRAFFIC: &[&str] = &[
+        "host  bytes  rate",
+        "a  80000  288000M",
+        "b  1234567.891  3.5K",
+        "c  -  12",
+        "d  -4500  0x1F00",
+    ];
+    const TRAFFIC_GROUPED_SORT1: &[&str] = &[
+        "host          bytes      rate",
+        "b     1,234,567.891      3.5K",
+        "a            80,000  288,000M",
+        "c                 -        12",
+        "d            -4,500    0x1F00",
+    ];
+    const YEARLY: &[&str] = &[
+        "2023  2024",
+        "12000  3000",
+    ];
+    const YEARLY_GROUPED: &[&str] = &[
+        "  2023   2024",
+        "12_000  3_000",
+    ];
+
+    let grouped = FormatOptions { group_digits: Some(','), sort: vec![1], ..Default::default() };
+    assert_eq!(format_table(&to_strings(TRAFFIC), &grouped), to_strings(TRAFFIC_GROUPED_SORT1));
+
+    let mut reader = TRAFFIC.join("\n").into_bytes();
+    reader.push(b'\n');
+    let table = Table::from_reader(reader.as_slice(), &grouped).unwrap();
+    assert_eq!(table.format(&grouped), to_strings(TRAFFIC_GROUPED_SORT1));
+
+    // the header row is left as is, even when it looks like numbers
+    let underscores = FormatOptions { group_digits: Some('_'), ..Default::default() };
+    assert_eq!(format_table(&to_strings(YEARLY), &underscores), to_strings(YEARLY_GROUPED));
+}
+
+#[test]
+fn test_column_projection() {
+    const SMTOUHOU_DATA_NAME_DEF_HP: &[&str] = &[
+        "Name        DEF   HP",
+        "Reimu        82  193",
+        "Marisa       57  125",
+        "Shingyoku   182  620",
+        "Yugenmagan  189  628",
+        "Elis        145  495",
+        "Sariel      217  690",
+        "Mima        166  494",
+    ];
+    const SMTOUHOU_DATA_NAME_DEF_HP_SORT6: &[&str] = &[
+        "Name        DEF   HP",
+        "Sariel      217  690",
+        "Yugenmagan  189  628",
+        "Shingyoku   182  620",
+        "Mima        166  494",
+        "Elis        145  495",
+        "Reimu        82  193",
+        "Marisa       57  125",
+    ];
+    const VARYING_LENGTH_TABLE_COLUMNS: &[&str] = &[
+        "A  c  1",
+        "B     8",
+        "C  c  4",
+        "D  c  3",
+        "E  c  5",
+        "H     6",
+        "G  c  7",
+    ];
+
+    let projected = FormatOptions { columns: vec![1, 6, 3, 42], ..Default::default() };
+    assert_eq!(format_table(&to_strings(SMTOUHOU_DATA), &projected), to_strings(SMTOUHOU_DATA_NAME_DEF_HP));
+    // sort keys refer to the input's columns
+    let sorted = FormatOptions { sort: vec![6], ..projected };
+    assert_eq!(format_table(&to_strings(SMTOUHOU_DATA), &sorted), to_strings(SMTOUHOU_DATA_NAME_DEF_HP_SORT6));
+
+    // rows too short for a column get a blank there; past their end, nothing
+    let projected = FormatOptions { columns: vec![0, 2, 1], ..Default::default() };
+    assert_eq!(format_table(&to_strings(VARYING_LENGTH_TABLE), &projected), to_strings(VARYING_LENGTH_TABLE_COLUMNS));
+}
+
+#[test]
+fn test_column_reorder() {
+    const SMTOUHOU_DATA_DEF_FIRST: &[&str] = &[
+        "DEF  #  Name        Lv.   HP   MP  ATK",
+        " 82  1  Reimu        40  193  211   63",
+        " 57  2  Marisa       28  125  166   46",
+        "182  3  Shingyoku    89  620  505  202",
+        "189  4  Yugenmagan   87  628  576  176",
+        "145  5  Elis         78  495  448  215",
+        "217  6  Sariel       90  690  630  164",
+        "166  7  Mima         74  494  472  146",
+    ];
+    const VARYING_LENGTH_TABLE_REORDERED: &[&str] = &[
+        "g  f  A  1  c  d  e",
+        "      B  8         ",
+        "g  f  C  4  c  d  e",
+        "      D  3  c      ",
+        "g  f  E  5  c  d  e",
+        "      H  6         ",
+        "g  f  G  7  c  d  e",
+    ];
+
+    let def_first = FormatOptions { reorder: vec![6], ..Default::default() };
+    assert_eq!(format_table(&to_strings(SMTOUHOU_DATA), &def_first), to_strings(SMTOUHOU_DATA_DEF_FIRST));
+    let full_spec = FormatOptions { reorder: vec![6, 0, 1, 2, 3, 4, 5], ..Default::default() };
+    assert_eq!(format_table(&to_strings(SMTOUHOU_DATA), &full_spec), to_strings(SMTOUHOU_DATA_DEF_FIRST));
+
+    // short rows get blanks for the moved cells they don't have, keeping the other columns in line
+    let reordered = FormatOptions { reorder: vec![6, 5], ..Default::default() };
+    assert_eq!(format_table(&to_strings(VARYING_LENGTH_TABLE), &reordered), to_strings(VARYING_LENGTH_TABLE_REORDERED));
+}
+
+#[test]
+fn test_transpose() {
+    const USAGE: &[&str] = &[
+        "metric  cpu  mem",
+        "jan  35  2G",
+        "feb  7  512M",
+        "mar",
+    ];
+    const USAGE_TRANSPOSED: &[&str] = &[
+        "metric  jan   feb  mar",
+        "cpu      35     7     ",
+        "mem      2G  512M     ",
+    ];
+
+    let transposed = FormatOptions { transpose: true, ..Default::default() };
+    assert_eq!(format_table(&to_strings(USAGE), &transposed), to_strings(USAGE_TRANSPOSED));
+
+    let mut reader = USAGE.join("\n").into_bytes();
+    reader.push(b'\n');
+    assert_eq!(Table::from_reader(reader.as_slice(), &transposed).unwrap(), Table::from_lines(&to_strings(USAGE), &transposed));
+}
+
+#[test]
+fn test_comment_lines() {
+    const ANNOTATED: &[&str] = &[
+        "# generated by du",
+        "name  size",
+        "tmp  10",
+        "    # the next ones are big   and old",
+        "var  2000",
+        "home  300",
+        "#",
+    ];
+    const ANNOTATED_ORGANIZED: &[&str] = &[
+        "# generated by du",
+        "name  size",
+        "tmp     10",
+        "    # the next ones are big   and old",
+        "var   2000",
+        "home   300",
+        "#",
+    ];
+    const ANNOTATED_SORT1: &[&str] = &[
+        "# generated by du",
+        "name  size",
+        "var   2000",
+        "    # the next ones are big   and old",
+        "home   300",
+        "tmp     10",
+        "#",
+    ];
+
+    let comments = FormatOptions { comment: Some("#".to_string()), ..Default::default() };
+    assert_eq!(format_table(&to_strings(ANNOTATED), &comments), to_strings(ANNOTATED_ORGANIZED));
+    // comments stay put while the rows around them get sorted
+    let sorted = FormatOptions { sort: vec![1], ..comments.clone() };
+    assert_eq!(format_table(&to_strings(ANNOTATED), &sorted), to_strings(ANNOTATED_SORT1));
+
+    let mut reader = ANNOTATED.join("\n").into_bytes();
+    reader.push(b'\n');
+    assert_eq!(Table::from_reader(reader.as_slice(), &comments).unwrap(), Table::from_lines(&to_strings(ANNOTATED), &comments));
+}
+
+#[test]
+fn test_split_regex() {
+    const PIPED: &[&str] = &[
+        "  name | size|kind  ",
+        "a.txt  |  12K | text",
+        "b.tar.gz | 3.5M|archive",
+    ];
+    const PIPED_ORGANIZED: &[&str] = &[
+        "name      size  kind   ",
+        "a.txt      12K  text   ",
+        "b.tar.gz  3.5M  archive",
+    ];
+
+    let piped = FormatOptions { split_regex: Some(Regex::new(r"\s*\|\s*").unwrap()), ..Default::default() };
+    assert_eq!(format_table(&to_strings(PIPED), &piped), to_strings(PIPED_ORGANIZED));
+
+    let output = Command::cargo_bin("table_formatter").unwrap().args(["--split-regex", "(", "a  b"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid value '(' for '--split-regex"));
+}
+
+#[test]
+fn test_header_rule() {
+    const SMTOUHOU_DATA_RULED: &[&str] = &[
+        "#   Name         Lv.    HP    MP   ATK   DEF",
+        "-   ----------   ---   ---   ---   ---   ---",
+        "1   Reimu         40   193   211    63    82",
+        "2   Marisa        28   125   166    46    57",
+        "3   Shingyoku     89   620   505   202   182",
+        "4   Yugenmagan    87   628   576   176   189",
+        "5   Elis          78   495   448   215   145",
+        "6   Sariel        90   690   630   164   217",
+        "7   Mima          74   494   472   146   166",
+    ];
+
+    let ruled = FormatOptions { header_rule: true, separator: 3, ..Default::default() };
+    assert_eq!(format_table(&to_strings(SMTOUHOU_DATA), &ruled), to_strings(SMTOUHOU_DATA_RULED));
+    assert_eq!(format_table(&[], &ruled), Vec::<String>::new());
+}
+
+#[test]
+fn test_natural_sort() {
+    const FILES: &[&str] = &[
+        "file  size",
+        "item10.txt  1K",
+        "item2.txt  3K",
+        "item1.txt  2K",
+        "item1b.txt  4K",
+        "item002.txt  5K",
+        "img12_v3.png  6K",
+        "img12_v10.png  7K",
+        "img9.png  8K",
+    ];
+    const FILES_NATURAL: &[&str] = &[
+        "file           size",
+        "img9.png         8K",
+        "img12_v3.png     6K",
+        "img12_v10.png    7K",
+        "item1.txt        2K",
+        "item1b.txt       4K",
+        "item002.txt      5K",
+        "item2.txt        3K",
+        "item10.txt       1K",
+    ];
+    const FILES_LEXICAL: &[&str] = &[
+        "file           size",
+        "img12_v10.png    7K",
+        "img12_v3.png     6K",
+        "img9.png         8K",
+        "item002.txt      5K",
+        "item1.txt        2K",
+        "item10.txt       1K",
+        "item1b.txt       4K",
+        "item2.txt        3K",
+    ];
+
+    let natural = FormatOptions { natural_sort: true, ..sorted_by(0) };
+    assert_eq!(format_table(&to_strings(FILES), &natural), to_strings(FILES_NATURAL));
+    assert_eq!(format_table(&to_strings(FILES), &sorted_by(0)), to_strings(FILES_LEXICAL));
+    // numeric columns sort the same either way
+    assert_eq!(format_table(&to_strings(FILES), &FormatOptions { natural_sort: true, ..sorted_by(1) }), format_table(&to_strings(FILES), &sorted_by(1)));
+}
+
+#[test]
+fn test_totals() {
+    const EXPENSES: &[&str] = &[
+        "item  qty  price",
+        "tea  2  0.1",
+        "pen  -  0.2",
+        "rent  1  900",
+    ];
+    const EXPENSES_TOTALS: &[&str] = &[
+        "item   qty  price",
+        "rent     1    900",
+        "pen      -    0.2",
+        "tea      2    0.1",
+        "TOTAL    3  900.3",
+    ];
+
+    let totals = FormatOptions { totals: true, ..sorted_by(2) };
+    assert_eq!(format_table(&to_strings(EXPENSES), &totals), to_strings(EXPENSES_TOTALS));
+
+    let labelled = FormatOptions { totals: true, totals_label: "Σ".to_string(), group_digits: Some(','), ..Default::default() };
+    assert_eq!(format_table(&to_strings(EXPENSES), &labelled).last().unwrap(), "Σ       3  900.3");
+    assert_eq!(format_table(&to_strings(&["n", "999", "1"]), &labelled).last().unwrap(), "1,000");  // no text column to label
+}
+
+#[test]
+fn test_bar_chart() {
+    const DISK: &[&str] = &[
+        "dir  size",
+        "src  40K",
+        "docs  -",
+        "target  1.6M",
+        "tests  100K",
+    ];
+    const DISK_BARS: &[&str] = &[
+        "dir     size          ",
+        "src      40K  ▎       ",
+        "docs       -          ",
+        "target  1.6M  ████████",
+        "tests   100K  ▌       ",
+    ];
+
+    let bars = FormatOptions { bar: Some(1), bar_width: 8, ..Default::default() };
+    let output = format_table(&to_strings(DISK), &bars);
+    assert_eq!(output, to_strings(DISK_BARS));
+    assert!(output.iter().all(|line| visible_len(line) == 22));
+}
+
+#[test]
+fn test_head_tail() {
+    assert_eq!(format_table(&to_strings(SMTOUHOU_DATA), &FormatOptions { head: Some(2), ..sorted_by(3) }), to_strings(&[
+        "#  Name        Lv.   HP   MP  ATK  DEF",
+        "6  Sariel       90  690  630  164  217",
+        "4  Yugenmagan   87  628  576  176  189",
+    ]));
+    assert_eq!(format_table(&to_strings(SMTOUHOU_DATA), &FormatOptions { tail: Some(2), ..sorted_by(3) }), to_strings(&[
+        "#  Name    Lv.   HP   MP  ATK  DEF",
+        "1  Reimu    40  193  211   63   82",
+        "2  Marisa   28  125  166   46   57",
+    ]));
+    assert_eq!(format_table(&to_strings(SMTOUHOU_DATA), &FormatOptions { tail: Some(50), ..Default::default() }), to_strings(SMTOUHOU_DATA_ORGANIZED));
+}
+
+#[test]
+fn test_sep_spec() {
+    let spaced = FormatOptions { sep_spec: vec![1, 1, 4], ..Default::default() };
+    let expected: Vec<String> = WIDE_TABLE_ORGANIZED.iter().map(|line| line.replacen("A  B  c  d", "A B c    d", 1)).collect();
+    assert_eq!(format_table(&to_strings(WIDE_TABLE), &spaced), expected);
+
+    // boundaries past the spec fall back to the separator, and every column stays lined up
+    let output = format_table(&to_strings(SMTOUHOU_DATA), &FormatOptions { sep_spec: vec![4], separator: 1, header_rule: true, ..Default::default() });
+    assert_eq!(output[..3], to_strings(&[
+        "#    Name       Lv.  HP  MP ATK DEF",
+        "-    ---------- --- --- --- --- ---",
+        "1    Reimu       40 193 211  63  82",
+    ]));
+    assert!(output.iter().all(|line| visible_len(line) == 35));
+}
+
+#[test]
+fn test_rtl() {
+    const MIXED: &[&str] = &[
+        "name  size  note",
+        "abcd  10",
+        "\u{1b}[31mb\u{1b}[0m  1.5K  ok",
+    ];
+    const MIXED_RTL: &[&str] = &[
+        "note  size  name",
+        "        10  abcd",
+        "ok    1.5K  \u{1b}[31mb\u{1b}[0m   ",
+    ];
+
+    let output = format_table(&to_strings(MIXED), &FormatOptions { rtl: true, ..Default::default() });
+    assert_eq!(output, to_strings(MIXED_RTL));
+    assert!(output.iter().all(|line| visible_len(line) == 16));
+
+    // columns are flipped after sorting by their input index
+    let sorted = format_table(&to_strings(SMTOUHOU_DATA), &FormatOptions { rtl: true, ..sorted_by(0) });
+    assert_eq!(sorted[1], "166  146  472  494   74  Mima        7");
+}
+
+#[test]
+fn test_group_by() {
+    use crate::Aggregate;
+
+    const SCORES: &[&str] = &[
+        "team  player  score",
+        "red  ann  10",
+        "blue  bob  -",
+        "red  cid  1.5",
+        "",
+        "blue  dan  4",
+        "green",
+    ];
+    const SCORES_GROUPED: &[&str] = &[
+        "team   sum(score)  avg(score)  min(score)  count",
+        "red          11.5        5.75         1.5      2",
+        "blue            4           4           4      2",
+        "green                                          1",
+    ];
+
+    let aggregates = vec![(Aggregate::Sum, 2), (Aggregate::Avg, 2), (Aggregate::Min, 2), (Aggregate::Count, 0)];
+    let grouped = FormatOptions { group_by: Some(0), aggregates, ..Default::default() };
+    assert_eq!(format_table(&to_strings(SCORES), &grouped), to_strings(SCORES_GROUPED));
+
+    // the row count by default; rows without the key column share an empty key
+    let by_player = FormatOptions { group_by: Some(1), no_header: true, ..Default::default() };
+    assert_eq!(format_table(&to_strings(&["a  x", "b  y", "c", "d  x"]), &by_player), to_strings(&["x  2", "y  1", "   1"]));
+}
+
+#[test]
+fn test_sort_sections() {
+    const MISSING_LINES_SORTED: &[&str] = &[
+        "A  B",
+        "3  X",
+        "2  X",
+        "1  X",
+        "    ",
+        "5  X",
+        "    ",
+        "8  X",
+        "8  X",
+        "7  X",
+        "7  X",
+    ];
+
+    // blank lines stay put, and the blocks between them are sorted on their own
+    assert_eq!(format_table(&to_strings(MISSING_LINES), &sorted_by(0)), to_strings(MISSING_LINES_SORTED));
+    let mut ascending = to_strings(MISSING_LINES_ORGANIZED);
+    assert_eq!(format_table(&to_strings(MISSING_LINES), &sorted_by_in(0, SortOrder::Asc)), ascending);
+    ascending[1..].reverse();
+    assert_eq!(format_table(&to_strings(MISSING_LINES), &FormatOptions { reverse: true, ..sorted_by_in(0, SortOrder::Asc) }), ascending);
+}
+
+#[test]
+fn test_reverse() {
+    let reversed = FormatOptions { reverse: true, ..Default::default() };
+    let mut expected = to_strings(MISSING_LINES_ORGANIZED);
+    expected[1..].reverse();
+    assert_eq!(format_table(&to_strings(MISSING_LINES), &reversed), expected);
+
+    // applies to the sorted rows
+    let mut expected = format_table(&to_strings(SMTOUHOU_DATA), &sorted_by(2));
+    expected[1..].reverse();
+    assert_eq!(format_table(&to_strings(SMTOUHOU_DATA), &FormatOptions { reverse: true, ..sorted_by(2) }), expected);
+    assert_eq!(expected[1], "2  Marisa       28  125  166   46   57");
+}
+
+#[test]
+fn test_sort_ignore_case() {
+    const FRUIT: &[&str] = &[
+        "name  n",
+        "Banana  1",
+        "apple  2",
+        "cherry  3",
+        "Avocado  4",
+    ];
+
+    let names = |options: &FormatOptions| format_table(&to_strings(FRUIT), options).iter().skip(1)
+        .map(|line| line.split_whitespace().next().unwrap().to_string()).collect::<Vec<_>>();
+    assert_eq!(names(&sorted_by(0)), ["Avocado", "Banana", "apple", "cherry"]);
+    assert_eq!(names(&FormatOptions { sort_ignore_case: true, ..sorted_by(0) }), ["apple", "Avocado", "Banana", "cherry"]);
+    assert_eq!(names(&FormatOptions { sort_ignore_case: true, ..sorted_by_in(0, SortOrder::Desc) }), ["cherry", "Banana", "Avocado", "apple"]);
+}
+
+#[test]
+fn test_stable_sort() {
+    const SORT_TESTER: &[&str] = &[
+        "X     X     X",
+        "2  1000    2M",
+        "3     9  3.5K",
+        "4     5    9G",
+        "5     6    3G",
+        "6     8   10T",
+        "7     9  288M",
+    ];
+    const SORT_TESTER_SORT1_STABLE: &[&str] = &[
+        "X     X     X",
+        "2  1000    2M",
+        "3     9  3.5K",
+        "7     9  288M",
+        "6     8   10T",
+        "5     6    3G",
+        "4     5    9G",
+    ];
+    const SCORES: &[&str] = &[
+        "team  score",
+        "b  10",
+        "a  5",
+        "c  10",
+        "d  5",
+    ];
+    const SCORES_SORT1_STABLE: &[&str] = &[
+        "team  score",
+        "b        10",
+        "c        10",
+        "a         5",
+        "d         5",
+    ];
+
+    let stable = |idx: usize| FormatOptions { stable: true, ..sorted_by(idx) };
+    assert_eq!(format_table(&to_strings(SORT_TESTER), &stable(1)), to_strings(SORT_TESTER_SORT1_STABLE));
+    let stable_desc = FormatOptions { sort_order: Some(SortOrder::Desc), ..stable(1) };
+    assert_eq!(format_table(&to_strings(SCORES), &stable_desc), to_strings(SCORES_SORT1_STABLE));
+    // ascending sorts were stable already
+    let stable_asc = FormatOptions { sort_order: Some(SortOrder::Asc), ..stable(1) };
+    assert_eq!(format_table(&to_strings(SCORES), &stable_asc), format_table(&to_strings(SCORES), &sorted_by_in(1, SortOrder::Asc)));
+}
+
+#[test]
+fn test_json_output() {
+    const INVENTORY: &[&str] = &[
+        "name  size  note",
+        "a  2M  x",
+        "b  3.5  \u{1b}[31m\"quoted\"\u{1b}[0m",
+        "c  -",
+        "d  10  k  extra",
+    ];
+    const INVENTORY_JSON: &[&str] = &[
+        r#"{"name":"a","size":2000000,"note":"x"}"#,
+        r#"{"name":"b","size":3.5,"note":"\"quoted\""}"#,
+        r#"{"name":"c","size":null}"#,
+        r#"{"name":"d","size":10,"note":"k","col3":"extra"}"#,
+    ];
+    const HEADERLESS: &[&str] = &[
+        "7  x",
+        "12  y",
+    ];
+    const HEADERLESS_JSON_SORT0: &[&str] = &[
+        r#"{"col0":12,"col1":"y"}"#,
+        r#"{"col0":7,"col1":"x"}"#,
+    ];
+
+    let json = FormatOptions { format: OutputFormat::Json, ..Default::default() };
+    assert_eq!(format_table(&to_strings(INVENTORY), &json), to_strings(INVENTORY_JSON));
+
+    let headerless = FormatOptions { no_header: true, sort: vec![0], ..json };
+    assert_eq!(format_table(&to_strings(HEADERLESS), &headerless), to_strings(HEADERLESS_JSON_SORT0));
+}
+
+#[test]
+fn test_currency_sort() {
+    const PRICES: &[&str] = &[
+        "item  price",
+        "tea  €4.20",
+        "car  $12,500",
+        "pen  £1",
+        "rent  ¥85,000.00",
+        "refund  -$30",
+    ];
+    const PRICES_SORTED: &[&str] = &[
+        "item         price",
+        "rent    ¥85,000.00",
+        "car        $12,500",
+        "tea          €4.20",
+        "pen             £1",
+        "refund        -$30",
+    ];
+
+    assert_eq!(format_table(&to_strings(PRICES), &sorted_by(1)), to_strings(PRICES_SORTED));
+}
+
+#[test]
+fn test_accounting_negatives_sort() {
+    const BALANCE: &[&str] = &[
+        "account  balance",
+        "fees  (200)",
+        "sales  500",
+        "loan  (1,234)",
+        "misc  0",
+    ];
+    const BALANCE_SORTED: &[&str] = &[
+        "account  balance",
+        "sales        500",
+        "misc           0",
+        "fees       (200)",
+        "loan     (1,234)",
+    ];
+
+    assert_eq!(format_table(&to_strings(BALANCE), &sorted_by(1)), to_strings(BALANCE_SORTED));
+}
+
+#[test]
+fn test_heatmap() {
+    const LATENCIES: &[&str] = &[
+        "host  ms",
+        "alpha  10",
+        "beta  -",
+        "gamma  110",
+        "delta  60",
+    ];
+    const LATENCIES_HEATMAP: &[&str] = &[
+        "host    ms",
+        "alpha   \u{1b}[38;5;46m10\u{1b}[0m",
+        "beta     -",
+        "gamma  \u{1b}[38;5;196m110\u{1b}[0m",
+        "delta   \u{1b}[38;5;226m60\u{1b}[0m",
+    ];
+
+    let heatmap = FormatOptions { heatmap: Some(1), ..Default::default() };
+    let output = format_table(&to_strings(LATENCIES), &heatmap);
+    assert_eq!(output, to_strings(LATENCIES_HEATMAP));
+    assert!(output.iter().all(|line| visible_len(line) == 10));
+
+    // only numeric columns get colored
+    assert_eq!(format_table(&to_strings(LATENCIES), &FormatOptions { heatmap: Some(0), ..Default::default() }),
+               format_table(&to_strings(LATENCIES), &FormatOptions::default()));
+}
+
+#[test]
+fn test_row_numbers() {
+    const FILES: &[&str] = &[
+        "name  size",
+        "a  10",
+        "b  -",
+        "c  1.5K",
+        "d  300",
+        "e  2",
+        "f  1",
+        "g  3",
+        "h  4",
+        "i  5",
+        "j  6",
+    ];
+    const FILES_NUMBERED: &[&str] = &[
+        " #  name  size",
+        " 1  c     1.5K",
+        " 2  d      300",
+        " 3  a       10",
+        " 4  j        6",
+        " 5  i        5",
+        " 6  h        4",
+        " 7  g        3",
+        " 8  e        2",
+        " 9  f        1",
+        "10  b        -",
+    ];
+    const FILES_NUMBERED_BEFORE_SORT: &[&str] = &[
+        " #  name  size",
+        " 3  c     1.5K",
+        " 4  d      300",
+        " 1  a       10",
+        "10  j        6",
+        " 9  i        5",
+        " 8  h        4",
+        " 7  g        3",
+        " 5  e        2",
+        " 6  f        1",
+        " 2  b        -",
+    ];
+
+    let numbered = FormatOptions { number: true, ..sorted_by(1) };
+    assert_eq!(format_table(&to_strings(FILES), &numbered), to_strings(FILES_NUMBERED));
+    assert_eq!(format_table(&to_strings(FILES), &FormatOptions { number_before_sort: true, ..numbered }), to_strings(FILES_NUMBERED_BEFORE_SORT));
+
+    // without a header, every row gets a number
+    let headless = FormatOptions { number: true, no_header: true, columns: vec![1], ..Default::default() };
+    assert_eq!(format_table(&to_strings(&FILES[1..4]), &headless), to_strings(&["1    10", "2     -", "3  1.5K"]));
+
+    // blank lines between sections aren't rows to count
+    let sections = to_strings(&["a  b", "x  1", "", "y  2", "z  3"]);
+    let numbered = FormatOptions { number: true, ..sorted_by(1) };
+    assert_eq!(format_table(&sections, &numbered), to_strings(&["#  a  b", "1  x  1", "       ", "2  z  3", "3  y  2"]));
+    assert_eq!(format_table(&sections, &FormatOptions { number_before_sort: true, ..numbered }), to_strings(&["#  a  b", "1  x  1", "       ", "3  z  3", "2  y  2"]));
+}
+
+#[test]
+fn test_fixed_cols() {
+    const LEGACY: &[&str] = &[
+        "id  name     price",
+        "1   foo bar  1.5",
+        "22  ünï cød  300",
+        "3   x",
+    ];
+    const LEGACY_SLICED: &[&str] = &[
+        "id  name     price",
+        " 1  foo bar    1.5",
+        "22  ünï cød    300",
+        " 3  x             ",
+    ];
+
+    let fixed = FormatOptions { fixed_cols: vec![(0, Some(2)), (3, Some(11)), (12, None)], ..Default::default() };
+    assert_eq!(format_table(&to_strings(LEGACY), &fixed), to_strings(LEGACY_SLICED));
+}
+
+#[test]
+fn test_tab_expansion() {
+    const PASTED: &[&str] = &[
+        "note,n",
+        "to\tdo,1",
+        "x,22",
+    ];
+
+    let comma = FormatOptions { delimiter: Some(','), ..Default::default() };
+    assert_eq!(format_table(&to_strings(PASTED), &comma), to_strings(&["note         n", "to      do   1", "x           22"]));
+    assert_eq!(format_table(&to_strings(PASTED), &FormatOptions { tab_width: 4, ..comma.clone() }), to_strings(&["note     n", "to  do   1", "x       22"]));
+    assert_eq!(format_table(&to_strings(PASTED), &FormatOptions { tab_width: 0, ..comma })[1], "to\tdo   1");
+
+    // tabs still separate cells by default
+    assert_eq!(format_table(&to_strings(&["a\tb", "cc\t\td"]), &FormatOptions::default()), to_strings(&["a   b", "cc  d"]));
+}
+
+#[test]
+fn test_decimal_comma() {
+    const AMOUNTS: &[&str] = &[
+        "item  amount",
+        "a  1.234,56",
+        "b  99,5",
+        "c  12.000",
+        "d  €2.500,00",
+        "e  -0,75",
+    ];
+    const AMOUNTS_SORTED: &[&str] = &[
+        "item     amount",
+        "c        12.000",
+        "d     €2.500,00",
+        "a      1.234,56",
+        "b          99,5",
+        "e         -0,75",
+    ];
+    const AMOUNTS_DECIMAL_ALIGNED: &[&str] = &[
+        "item     amount",
+        "a      1.234,56",
+        "b         99,5 ",
+        "c     12.000   ",
+        "d     €2.500,00",
+        "e         -0,75",
+    ];
+
+    let decimal_comma = FormatOptions { decimal_comma: true, ..Default::default() };
+    assert_eq!(format_table(&to_strings(AMOUNTS), &FormatOptions { sort: vec![1], ..decimal_comma.clone() }), to_strings(AMOUNTS_SORTED));
+    assert_eq!(format_table(&to_strings(AMOUNTS), &FormatOptions { decimal_align: true, ..decimal_comma }), to_strings(AMOUNTS_DECIMAL_ALIGNED));
+
+    // without the flag these are text
+    assert!(!is_numeric_or_neutral("1.234,56") && !is_numeric_or_neutral("99,5"));
+
+    // with --group-digits, thousands are grouped by '.' then, and ',' can't be the separator
+    const MIXED: &str = "a\tb\n1234,5\tx\n5000\ty\n";
+    let grouped = |extra: &[&str]| assert_cmd_and_print(Command::cargo_bin("table_formatter").unwrap()
+        .args([&["--decimal-comma", "--group-digits"], extra].concat()).write_stdin(MIXED));
+    assert_eq!(grouped(&[]), ["      a  b", "1.234,5  x", "  5.000  y"]);
+    assert_eq!(grouped(&["--sort", "0", "--totals"]), ["      a  b    ", "  5.000  y    ", "1.234,5  x    ", "6.234,5  TOTAL"]);
+    Command::cargo_bin("table_formatter").unwrap()
+        .args(["--decimal-comma", "--group-digits=,"]).write_stdin(MIXED)
+        .assert().failure().code(2);
+}
+
+#[test]
+fn test_buffered_output() {
+    let formatted = format_table(&to_strings(SMTOUHOU_DATA), &FormatOptions::default());
+
+    let mut out: Vec<u8> = Vec::new();
+    write_table(&formatted, &mut out).unwrap();
+
+    let expected = SMTOUHOU_DATA_ORGANIZED.iter().map(|line| format!("{line}\n")).collect::<String>();
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+    assert_eq!(run_with_piped_data(&SMTOUHOU_DATA.join("\n")), to_strings(SMTOUHOU_DATA_ORGANIZED));
+}
+
+#[test]
+fn test_crlf_input() {
+    const INVENTORY: &[&str] = &[
+        "item  count",
+        "apples  12",
+        "pears  7",
+    ];
+    const INVENTORY_ORGANIZED: &[&str] = &[
+        "item    count",
+        "apples     12",
+        "pears       7",
+    ];
+
+    let crlf: Vec<String> = INVENTORY.iter().map(|line| format!("{line}\r")).collect();
+    assert_eq!(format_table(&crlf, &FormatOptions::default()), to_strings(INVENTORY_ORGANIZED));
+    assert_eq!(run_with_piped_data(&INVENTORY.join("\r\n")), to_strings(INVENTORY_ORGANIZED));
+
+    // a '\r' inside a line is content
+    assert_eq!(format_table(&to_strings(&["to\rdo  1\r"]), &FormatOptions::default()), to_strings(&["to\rdo  1"]));
+}
+
+#[test]
+fn test_color_modes() {
+    let colored = SAMPLE_INPUT.join("\n");
+    let uncolored: Vec<String> = SAMPLE_OUTPUT.iter().map(|line| strip_ansi(line)).collect();
+    let with_color = |mode: Option<&str>| {
+        let mut command = Command::cargo_bin("table_formatter").unwrap();
+        if let Some(mode) = mode { command.args(["--color", mode]); }
+        assert_cmd_and_print(command.env_remove("NO_COLOR").write_stdin(colored.clone()))
+    };
+
+    assert_eq!(with_color(Some("always")), to_strings(SAMPLE_OUTPUT));
+    assert_eq!(with_color(Some("never")), uncolored);
+    assert_eq!(with_color(None), uncolored);  // "auto", and stdout isn't a terminal
+    let heatmap = assert_cmd_and_print(Command::cargo_bin("table_formatter").unwrap().args(["--heatmap", "1"]).env("NO_COLOR", "1").write_stdin("n  v\na  1\nb  2"));
+    assert_eq!(heatmap, to_strings(&["n  v", "a  1", "b  2"]));
+}
+
+#[test]
+fn test_unopenable_input() {
+    let missing = Command::cargo_bin("table_formatter").unwrap()
+        .arg("no/such/file.txt")
+        .assert().failure().code(1);
+    let stderr = String::from_utf8_lossy(&missing.get_output().stderr).into_owned();
+    assert!(stderr.starts_with("error: cannot open 'no/such/file.txt': "), "{stderr}");
+    assert!(!stderr.contains("panicked"));
+
+    let single_line = Command::cargo_bin("table_formatter").unwrap()
+        .arg("name  size")
+        .assert().failure().code(1);
+    assert!(String::from_utf8_lossy(&single_line.get_output().stderr).contains("needs more than one line"));
+
+    let directory = Command::cargo_bin("table_formatter").unwrap()
+        .arg("src")
+        .assert().failure().code(1);
+    assert_eq!(String::from_utf8_lossy(&directory.get_output().stderr), "error: cannot open 'src': it's a directory\n");
+}
+
+#[test]
+fn test_output_file() {
+    use tempfile::NamedTempFile;
+    use std::fs;
+
+    let out_file = NamedTempFile::new().unwrap();
+    fs::write(&out_file, "stale content, longer than the table will be\n".repeat(100)).unwrap();
+
+    let output = Command::cargo_bin("table_formatter").unwrap()
+        .arg("--output").arg(out_file.path())
+        .write_stdin(SMTOUHOU_DATA.join("\n"))
+        .assert().success();
+    assert!(output.get_output().stdout.is_empty());
+
+    let expected = SMTOUHOU_DATA_ORGANIZED.iter().map(|line| format!("{line}\n")).collect::<String>();
+    assert_eq!(fs::read_to_string(out_file.path()).unwrap(), expected);
+
+    // a file that can't be created is named in the error
+    let uncreatable = Command::cargo_bin("table_formatter").unwrap()
+        .args(["--output", "no/such/dir/table.txt"])
+        .write_stdin(SMTOUHOU_DATA.join("\n"))
+        .assert().failure().code(1);
+    let stderr = String::from_utf8_lossy(&uncreatable.get_output().stderr).into_owned();
+    assert!(stderr.starts_with("error: cannot create 'no/such/dir/table.txt': "), "{stderr}");
+}
+
+#[test]
+fn test_streaming_windows() {
+    use std::io::Cursor;
+
+    const SMTOUHOU_DATA_STREAMED_SORT6: &[&str] = &[
+        "#  Name    Lv.   HP   MP  ATK  DEF",  // window 1: the header, Reimu and Marisa
+        "1  Reimu    40  193  211   63   82",
+        "2  Marisa   28  125  166   46   57",
+        "4  Yugenmagan   87  628  576  176  189",  // window 2
+        "3  Shingyoku    89  620  505  202  182",
+        "5  Elis         78  495  448  215  145",
+        "6  Sariel   90  690  630  164  217",  // window 3
+        "7  Mima     74  494  472  146  166",
+    ];
+
+    let mut out: Vec<u8> = Vec::new();
+    stream_table(Cursor::new(SMTOUHOU_DATA.join("\n")), &sorted_by(6), 3, &mut out).unwrap();
+    let lines: Vec<String> = String::from_utf8(out).unwrap().lines().map(String::from).collect();
+    assert_eq!(lines, to_strings(SMTOUHOU_DATA_STREAMED_SORT6));
+
+    // a window spanning everything is just the regular output
+    let mut out: Vec<u8> = Vec::new();
+    stream_table(Cursor::new(SMTOUHOU_DATA.join("\n")), &FormatOptions::default(), 100, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap().lines().collect::<Vec<_>>(), SMTOUHOU_DATA_ORGANIZED);
+
+    // without a header, no line is carried along: each one is printed once, sorted within its window
+    let mut out: Vec<u8> = Vec::new();
+    let options = FormatOptions { no_header: true, ..sorted_by(0) };
+    stream_table(Cursor::new("x  y\nb  2\na  3\nc  1\nd  4\n"), &options, 2, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap().lines().collect::<Vec<_>>(), ["b  2", "x  y", "a  3", "c  1", "d  4"]);
+
+    // formats with a frame or header rule can't be split into windows
+    for format in ["box", "markdown", "csv"] {
+        Command::cargo_bin("table_formatter").unwrap()
+            .args(["--stream", "2", "--format", format])
+            .write_stdin("h1  h2\na  1\nb  2\n")
+            .assert().failure().code(2);
+    }
+}
+
+#[test]
+fn test_loose_numeric() {
+    const MONITORING: &[&str] = &[
+        "Probe  Latency  Loss",
+        "a  ~5ms  <1%",
+        "b  >100ms  3%",
+        "c  2ms  >=0.5%",
+        "d  1s  0%",
+    ];
+    const MONITORING_LOOSE: &[&str] = &[
+        "Probe  Latency    Loss",
+        "a         ~5ms     <1%",
+        "b       >100ms      3%",
+        "c          2ms  >=0.5%",
+        "d           1s      0%",
+    ];
+    const MONITORING_LOOSE_SORT1: &[&str] = &[  // durations compare in seconds
+        "Probe  Latency    Loss",
+        "d           1s      0%",
+        "b       >100ms      3%",
+        "a         ~5ms     <1%",
+        "c          2ms  >=0.5%",
+    ];
+    const MONITORING_LOOSE_SORT2: &[&str] = &[
+        "Probe  Latency    Loss",
+        "b       >100ms      3%",
+        "a         ~5ms     <1%",
+        "c          2ms  >=0.5%",
+        "d           1s      0%",
+    ];
+
+    let loose = FormatOptions { loose_numeric: true, ..Default::default() };
+    assert_eq!(format_table(&to_strings(MONITORING), &loose), to_strings(MONITORING_LOOSE));
+    assert_eq!(format_table(&to_strings(MONITORING), &FormatOptions { sort: vec![1], ..loose.clone() }), to_strings(MONITORING_LOOSE_SORT1));
+    assert_eq!(format_table(&to_strings(MONITORING), &FormatOptions { sort: vec![2], ..loose }), to_strings(MONITORING_LOOSE_SORT2));
+
+    // off by default: markers keep the columns textual
+    assert_eq!(format_table(&to_strings(MONITORING), &FormatOptions::default())[1], "a      ~5ms     <1%   ");
+}
+
+#[test]
+fn test_width_redistribution() {
+
+    const PATHS: &[&str] = &[
+        "name  path  size",
+        "foo  /usr/local/share/applications/some/very/deep/file.desktop  12K",
+        "barbaz  /etc/x  3M",
+    ];
+    const PATHS_FIT_30: &[&str] = &[
+        "name    path      size",
+        "foo     /usr/lo…   12K",
+        "barbaz  /etc/x      3M",
+    ];
+    const PATHS_FIT_30_REDISTRIBUTED: &[&str] = &[
+        "name    path              size",
+        "foo     /usr/local/shar…   12K",
+        "barbaz  /etc/x              3M",
+    ];
+
+    let fit = FormatOptions { width: Some(30), ..Default::default() };
+    assert_eq!(format_table(&to_strings(PATHS), &fit), to_strings(PATHS_FIT_30));
 
+    let redistributed = format_table(&to_strings(PATHS), &FormatOptions { two_pass_widths: true, ..fit });
+    assert_eq!(redistributed, to_strings(PATHS_FIT_30_REDISTRIBUTED));
+    assert!(redistributed.iter().all(|line| visible_len(line) == 30));
+
+    // a table that already fits is left alone
+    assert_eq!(format_table(&to_strings(SMTOUHOU_DATA), &FormatOptions { width: Some(80), two_pass_widths: true, ..Default::default() }),
+               to_strings(SMTOUHOU_DATA_ORGANIZED));
+}
+
+#[test]
+fn test_alignment_modes() {
+    use crate::Align;
+
+    const LABELS: &[&str] = &[
+        "Name  Value  Note",
+        "alpha  1  x",
+        "b  12345  \u{1b}[31mred\u{1b}[0m",
+        "ハハ  3  long note here",
+    ];
+    const LABELS_CENTER: &[&str] = &[
+        "Name   Value       Note     ",
+        "alpha    1          x       ",
+        "  b    12345       \u{1b}[31mred\u{1b}[0m      ",
+        "ハハ     3    long note here",
+    ];
+    const SMTOUHOU_DATA_LEFT: &[&str] = &[
+        "#  Name        Lv.  HP   MP   ATK  DEF",
+        "1  Reimu       40   193  211  63   82 ",
+        "2  Marisa      28   125  166  46   57 ",
+        "3  Shingyoku   89   620  505  202  182",
+        "4  Yugenmagan  87   628  576  176  189",
+        "5  Elis        78   495  448  215  145",
+        "6  Sariel      90   690  630  164  217",
+        "7  Mima        74   494  472  146  166",
+    ];
+    const SMTOUHOU_DATA_RIGHT: &[&str] = &[
+        "#        Name  Lv.   HP   MP  ATK  DEF",
+        "1       Reimu   40  193  211   63   82",
+        "2      Marisa   28  125  166   46   57",
+        "3   Shingyoku   89  620  505  202  182",
+        "4  Yugenmagan   87  628  576  176  189",
+        "5        Elis   78  495  448  215  145",
+        "6      Sariel   90  690  630  164  217",
+        "7        Mima   74  494  472  146  166",
+    ];
+
+    const WIDE: &[&str] = &[
+        "日本語x  1",
+        "ab  22",
+    ];
+    const WIDE_LEFT: &[&str] = &[
+        "日本語x  1 ",
+        "ab       22",
+    ];
+    const WIDE_RIGHT: &[&str] = &[
+        "日本語x   1",
+        "     ab  22",
+    ];
+    let aligned = |input, align| format_table(&to_strings(input), &FormatOptions { align, ..Default::default() });
+    assert_eq!(aligned(LABELS, Align::Center), to_strings(LABELS_CENTER));  // padding by visible width (ANSI, wide chars)
+    assert_eq!(aligned(SMTOUHOU_DATA, Align::Left), to_strings(SMTOUHOU_DATA_LEFT));
+    assert_eq!(aligned(WIDE, Align::Left), to_strings(WIDE_LEFT), "wide characters take two columns");
+    assert_eq!(aligned(WIDE, Align::Right), to_strings(WIDE_RIGHT));
+    assert_eq!(aligned(SMTOUHOU_DATA, Align::Right), to_strings(SMTOUHOU_DATA_RIGHT));
+    assert_eq!(aligned(SMTOUHOU_DATA, Align::Auto), to_strings(SMTOUHOU_DATA_ORGANIZED));
+}
+
+#[test]
+fn test_markdown_output() {
+
+    const NOTES: &[&str] = &[
+        "Name  Value  Note",
+        "alpha  1  x|y",
+        "b  12345  \u{1b}[31mred\u{1b}[0m",
+        "c  7",
+    ];
+    const NOTES_MARKDOWN: &[&str] = &[
+        "| Name  | Value | Note |",
+        "| ----- | ----: | ---- |",
+        "| alpha |     1 | x\\|y |",
+        "| b     | 12345 | red  |",
+        "| c     |     7 |      |",
+    ];
+
+    let markdown = FormatOptions { format: OutputFormat::Markdown, ..Default::default() };
+    assert_eq!(format_table(&to_strings(NOTES), &markdown), to_strings(NOTES_MARKDOWN));
+    assert_eq!(format_table(&to_strings(NOTES), &FormatOptions { align: crate::Align::Center, ..markdown })[1], "| :---: | :---: | :--: |");
+}
+
+#[test]
+fn test_explicit_delimiter() {
+    const CSV: &[&str] = &[
+        "name,qty,note",
+        "single space,1,",
+        "gap,,after gap",
+        " padded , 30 ,x",
+    ];
+    const CSV_ORGANIZED: &[&str] = &[
+        "name          qty  note     ",
+        "single space    1           ",
+        "gap                after gap",
+        "padded         30  x        ",
+    ];
+
+    let comma = FormatOptions { delimiter: Some(','), ..Default::default() };
+    assert_eq!(Table::from_lines(&to_strings(CSV), &comma).rows[2], ["gap", "", "after gap"]);
+    assert_eq!(format_table(&to_strings(CSV), &comma), to_strings(CSV_ORGANIZED));
+    assert_eq!(Table::from_lines(&to_strings(&["a b  c"]), &FormatOptions { delimiter: Some(' '), ..Default::default() }).rows[0], ["a", "b", "", "c"]);
+
+    // whitespace runs stay the default
+    assert_eq!(Table::from_lines(&to_strings(CSV), &FormatOptions::default()).rows[1], ["single space,1,"]);
+}
+
+#[test]
+fn test_csv_output() {
+
+    const QUOTES: &[&str] = &[
+        "Name  Quote  Qty",
+        "   b,c     plain  2",
+        "alpha  say \"hi\"  10",
+        "",
+    ];
+    const QUOTES_CSV_SORT2: &[&str] = &[
+        "Name,Quote,Qty",
+        "alpha,\"say \"\"hi\"\"\",10",
+        "\"b,c\",plain,2",
+        "",
+    ];
+
+    let csv = FormatOptions { format: OutputFormat::Csv, sort: vec![2], ..Default::default() };
+    assert_eq!(format_table(&to_strings(QUOTES), &csv), to_strings(QUOTES_CSV_SORT2));
+    assert!(Table::from_lines(&to_strings(QUOTES), &csv).widths.iter().all(|&w| w == 0), "CSV doesn't need column widths");
+}
+
+#[test]
+fn test_grapheme_width() {
+    assert_eq!(visible_len("👨\u{200D}👩\u{200D}👧"), 2);  // ZWJ family
+    assert_eq!(visible_len("👍🏽"), 2);  // skin tone modifier
+    assert_eq!(visible_len("❤\u{FE0F}"), 2);  // emoji presentation
+    assert_eq!(visible_len("🇺🇸"), 2);
+    assert_eq!(visible_len("cafe\u{301}"), 4);  // combining accent
+    assert_eq!(visible_len("n\u{303}o\u{308}"), 2);
+    assert_eq!(visible_len("\u{1b}[31mハハ\u{1b}[0m"), 4);
+
+    const FAMILIES: &[&str] = &[
+        "who  n",
+        "👨\u{200D}👩\u{200D}👧  3",
+        "cafe\u{301}  1",
+    ];
+    let centered = format_table(&to_strings(FAMILIES), &FormatOptions { align: crate::Align::Center, ..Default::default() });
+    assert_eq!(centered, to_strings(&["who   n", " 👨\u{200D}👩\u{200D}👧   3", "cafe\u{301}  1"]));
+    assert!(centered.iter().all(|line| visible_len(line) == 7));
 }
 
 #[test]
@@ -368,11 +1742,19 @@ fn test_is_numeric_or_neutral() {
     let numeric = [
         "10.0", "123", "123K", "123.45M", "2MB", "-1.23Gi", "5TiB", "1K", "1k", "2.5G",
         "10MiB", "4.5", "2.000", "5 TiB", "+12.5", "10%", "2k%", "1.3 k", "1.12 kb/s",
-        "2 MB/s", "4.4GB/s", "4K", "1080p", "60Hz", "1440p@120Hz"
+        "2 MB/s", "4.4GB/s", "4K", "1080p", "60Hz", "1440p@120Hz", "5ms", "2s",
+        "0x1F", "0xDEADBEEF", "0X7f", "-0x10", "0xffffffff81000000",
+        "1.5e9", "2.3E-4", "-6e+3", "1e5", "3.2e-3s", "1.5e3 MB",
+        "$1,234.50", "€99", "£0.5", "¥1000", "-$5", "$ 12", "$2.5M", "$1,000,000",
+        "(200)", "(1,234)", "(0.5)", "($99.50)", "( 12 )"
     ];
 
     let non_numeric = [
-        "abc", "1.2X", "1.2.3", "1 0", "2/2", "kB", "2%k", "1440p@Hz", "5950X"
+        "abc", "1.2X", "1.2.3", "1 0", "2/2", "kB", "2%k", "1440p@Hz", "5950X",
+        "0x", "0xG1", "x1F", "1F", "0x1F.5",
+        "1.5e", "e5", "1.2e3.4", "1e5X", "5ks", "2 ms/s",
+        "$", "$abc", "$12,34", "€1,2345", "$,100", "1,234", "$1.2.3", "5$",
+        "(abc)", "()", "(12", "12)", "(-5)", "(1,23)", "((5))"
     ];
 
     for val in numeric {
@@ -383,3 +1765,47 @@ fn test_is_numeric_or_neutral() {
         assert!(!is_numeric_or_neutral(val), "{} should not be numeric", val);
     }
 }
+
+#[test]
+fn test_hex_sort() {
+    const REGISTERS: &[&str] = &[
+        "reg  value",
+        "rax  0x1F",
+        "rbx  0xDEADBEEF",
+        "rcx  0x100",
+        "rsp  0xffffffff81000000",
+        "rdx  0x0A",
+    ];
+    const REGISTERS_SORTED: &[&str] = &[
+        "reg               value",
+        "rsp  0xffffffff81000000",
+        "rbx          0xDEADBEEF",
+        "rcx               0x100",
+        "rax                0x1F",
+        "rdx                0x0A",
+    ];
+
+    assert_eq!(format_table(&to_strings(REGISTERS), &sorted_by(1)), to_strings(REGISTERS_SORTED));
+}
+
+#[test]
+fn test_scientific_notation_sort() {
+    const SAMPLES: &[&str] = &[
+        "sample  rate",
+        "a  1.5e9",
+        "b  2.3E-4",
+        "c  12",
+        "d  3.2e-3 s",
+        "e  -6e+3",
+    ];
+    const SAMPLES_SORTED: &[&str] = &[
+        "sample      rate",
+        "a          1.5e9",
+        "c             12",
+        "d       3.2e-3 s",
+        "b         2.3E-4",
+        "e          -6e+3",
+    ];
+
+    assert_eq!(format_table(&to_strings(SAMPLES), &sorted_by(1)), to_strings(SAMPLES_SORTED));
+}